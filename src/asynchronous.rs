@@ -0,0 +1,106 @@
+use std::io::{BufWriter, Cursor, Read, Write};
+
+use geojson::FeatureReader;
+use pmtiles2::PMTiles;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::{ConversionError, Options};
+
+/// How many chunks can be waiting between the runtime and the thread making tiles, each way
+const CHANNEL_CHUNKS: usize = 16;
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Like `geojson_to_pmtiles_writer`, but reads the GeoJSON from an `AsyncRead` and writes the
+/// archive to an `AsyncWrite`. Tiles are still made on rayon's threads, with everything else that
+/// blocks on one of tokio's blocking threads, so this has to be called from inside a tokio runtime.
+/// Input is read while features are loaded, but nothing is written until the tiles are done.
+pub async fn geojson_to_pmtiles_async<R, W>(
+    mut geojson_input: R,
+    options: Options,
+    output: &mut W,
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin,
+{
+    let (input_tx, input_rx) = mpsc::channel(CHANNEL_CHUNKS);
+    let (output_tx, mut output_rx) = mpsc::channel(CHANNEL_CHUNKS);
+
+    // If converting fails early, the receiver is dropped and this stops at the next chunk
+    tokio::spawn(async move {
+        loop {
+            let mut chunk = vec![0; CHUNK_BYTES];
+            let result = geojson_input.read(&mut chunk).await.map(|n| {
+                chunk.truncate(n);
+                chunk
+            });
+            let done = matches!(result, Ok(ref chunk) if chunk.is_empty()) || result.is_err();
+            if input_tx.send(result).await.is_err() || done {
+                return;
+            }
+        }
+    });
+
+    let converting = tokio::task::spawn_blocking(move || {
+        let input = ChannelReader {
+            receiver: input_rx,
+            chunk: Cursor::new(Vec::new()),
+        };
+        let mut output = BufWriter::with_capacity(CHUNK_BYTES, ChannelWriter(output_tx));
+        let features = FeatureReader::from_reader(input)
+            .features()
+            .map(|f| f.map_err(anyhow::Error::from));
+        let layers = crate::input_layers(features, &options)?;
+        let archive = crate::layers_to_pmtiles_writer(layers, options, &mut output)?;
+        output.flush()?;
+        Ok::<_, ConversionError>(archive)
+    });
+
+    while let Some(chunk) = output_rx.recv().await {
+        output.write_all(&chunk).await?;
+    }
+    output.flush().await?;
+    match converting.await {
+        Ok(result) => result,
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        Err(err) => Err(ConversionError::Other(err.into())),
+    }
+}
+
+/// Reads chunks sent from the runtime, blocking the thread while waiting
+struct ChannelReader {
+    receiver: mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Cursor<Vec<u8>>,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let n = Read::read(&mut self.chunk, buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.chunk = Cursor::new(chunk?),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Sends chunks back to the runtime, blocking the thread while the output catches up
+struct ChannelWriter(mpsc::Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.blocking_send(buf.to_vec()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Writing the output stopped")
+        })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
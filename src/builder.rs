@@ -0,0 +1,298 @@
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use crate::{
+    ArchiveOptions, Attributes, CompressionPreset, ConversionError, DensityRaster, DirectionRule,
+    FeatureAction, FeatureRef, Filter, GridAggregation, LayerOptions, Options, ProgressObserver,
+    SkippedFeature, Warning,
+};
+
+/// Sets up `Options`, starting from the defaults, like
+/// `Options::builder().layer_name("roads").zooms(0..=12).build()?`. Each method sets the field of
+/// `Options` with the same name. Fields that are optional get wrapped in `Some`.
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl Options {
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder {
+            options: Options::default(),
+        }
+    }
+}
+
+impl Default for Options {
+    /// One layer called `layer1` at zooms 0 to 12, with tiles capped at 200KiB and everything else
+    /// off, like `convert` with no flags besides the input
+    fn default() -> Self {
+        Self {
+            layer_name: "layer1".to_string(),
+            layers: Vec::new(),
+            description: None,
+            sort_by_key: None,
+            zoom_levels: (0..=12).collect(),
+            guess_max_zoom: false,
+            guess_min_zoom: false,
+            limit_size_bytes: Some(200 * 1024),
+            dissolve_by_key: None,
+            grid_aggregation: None,
+            filter: None,
+            sample_rate: None,
+            seed: 0,
+            attributes: Attributes::All,
+            mask: None,
+            direction: None,
+            bbox: None,
+            density: None,
+            canonical: false,
+            archive: ArchiveOptions::default(),
+            compression_preset: CompressionPreset::default(),
+            compression_level: None,
+            extent: 4096,
+            strict: false,
+            center: None,
+            center_zoom: None,
+            progress: None,
+            checkpoint: None,
+            threads: None,
+            max_memory: None,
+            two_pass: false,
+            skip_invalid: false,
+            on_skip: None,
+            on_warning: None,
+            cancel: None,
+            transform: None,
+            process_tile: None,
+            #[cfg(feature = "serde")]
+            record_options: false,
+        }
+    }
+}
+
+impl OptionsBuilder {
+    /// Fails if the options can't work together, like an empty range of zooms
+    pub fn build(self) -> Result<Options, ConversionError> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+
+    pub fn layer_name(mut self, layer_name: impl Into<String>) -> Self {
+        self.options.layer_name = layer_name.into();
+        self
+    }
+
+    /// Adds a layer to `layers`
+    pub fn layer(mut self, layer: LayerOptions) -> Self {
+        self.options.layers.push(layer);
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.options.description = Some(description.into());
+        self
+    }
+
+    pub fn sort_by_key(mut self, key: impl Into<String>) -> Self {
+        self.options.sort_by_key = Some(key.into());
+        self
+    }
+
+    /// Sets `zoom_levels` to every zoom in the range
+    pub fn zooms(mut self, zooms: RangeInclusive<u32>) -> Self {
+        self.options.zoom_levels = zooms.collect();
+        self
+    }
+
+    pub fn zoom_levels(mut self, zoom_levels: impl IntoIterator<Item = u32>) -> Self {
+        self.options.zoom_levels = zoom_levels.into_iter().collect();
+        self
+    }
+
+    pub fn guess_max_zoom(mut self, guess: bool) -> Self {
+        self.options.guess_max_zoom = guess;
+        self
+    }
+
+    pub fn guess_min_zoom(mut self, guess: bool) -> Self {
+        self.options.guess_min_zoom = guess;
+        self
+    }
+
+    /// `None` puts every feature in every tile, no matter how big tiles get
+    pub fn limit_size_bytes(mut self, limit: Option<usize>) -> Self {
+        self.options.limit_size_bytes = limit;
+        self
+    }
+
+    pub fn dissolve_by_key(mut self, key: impl Into<String>) -> Self {
+        self.options.dissolve_by_key = Some(key.into());
+        self
+    }
+
+    pub fn grid_aggregation(mut self, grid: GridAggregation) -> Self {
+        self.options.grid_aggregation = Some(grid);
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.options.filter = Some(filter);
+        self
+    }
+
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.options.sample_rate = Some(rate);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.options.seed = seed;
+        self
+    }
+
+    pub fn attributes(mut self, attributes: Attributes) -> Self {
+        self.options.attributes = attributes;
+        self
+    }
+
+    /// Sets `attributes` to only these keys
+    pub fn include_attributes(mut self, keys: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options.attributes =
+            Attributes::Only(keys.into_iter().map(Into::into).collect::<HashSet<_>>());
+        self
+    }
+
+    pub fn mask(mut self, mask: geo_types::MultiPolygon<f64>) -> Self {
+        self.options.mask = Some(mask);
+        self
+    }
+
+    pub fn direction(mut self, rule: DirectionRule) -> Self {
+        self.options.direction = Some(rule);
+        self
+    }
+
+    pub fn bbox(mut self, bbox: geo_types::Rect<f64>) -> Self {
+        self.options.bbox = Some(bbox);
+        self
+    }
+
+    pub fn density(mut self, raster: DensityRaster) -> Self {
+        self.options.density = Some(raster);
+        self
+    }
+
+    pub fn canonical(mut self, canonical: bool) -> Self {
+        self.options.canonical = canonical;
+        self
+    }
+
+    pub fn archive(mut self, archive: ArchiveOptions) -> Self {
+        self.options.archive = archive;
+        self
+    }
+
+    pub fn compression_preset(mut self, preset: CompressionPreset) -> Self {
+        self.options.compression_preset = preset;
+        self
+    }
+
+    pub fn compression_level(mut self, level: i32) -> Self {
+        self.options.compression_level = Some(level);
+        self
+    }
+
+    pub fn extent(mut self, extent: u32) -> Self {
+        self.options.extent = extent;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    pub fn center(mut self, center: geo_types::Point<f64>) -> Self {
+        self.options.center = Some(center);
+        self
+    }
+
+    pub fn center_zoom(mut self, zoom: u8) -> Self {
+        self.options.center_zoom = Some(zoom);
+        self
+    }
+
+    /// Takes a `ProgressObserver`, or a closure taking a `Progress`
+    pub fn progress(mut self, observer: impl ProgressObserver + 'static) -> Self {
+        self.options.progress = Some(Box::new(observer));
+        self
+    }
+
+    pub fn checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.checkpoint = Some(path.into());
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.options.threads = Some(threads);
+        self
+    }
+
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.options.max_memory = Some(bytes);
+        self
+    }
+
+    pub fn two_pass(mut self, two_pass: bool) -> Self {
+        self.options.two_pass = two_pass;
+        self
+    }
+
+    pub fn skip_invalid(mut self, skip: bool) -> Self {
+        self.options.skip_invalid = skip;
+        self
+    }
+
+    pub fn on_skip(mut self, callback: impl Fn(SkippedFeature) + Send + Sync + 'static) -> Self {
+        self.options.on_skip = Some(Box::new(callback));
+        self
+    }
+
+    pub fn on_warning(mut self, callback: impl Fn(Warning) + Send + Sync + 'static) -> Self {
+        self.options.on_warning = Some(Box::new(callback));
+        self
+    }
+
+    pub fn cancel(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.options.cancel = Some(cancel);
+        self
+    }
+
+    pub fn transform(
+        mut self,
+        transform: impl Fn(&mut geo_types::Geometry<f64>, &mut geojson::JsonObject) -> FeatureAction
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.options.transform = Some(Box::new(transform));
+        self
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn record_options(mut self, record: bool) -> Self {
+        self.options.record_options = record;
+        self
+    }
+
+    pub fn process_tile(
+        mut self,
+        process_tile: impl Fn(&str, [u32; 3], &mut Vec<FeatureRef<'_>>) + Send + Sync + 'static,
+    ) -> Self {
+        self.options.process_tile = Some(Box::new(process_tile));
+        self
+    }
+}
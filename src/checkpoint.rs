@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use fs_err::{File, OpenOptions};
+
+use crate::clock::Instant;
+
+const MAGIC: &[u8; 8] = b"L2PCKPT1";
+
+/// How often finished tiles are made sure to be on disk
+const SYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Finished tiles, appended to a file as each one is made, so an interrupted run can pick up
+/// where it left off. The file starts with `MAGIC` and a fingerprint of the run, then has a record
+/// per tile: the PMTiles tile ID and length as little-endian u64 and u32, then the compressed
+/// tile. Empty tiles have a length of 0. A record cut off by the interruption is dropped.
+pub(crate) struct Checkpoint {
+    file: Mutex<(BufWriter<File>, Instant)>,
+}
+
+impl Checkpoint {
+    /// Opens the checkpoint at `path`, or starts one. Tiles in `wanted` already finished by an
+    /// earlier run with the same `fingerprint` are passed to `add_tile`, and their IDs, including
+    /// empty ones, are returned. A checkpoint from a different run is started over.
+    pub fn open(
+        path: &Path,
+        fingerprint: u32,
+        wanted: &HashSet<u64>,
+        add_tile: impl Fn(u64, Vec<u8>) -> Result<()>,
+    ) -> Result<(Self, HashSet<u64>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut done = HashSet::new();
+
+        let mut reader = BufReader::new(&mut file);
+        let mut header = [0; 12];
+        let mut valid_length = 0;
+        if reader.read_exact(&mut header).is_ok() {
+            if &header[..8] == MAGIC && header[8..] == fingerprint.to_le_bytes() {
+                valid_length = header.len() as u64;
+                while let Some((tile_id, data)) = read_record(&mut reader) {
+                    valid_length += 12 + data.len() as u64;
+                    if wanted.contains(&tile_id) && done.insert(tile_id) && !data.is_empty() {
+                        add_tile(tile_id, data)?;
+                    }
+                }
+            } else {
+                log::warn!(
+                    "{} is a checkpoint from a different run, so starting over",
+                    path.display()
+                );
+            }
+        }
+
+        // Drop anything cut off, or an old run
+        file.set_len(valid_length)?;
+        file.seek(SeekFrom::End(0))?;
+        let mut file = BufWriter::new(file);
+        if valid_length == 0 {
+            file.write_all(MAGIC)?;
+            file.write_all(&fingerprint.to_le_bytes())?;
+        }
+        Ok((
+            Self {
+                file: Mutex::new((file, Instant::now())),
+            },
+            done,
+        ))
+    }
+
+    /// Notes a finished tile, or an empty one if `data` is `None`
+    pub fn record(&self, tile_id: u64, data: Option<&[u8]>) -> Result<()> {
+        let data = data.unwrap_or_default();
+        let mut guard = self.file.lock().unwrap();
+        let (file, last_sync) = &mut *guard;
+        file.write_all(&tile_id.to_le_bytes())?;
+        file.write_all(&(data.len() as u32).to_le_bytes())?;
+        file.write_all(data)?;
+        if last_sync.elapsed() >= SYNC_INTERVAL {
+            file.flush()?;
+            file.get_ref().sync_data()?;
+            *last_sync = Instant::now();
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        let (mut file, _) = self.file.into_inner().unwrap();
+        file.flush()?;
+        file.get_ref().sync_data()?;
+        Ok(())
+    }
+}
+
+/// Returns `None` at the end, or where a record was cut off
+fn read_record(reader: &mut impl Read) -> Option<(u64, Vec<u8>)> {
+    let mut prefix = [0; 12];
+    reader.read_exact(&mut prefix).ok()?;
+    let tile_id = u64::from_le_bytes(prefix[..8].try_into().unwrap());
+    let length = u32::from_le_bytes(prefix[8..].try_into().unwrap());
+    let mut data = vec![0; length as usize];
+    reader.read_exact(&mut data).ok()?;
+    Some((tile_id, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    /// Opens the checkpoint, also returning the tiles it replayed
+    #[allow(clippy::type_complexity)]
+    fn open(
+        path: &Path,
+        fingerprint: u32,
+        wanted: &[u64],
+    ) -> (Checkpoint, HashSet<u64>, Vec<(u64, Vec<u8>)>) {
+        let added = RefCell::new(Vec::new());
+        let wanted = wanted.iter().copied().collect();
+        let (checkpoint, done) = Checkpoint::open(path, fingerprint, &wanted, |tile_id, data| {
+            added.borrow_mut().push((tile_id, data));
+            Ok(())
+        })
+        .unwrap();
+        (checkpoint, done, added.into_inner())
+    }
+
+    #[test]
+    fn test_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+
+        let (checkpoint, done, added) = open(&path, 7, &[1, 2, 3, 4]);
+        assert!(done.is_empty());
+        assert!(added.is_empty());
+        checkpoint.record(1, Some(b"one")).unwrap();
+        checkpoint.record(2, None).unwrap();
+        checkpoint.record(3, Some(b"three")).unwrap();
+        checkpoint.finish().unwrap();
+
+        // Empty tiles are done but not added, and tiles that aren't wanted anymore are neither
+        let (checkpoint, done, added) = open(&path, 7, &[1, 2, 4]);
+        assert_eq!(done, HashSet::from([1, 2]));
+        assert_eq!(added, [(1, b"one".to_vec())]);
+        checkpoint.record(4, Some(b"four")).unwrap();
+        checkpoint.finish().unwrap();
+
+        let (_, done, added) = open(&path, 7, &[1, 2, 3, 4]);
+        assert_eq!(done, HashSet::from([1, 2, 3, 4]));
+        assert_eq!(
+            added,
+            [
+                (1, b"one".to_vec()),
+                (3, b"three".to_vec()),
+                (4, b"four".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cut_off_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+
+        let (checkpoint, _, _) = open(&path, 7, &[1, 2]);
+        checkpoint.record(1, Some(b"one")).unwrap();
+        checkpoint.record(2, Some(b"two")).unwrap();
+        checkpoint.finish().unwrap();
+        let valid_length = 12 + 2 * (12 + 3);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), valid_length);
+
+        // Lose the end of the last tile
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(valid_length - 2).unwrap();
+        drop(file);
+
+        let (checkpoint, done, added) = open(&path, 7, &[1, 2]);
+        assert_eq!(done, HashSet::from([1]));
+        assert_eq!(added, [(1, b"one".to_vec())]);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 12 + 12 + 3);
+
+        // The tile can be written again after the last whole one
+        checkpoint.record(2, Some(b"two")).unwrap();
+        checkpoint.finish().unwrap();
+        let (_, done, _) = open(&path, 7, &[1, 2]);
+        assert_eq!(done, HashSet::from([1, 2]));
+    }
+
+    #[test]
+    fn test_different_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+
+        let (checkpoint, _, _) = open(&path, 7, &[1]);
+        checkpoint.record(1, Some(b"one")).unwrap();
+        checkpoint.finish().unwrap();
+
+        let (checkpoint, done, added) = open(&path, 8, &[1]);
+        assert!(done.is_empty());
+        assert!(added.is_empty());
+        checkpoint.finish().unwrap();
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&8_u32.to_le_bytes());
+        assert_eq!(std::fs::read(&path).unwrap(), header);
+    }
+}
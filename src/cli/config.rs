@@ -0,0 +1,186 @@
+//! `--config` files, holding the same settings as `convert`'s flags, plus per-layer settings
+
+use std::ffi::OsString;
+
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, FromArgMatches};
+use lines2pmtiles::{Filter, LayerOptions};
+use serde_json::Value;
+
+use super::convert::{parse_key, parse_size, parse_zoom_range, ConvertArgs};
+use super::logger::LogArgs;
+
+/// One `[[layer]]` of a config file
+#[derive(Clone)]
+pub struct ConfigLayer {
+    pub path: String,
+    pub options: LayerOptions,
+}
+
+/// Parses `convert` again with the settings from the config file at `path` filled in. Anything
+/// `matches` got from the command line or the environment wins over the file.
+///
+/// Top-level keys are the long flag names, like `max-zoom = 14` or `tile-size-limit = "500KB"`.
+/// Switches are `true` or `false`, repeatable flags like `output` take arrays, and `inputs` lists
+/// input files. Each `[[layer]]` has a `path` and optionally a `name`, `zooms` like `"5-14"`,
+/// `sort-by`, `tile-size-limit`, and a `filter` on top of the top-level one, overriding the
+/// top-level settings for that input.
+pub fn apply(path: &str, matches: &ArgMatches) -> Result<ConvertArgs> {
+    let config = load(path)?;
+    let Value::Object(settings) = config else {
+        bail!("{path} should hold a table of settings");
+    };
+
+    let command = super::with_env_vars(ConvertArgs::augment_args(clap::Command::new("convert")));
+    let mut argv: Vec<OsString> = vec!["convert".into()];
+    let mut layers = Vec::new();
+    for (key, value) in settings {
+        if key == "layer" {
+            let Value::Array(tables) = value else {
+                bail!("layer in {path} should be a list of tables, like [[layer]]");
+            };
+            for table in tables {
+                layers.push(read_layer(table).with_context(|| format!("A layer in {path}"))?);
+            }
+            continue;
+        }
+
+        let name = key.replace('_', "-");
+        let Some(arg) = command.get_arguments().find(|arg| {
+            arg.get_long() == Some(name.as_str())
+                || (arg.is_positional() && arg.get_id() == name.as_str())
+        }) else {
+            bail!("{path} has an unknown setting {key}");
+        };
+        if name == "config" {
+            bail!("{path} can't point to another config file");
+        }
+        if matches!(
+            matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::Bool(true) => {
+                    argv.push(format!("--{name}").into());
+                    continue;
+                }
+                Value::Bool(false) => continue,
+                Value::String(x) => x,
+                Value::Number(x) => x.to_string(),
+                _ => bail!("{key} in {path} should be a string, number, or true or false"),
+            };
+            if arg.is_positional() {
+                argv.push(value.into());
+            } else {
+                // Keeps values like negative longitudes from looking like flags
+                argv.push(format!("--{name}={value}").into());
+            }
+        }
+    }
+    // The command line goes after the file, skipping everything up to the subcommand
+    argv.extend(
+        std::env::args_os()
+            .skip_while(|arg| arg != "convert")
+            .skip(1),
+    );
+
+    // Logging is already set up, but the flags for it can come after the subcommand
+    let matches =
+        super::with_env_vars(LogArgs::augment_args(command)).try_get_matches_from(argv)?;
+    let mut args = ConvertArgs::from_arg_matches(&matches)?;
+    args.layers = layers;
+    Ok(args)
+}
+
+/// Reads TOML or JSON, depending on the extension
+fn load(path: &str) -> Result<Value> {
+    let contents = fs_err::read_to_string(path)?;
+    if path.to_lowercase().ends_with(".json") {
+        return Ok(serde_json::from_str(&contents)?);
+    }
+    let document: toml_edit::Document = contents.parse()?;
+    Ok(table_to_json(document.as_table()))
+}
+
+fn table_to_json<'a>(items: impl IntoIterator<Item = (&'a str, &'a toml_edit::Item)>) -> Value {
+    Value::Object(
+        items
+            .into_iter()
+            .map(|(key, item)| (key.to_string(), item_to_json(item)))
+            .collect(),
+    )
+}
+
+fn item_to_json(item: &toml_edit::Item) -> Value {
+    match item {
+        toml_edit::Item::None => Value::Null,
+        toml_edit::Item::Value(value) => value_to_json(value),
+        toml_edit::Item::Table(table) => table_to_json(table.iter()),
+        toml_edit::Item::ArrayOfTables(tables) => tables
+            .iter()
+            .map(|table| table_to_json(table.iter()))
+            .collect(),
+    }
+}
+
+fn value_to_json(value: &toml_edit::Value) -> Value {
+    match value {
+        toml_edit::Value::String(x) => x.value().clone().into(),
+        toml_edit::Value::Integer(x) => (*x.value()).into(),
+        toml_edit::Value::Float(x) => (*x.value()).into(),
+        toml_edit::Value::Boolean(x) => (*x.value()).into(),
+        toml_edit::Value::Datetime(x) => x.value().to_string().into(),
+        toml_edit::Value::Array(values) => values.iter().map(value_to_json).collect(),
+        toml_edit::Value::InlineTable(table) => Value::Object(
+            table
+                .iter()
+                .map(|(key, value)| (key.to_string(), value_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn read_layer(table: Value) -> Result<ConfigLayer> {
+    let Value::Object(table) = table else {
+        bail!("should be a table");
+    };
+    let Some(path) = table.get("path").and_then(|x| x.as_str()) else {
+        bail!("needs a path");
+    };
+    let mut options = LayerOptions::new(match table.get("name") {
+        Some(name) => parse_key(name.as_str().context("name should be a string")?)?,
+        None if super::glob::has_wildcards(path) => {
+            bail!("needs a name, since {path} has wildcards")
+        }
+        None => super::convert::layer_name_from_path(path),
+    });
+    for (key, value) in &table {
+        // Numbers are fine anywhere text is expected
+        let text = match value {
+            Value::String(x) => x.clone(),
+            Value::Number(x) => x.to_string(),
+            _ => bail!("{key} should be a string or number"),
+        };
+        match key.replace('_', "-").as_str() {
+            "path" | "name" => {}
+            "zooms" => options.zoom_levels = Some(parse_zoom_range(&text)?.collect()),
+            "sort-by" => options.sort_by_key = Some(parse_key(&text)?),
+            "tile-size-limit" => options.limit_size_bytes = Some(parse_size(&text)?),
+            "filter" => options.filter = Some(Filter::parse(&text)?),
+            _ => bail!("has an unknown setting {key}"),
+        }
+    }
+    Ok(ConfigLayer {
+        path: path.to_string(),
+        options,
+    })
+}
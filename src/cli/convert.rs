@@ -0,0 +1,1091 @@
+//! `lines2pmtiles convert`, turning inputs into tiles
+
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::config::ConfigLayer;
+use anyhow::{bail, Result};
+use clap::{Args, ValueEnum};
+use fs_err::File;
+use geo::BoundingRect;
+use lines2pmtiles::human::{HumanBytes, HumanCount, HumanDuration};
+use lines2pmtiles::input::{CsvGeometry, FeatureIter, InputOptions, TagFilter};
+use lines2pmtiles::output::{tilejson, OutputFormat};
+use lines2pmtiles::{
+    Attributes, CompressionPreset, DensityRaster, DirectionRule, Filter, GridAggregation, Progress,
+    SkippedFeature, MAX_ZOOM,
+};
+use pmtiles2::PMTiles;
+
+/// Converts inputs into vector tiles
+#[derive(Args, Clone)]
+pub struct ConvertArgs {
+    /// Input files, like .geojson, optionally naming layers like roads.geojson=roads. Each becomes
+    /// its own layer. Wildcards like data/*.geojson are expanded, with each file becoming its own
+    /// layer, unless the pattern is named, like data/*.geojson=roads, to put them all in one.
+    /// URLs, postgres:// connection strings, and - for stdin are read as they are.
+    #[arg(required_unless_present = "config")]
+    inputs: Vec<String>,
+    /// Put every input in one layer, named by --layer
+    #[arg(long)]
+    merge_inputs: bool,
+    /// Read settings from a TOML or JSON file, with the same names as these flags. Flags given
+    /// here override the file.
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<String>,
+    /// Inputs with their own settings, from `--config`
+    #[arg(skip)]
+    pub layers: Vec<ConfigLayer>,
+    /// Where to write the tiles: out.pmtiles, out.mbtiles, out/, out.tar, out.zip,
+    /// s3://bucket/out.pmtiles, or - for stdout. Repeat to write several outputs at once.
+    /// Parent directories are created as needed. [default: out.pmtiles]
+    #[arg(short = 'o', long = "output", value_name = "PATH")]
+    outputs: Vec<String>,
+    /// Overwrite outputs that already exist
+    #[arg(short = 'f', long)]
+    force: bool,
+
+    /// The name of the layer, when there's one input that isn't named
+    #[arg(short = 'l', long, default_value = "layer1")]
+    layer: String,
+    /// A description to put in the metadata
+    #[arg(short = 'N', long)]
+    description: Option<String>,
+    /// Sort features by this numeric property, descending, so the highest are kept when tiles get
+    /// too big
+    #[arg(
+        long,
+        value_name = "KEY",
+        default_value = "count",
+        value_parser = parse_key,
+        conflicts_with = "no_sort"
+    )]
+    sort_by: String,
+    /// Keep features in the order they're read, instead of sorting them
+    #[arg(long)]
+    no_sort: bool,
+    /// The lowest zoom to make tiles for, or g to start where all the features fit in one tile
+    #[arg(short = 'Z', long, default_value = "0", value_parser = parse_zoom)]
+    min_zoom: Zoom,
+    /// The highest zoom to make tiles for, or g to guess one from how closely spaced the features
+    /// are
+    #[arg(short = 'z', long, default_value = "12", value_parser = parse_zoom)]
+    max_zoom: Zoom,
+    /// Stop adding features to a tile once their geometry reaches this size, like 500KB or 1MiB.
+    /// A number on its own is in bytes.
+    #[arg(long, value_name = "SIZE", default_value = "200KiB", value_parser = parse_size, conflicts_with = "no_size_limit")]
+    tile_size_limit: usize,
+    /// Put every feature in every tile, no matter how big tiles get
+    #[arg(long)]
+    no_size_limit: bool,
+    /// Does nothing, for tippecanoe compatibility. Tiles always drop the lowest features past
+    /// --tile-size-limit.
+    #[arg(long, conflicts_with = "no_size_limit")]
+    drop_densest_as_needed: bool,
+    /// Merge all LineStrings sharing a value for this property into one MultiLineString
+    #[arg(long, value_name = "KEY", value_parser = parse_key)]
+    dissolve_by: Option<String>,
+    /// Replace raw features up to and including this zoom with a grid of polygons, each holding
+    /// the sum of the sort key, or the number of features
+    #[arg(long, value_name = "ZOOM")]
+    grid_max_zoom: Option<u32>,
+    /// The layer name for the grid
+    #[arg(long, default_value = "grid", requires = "grid_max_zoom")]
+    grid_layer: String,
+    /// How many cells to divide each side of a tile into for the grid
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u32).range(1..=4096), requires = "grid_max_zoom")]
+    grid_cells: u32,
+    /// Only keep features inside the Polygons and MultiPolygons of this GeoJSON file, clipping
+    /// LineStrings crossing the edge
+    #[arg(long, value_name = "PATH")]
+    mask: Option<String>,
+    /// Reverse LineStrings with this property value, like oneway=-1. The value is read as JSON,
+    /// or as a string if it isn't valid JSON.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_reverse_when, conflicts_with = "ascending")]
+    reverse_when: Option<DirectionRule>,
+    /// Point LineStrings so the first property is at most the second, like
+    /// from_node,to_node, swapping the values when a LineString is reversed
+    #[arg(long, value_name = "FROM_KEY,TO_KEY", value_parser = parse_ascending)]
+    ascending: Option<DirectionRule>,
+    /// Only put this property in the tiles, and any other --include ones
+    #[arg(
+        short = 'y',
+        long = "include",
+        value_name = "KEY",
+        conflicts_with = "exclude"
+    )]
+    include: Vec<String>,
+    /// Leave this property out of the tiles, and any other --exclude ones
+    #[arg(short = 'x', long = "exclude", value_name = "KEY")]
+    exclude: Vec<String>,
+    /// Leave every property out of the tiles, apart from any --include ones
+    #[arg(short = 'X', long, conflicts_with = "exclude")]
+    exclude_all: bool,
+    /// Only generate tiles covering this area, even if the features extend further. FlatGeobuf
+    /// inputs with a spatial index only read the features inside it.
+    #[arg(long, value_name = "MINLON,MINLAT,MAXLON,MAXLAT", value_parser = parse_bbox)]
+    bbox: Option<geo_types::Rect<f64>>,
+
+    /// Draw PNG heatmap tiles of line density, instead of vector tiles
+    #[arg(long)]
+    density: bool,
+    /// The width and height of each heatmap tile in pixels
+    #[arg(long, value_name = "PIXELS", default_value_t = 256, value_parser = clap::value_parser!(u32).range(1..=4096), requires = "density")]
+    density_tile_size: u32,
+    /// Pixels reaching this total are drawn at full intensity. By default, that's the weight of
+    /// the heaviest single feature.
+    #[arg(long, value_name = "VALUE", requires = "density")]
+    density_max: Option<f64>,
+    /// The color of the faintest heatmap pixels
+    #[arg(long, value_name = "RRGGBB", default_value = "ffeda0", value_parser = parse_color, requires = "density")]
+    density_low_color: [u8; 3],
+    /// The color of the most intense heatmap pixels
+    #[arg(long, value_name = "RRGGBB", default_value = "f03b20", value_parser = parse_color, requires = "density")]
+    density_high_color: [u8; 3],
+
+    /// Leave everything uncompressed and in a fixed order, for diffing outputs
+    #[arg(long)]
+    canonical: bool,
+    /// How to compress vector tiles
+    #[arg(long, value_enum, default_value_t = CompressionArg::Gzip)]
+    compression: CompressionArg,
+    /// Leave vector tiles uncompressed, like --compression none
+    #[arg(long, conflicts_with = "compression")]
+    no_tile_compression: bool,
+    /// Trades tile size for speed, for whichever compression is used
+    #[arg(long, value_enum, default_value_t = PresetArg::Default)]
+    compression_preset: PresetArg,
+    /// An exact compression level, overriding the preset
+    #[arg(long, value_name = "LEVEL")]
+    compression_level: Option<i32>,
+    /// How to compress the directories and metadata
+    #[arg(long, value_enum, default_value_t = CompressionArg::Gzip)]
+    internal_compression: CompressionArg,
+    /// Copy tile data into PMTiles in the order tiles are made, instead of order of tile ID
+    #[arg(long)]
+    no_clustering: bool,
+    /// The size of the vector tile grid
+    #[arg(long, default_value_t = 4096, value_parser = clap::value_parser!(u32).range(512..=8192))]
+    extent: u32,
+    /// Stop on the first tile breaking the MVT spec
+    #[arg(long)]
+    strict: bool,
+    /// Where viewers open the archive, instead of the busiest area
+    #[arg(long, value_name = "LON,LAT", value_parser = parse_center)]
+    center: Option<geo_types::Point<f64>>,
+    /// The zoom viewers open the archive at, instead of where everything fits
+    #[arg(long, value_name = "ZOOM")]
+    center_zoom: Option<u8>,
+
+    /// Write a TileJSON file next to the output, with this tile URL, like
+    /// https://example.com/{z}/{x}/{y}.pbf
+    #[arg(long, value_name = "URL")]
+    tilejson: Option<String>,
+    /// Split the output by zoom, like 0-9=low.pmtiles. Can be repeated.
+    #[arg(long, value_name = "ZOOMS=PATH", value_parser = parse_shard)]
+    shard: Vec<(RangeInclusive<u32>, String)>,
+    /// Update this existing PMTiles file, instead of starting from scratch
+    #[arg(long, value_name = "PATH")]
+    update: Option<String>,
+    /// With --update, only regenerate tiles around the new, changed, and removed features in
+    /// this file
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "update",
+        conflicts_with = "regenerate_zooms"
+    )]
+    changed: Option<String>,
+    /// With --update, only regenerate these zooms, like 12-13
+    #[arg(long, value_name = "ZOOMS", value_parser = parse_zoom_range, requires = "update")]
+    regenerate_zooms: Option<RangeInclusive<u32>>,
+    /// Write each tile's features to DIR/z/x/y.geojson instead
+    #[arg(long, value_name = "DIR", conflicts_with = "density")]
+    debug_geojson: Option<String>,
+    /// Leave --debug-geojson coordinates in tile pixels
+    #[arg(long, requires = "debug_geojson")]
+    debug_pixels: bool,
+    /// Write each tile to stdout as it's made, as a byte of z, big-endian u32s of x, y, and the
+    /// length, then the tile
+    #[arg(long, conflicts_with_all = ["outputs", "debug_geojson", "shard"])]
+    tile_stream: bool,
+    /// Write nothing, just count the tiles at each zoom and estimate the output size and how
+    /// long it'd take, by making a few of them
+    #[arg(long, conflicts_with_all = ["update", "tile_stream", "debug_geojson"])]
+    dry_run: bool,
+    /// How to show progress. json prints one line of JSON to stderr per update, for other
+    /// programs to read.
+    #[arg(long, value_enum, default_value_t = ProgressArg::Bars)]
+    progress: ProgressArg,
+    /// Keep running, converting again whenever an input changes. Outputs from the first run are
+    /// overwritten without --force.
+    #[arg(long, conflicts_with_all = ["update", "dry_run"])]
+    watch: bool,
+    /// Save tiles to this file as they're made, so running the same command again after an
+    /// interruption picks up where it left off. It's deleted once the outputs are written.
+    #[arg(long, value_name = "PATH", conflicts_with = "dry_run")]
+    checkpoint: Option<String>,
+    /// How many threads to use. By default, there's one per core.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+    /// Keep features on disk once they'd take up about this much memory, like 8GB, instead of
+    /// running out on huge inputs. Tiling gets much slower past the limit. Temporary files go in
+    /// $TMPDIR.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with = "dissolve_by")]
+    max_memory: Option<usize>,
+    /// Read the features once, sorting them by tile on disk, then make each tile from its own
+    /// features, instead of indexing them all in memory. This scales to inputs much bigger than
+    /// memory, with --max-memory setting how much sorting takes. Zooms can't be guessed.
+    #[arg(long)]
+    two_pass: bool,
+    /// Only keep features matching this, like 'count > 10 && highway != "footway"'. Properties
+    /// are compared with ==, !=, <, <=, >, and >=, combined with &&, ||, !, and parentheses, and
+    /// $type is the geometry type.
+    #[arg(long, value_name = "EXPRESSION", value_parser = Filter::parse)]
+    filter: Option<Filter>,
+    /// Only keep about this fraction of the features, from 0 to 1, picked at random
+    #[arg(long, value_name = "FRACTION")]
+    sample_rate: Option<f64>,
+    /// Picks which features --sample-rate keeps. The same seed always keeps the same ones.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Leave out features that can't be tiled, like ones with no geometry, instead of failing. If
+    /// any are left out, the exit code is 3.
+    #[arg(long)]
+    skip_invalid: bool,
+    /// If the run fails or --skip-invalid leaves out any features, write JSON here describing what
+    /// went wrong, with the layer, index, and reason for each feature left out
+    #[arg(long, value_name = "PATH")]
+    error_report: Option<String>,
+
+    /// Which layer to read from GeoPackage, MBTiles, PMTiles, or TopoJSON inputs with several
+    #[arg(long, value_name = "NAME")]
+    input_layer: Option<String>,
+    /// Only read OSM ways with this tag, like highway, or highway=primary,secondary. Can be
+    /// repeated to keep ways matching any of them.
+    #[arg(long, value_name = "KEY[=VALUES]")]
+    osm_tag: Vec<String>,
+    /// The column of CSV inputs holding WKT geometry, instead of wkt or geometry
+    #[arg(long, value_name = "COLUMN", conflicts_with = "csv_od")]
+    csv_wkt: Option<String>,
+    /// Four columns of CSV inputs to draw straight lines between, like
+    /// from_lon,from_lat,to_lon,to_lat
+    #[arg(long, value_name = "LON,LAT,LON,LAT", value_parser = parse_csv_od)]
+    csv_od: Option<[String; 4]>,
+    /// Which file to read from zip inputs holding several
+    #[arg(long, value_name = "NAME")]
+    archive_member: Option<String>,
+    /// Keep downloads of URL inputs in this directory, so later runs don't download them again
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+    /// The query to run for postgres:// inputs, like 'SELECT geom, name FROM roads'
+    #[arg(long, value_name = "QUERY")]
+    sql: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ProgressArg {
+    Bars,
+    Json,
+}
+
+/// How often --watch checks the inputs
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often --progress json reports tiles being made
+const JSON_PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How many tiles --dry-run makes at each zoom
+const DRY_RUN_SAMPLES: usize = 20;
+
+/// The exit code when --skip-invalid left out features, but everything else worked
+const SKIPPED_EXIT_CODE: u8 = 3;
+
+#[derive(Clone, Copy)]
+enum Zoom {
+    Level(u32),
+    Guess,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CompressionArg {
+    None,
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl From<CompressionArg> for pmtiles2::Compression {
+    fn from(value: CompressionArg) -> Self {
+        match value {
+            CompressionArg::None => pmtiles2::Compression::None,
+            CompressionArg::Gzip => pmtiles2::Compression::GZip,
+            CompressionArg::Brotli => pmtiles2::Compression::Brotli,
+            CompressionArg::Zstd => pmtiles2::Compression::ZStd,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PresetArg {
+    Fast,
+    Default,
+    Best,
+}
+
+impl From<PresetArg> for CompressionPreset {
+    fn from(value: PresetArg) -> Self {
+        match value {
+            PresetArg::Fast => CompressionPreset::Fast,
+            PresetArg::Default => CompressionPreset::Default,
+            PresetArg::Best => CompressionPreset::Best,
+        }
+    }
+}
+
+pub fn run(args: ConvertArgs) -> Result<ExitCode> {
+    if let Some(jobs) = args.jobs {
+        // Tiles are made on their own pool, but this limits reading the input too
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs.into())
+            .build_global()?;
+    }
+    if args.watch {
+        watch(args)
+    } else {
+        run_once(args)
+    }
+}
+
+/// Converts, then converts again each time the inputs change and then settle, until interrupted
+fn watch(mut args: ConvertArgs) -> Result<ExitCode> {
+    if args.inputs.iter().any(|input| input == "-") {
+        bail!("--watch can't tell when stdin changes");
+    }
+    loop {
+        let before = watched_files(&args);
+        if let Err(err) = run_once(args.clone()) {
+            log::error!("{err:#}");
+        }
+        args.force = true;
+
+        log::info!("Watching the inputs for changes");
+        let mut last = before.clone();
+        loop {
+            std::thread::sleep(WATCH_INTERVAL);
+            let now = watched_files(&args);
+            // Wait for writes to finish, so one save doesn't start several runs
+            if now != before && now == last {
+                break;
+            }
+            last = now;
+        }
+    }
+}
+
+/// Every local input file, with when it was last modified. Wildcards are expanded each time, so
+/// adding or removing matching files counts as a change.
+fn watched_files(args: &ConvertArgs) -> Vec<(String, Option<SystemTime>)> {
+    let mut patterns: Vec<&str> = args
+        .inputs
+        .iter()
+        .map(|input| split_layer_name(input).0)
+        .collect();
+    patterns.extend(args.layers.iter().map(|layer| layer.path.as_str()));
+    patterns.extend(args.mask.as_deref());
+    patterns
+        .into_iter()
+        .flat_map(|pattern| super::glob::expand(pattern).unwrap_or_default())
+        .map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|x| x.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+fn run_once(args: ConvertArgs) -> Result<ExitCode> {
+    let json_progress = args.progress == ProgressArg::Json;
+    let checkpoint = args.checkpoint.clone();
+    let error_report = args.error_report.clone();
+    let skipped = Arc::new(Mutex::new(Vec::new()));
+    let result = convert(args, skipped.clone());
+    let skipped = std::mem::take(&mut *skipped.lock().unwrap());
+    if let Some(path) = error_report {
+        if result.is_err() || !skipped.is_empty() {
+            write_error_report(&path, result.as_ref().err(), &skipped)?;
+        }
+    }
+    result?;
+    if let Some(path) = checkpoint {
+        fs_err::remove_file(path)?;
+    }
+    if json_progress {
+        eprintln!("{}", serde_json::json!({ "phase": "done" }));
+    }
+    Ok(if skipped.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(SKIPPED_EXIT_CODE)
+    })
+}
+
+/// Writes the JSON for --error-report
+fn write_error_report(
+    path: &str,
+    error: Option<&anyhow::Error>,
+    skipped: &[SkippedFeature],
+) -> Result<()> {
+    let report = serde_json::json!({
+        "status": if error.is_some() { "failed" } else { "skipped_features" },
+        "error": error.map(|err| format!("{err:#}")),
+        "skipped": skipped
+            .iter()
+            .map(|feature| {
+                serde_json::json!({
+                    "layer": feature.layer,
+                    "index": feature.index,
+                    "reason": feature.reason,
+                })
+            })
+            .collect::<Vec<_>>(),
+    });
+    fs_err::write(path, serde_json::to_vec_pretty(&report)?)?;
+    Ok(())
+}
+
+/// Reports progress to stderr as lines of JSON, leaving out most tiles so the lines don't swamp
+/// whatever's reading them
+fn report_json_progress() -> impl Fn(Progress) + Send + Sync {
+    let last_report = Mutex::new(None::<Instant>);
+    move |progress| {
+        let event = match progress {
+            Progress::Loading => serde_json::json!({ "phase": "loading" }),
+            Progress::Tiling {
+                tiles_done,
+                tiles_total,
+                bytes,
+            } => {
+                let mut last_report = last_report.lock().unwrap();
+                let due = last_report.is_none_or(|time| time.elapsed() >= JSON_PROGRESS_INTERVAL);
+                if !due && tiles_done != 0 && tiles_done != tiles_total {
+                    return;
+                }
+                *last_report = Some(Instant::now());
+                serde_json::json!({
+                    "phase": "tiling",
+                    "tiles_done": tiles_done,
+                    "tiles_total": tiles_total,
+                    "bytes_written": bytes,
+                })
+            }
+            Progress::Writing => serde_json::json!({ "phase": "writing" }),
+        };
+        eprintln!("{event}");
+    }
+}
+
+/// Features left out by --skip-invalid are added to `skipped`
+fn convert(args: ConvertArgs, skipped: Arc<Mutex<Vec<SkippedFeature>>>) -> Result<()> {
+    let explicit_output = !args.outputs.is_empty();
+    let output_paths = if explicit_output {
+        args.outputs.clone()
+    } else {
+        vec!["out.pmtiles".to_string()]
+    };
+    let (min_zoom, max_zoom) = match (args.min_zoom, args.max_zoom) {
+        (Zoom::Level(min_zoom), Zoom::Level(max_zoom)) if min_zoom > max_zoom => {
+            bail!("--min-zoom {min_zoom} is more than --max-zoom {max_zoom}");
+        }
+        (Zoom::Level(min_zoom), Zoom::Level(max_zoom)) => (min_zoom, max_zoom),
+        (Zoom::Level(min_zoom), Zoom::Guess) => (min_zoom, min_zoom),
+        (Zoom::Guess, Zoom::Level(max_zoom)) => (max_zoom, max_zoom),
+        (Zoom::Guess, Zoom::Guess) => (0, 0),
+    };
+    let guess_zooms = args.regenerate_zooms.is_none();
+    let mask = match args.mask {
+        Some(ref path) => Some(lines2pmtiles::read_mask(BufReader::new(File::open(path)?))?),
+        None => None,
+    };
+    let input_options = Arc::new(input_options(&args));
+
+    let mut options = lines2pmtiles::Options::default();
+    options.layer_name = args.layer.clone();
+    options.description = args.description.clone();
+    options.sort_by_key = (!args.no_sort).then(|| args.sort_by.clone());
+    options.zoom_levels = match args.regenerate_zooms {
+        Some(ref zooms) => zooms.clone().collect(),
+        None => (min_zoom..=max_zoom).collect(),
+    };
+    options.guess_min_zoom = guess_zooms && matches!(args.min_zoom, Zoom::Guess);
+    options.guess_max_zoom = guess_zooms && matches!(args.max_zoom, Zoom::Guess);
+    // This is so much less than 500KB, but the final tile size is still big
+    options.limit_size_bytes = (!args.no_size_limit).then_some(args.tile_size_limit);
+    options.dissolve_by_key = args.dissolve_by.clone();
+    options.grid_aggregation = args.grid_max_zoom.map(|max_zoom| GridAggregation {
+        max_zoom,
+        layer_name: args.grid_layer.clone(),
+        cells_per_tile: args.grid_cells,
+    });
+    options.mask = mask;
+    options.filter = args.filter.clone();
+    options.sample_rate = args.sample_rate;
+    options.seed = args.seed;
+    options.attributes = if args.exclude_all || !args.include.is_empty() {
+        Attributes::Only(args.include.iter().cloned().collect())
+    } else if !args.exclude.is_empty() {
+        Attributes::Except(args.exclude.iter().cloned().collect())
+    } else {
+        Attributes::All
+    };
+    options.direction = args.reverse_when.or(args.ascending);
+    options.bbox = args.bbox;
+    options.density = args.density.then_some(DensityRaster {
+        tile_size: args.density_tile_size,
+        max_value: args.density_max,
+        low_color: args.density_low_color,
+        high_color: args.density_high_color,
+    });
+    options.canonical = args.canonical;
+    options.archive = lines2pmtiles::ArchiveOptions {
+        tile_compression: if args.no_tile_compression {
+            pmtiles2::Compression::None
+        } else {
+            args.compression.into()
+        },
+        internal_compression: args.internal_compression.into(),
+        clustered: !args.no_clustering,
+        ..Default::default()
+    };
+    options.compression_preset = args.compression_preset.into();
+    options.compression_level = args.compression_level;
+    options.extent = args.extent;
+    options.strict = args.strict;
+    options.center = args.center;
+    options.center_zoom = args.center_zoom;
+    options.progress = match args.progress {
+        ProgressArg::Json => Some(Box::new(report_json_progress())),
+        // Bars are only drawn when info logs would be, so quiet runs stay quiet
+        #[cfg(feature = "progress")]
+        ProgressArg::Bars if log::log_enabled!(log::Level::Info) => {
+            Some(Box::new(lines2pmtiles::IndicatifProgress::new()))
+        }
+        ProgressArg::Bars => None,
+    };
+    options.checkpoint = args.checkpoint.clone().map(Into::into);
+    options.threads = args.jobs.map(Into::into);
+    options.max_memory = args.max_memory;
+    options.two_pass = args.two_pass;
+    options.skip_invalid = args.skip_invalid;
+    options.on_skip = Some(Box::new(move |feature| {
+        skipped.lock().unwrap().push(feature)
+    }));
+
+    if args.inputs.is_empty() && args.layers.is_empty() {
+        bail!("There are no inputs to convert");
+    }
+    let mut layers = Vec::new();
+    if args.merge_inputs {
+        let mut paths = Vec::new();
+        for arg in &args.inputs {
+            if split_layer_name(arg).1.is_some() {
+                bail!(
+                    "--merge-inputs puts everything in the --layer layer, so {arg} can't be named"
+                );
+            }
+            paths.extend(super::glob::expand(arg)?);
+        }
+        if !paths.is_empty() {
+            layers.push((
+                lines2pmtiles::LayerOptions::new(options.layer_name.clone()),
+                read_paths(paths, &input_options),
+            ));
+        }
+    } else {
+        let mut inputs = Vec::new();
+        for arg in &args.inputs {
+            match split_layer_name(arg) {
+                (pattern, Some(layer_name)) => {
+                    inputs.push((super::glob::expand(pattern)?, Some(layer_name)))
+                }
+                (_, None) => inputs.extend(
+                    super::glob::expand(arg)?
+                        .into_iter()
+                        .map(|path| (vec![path], None)),
+                ),
+            }
+        }
+        let num_layers = inputs.len() + args.layers.len();
+        for (paths, layer_name) in inputs {
+            let layer_name = match layer_name {
+                Some(layer_name) => layer_name.to_string(),
+                // One unnamed input keeps the default layer name
+                None if num_layers == 1 => options.layer_name.clone(),
+                None => layer_name_from_path(&paths[0]),
+            };
+            layers.push((
+                lines2pmtiles::LayerOptions::new(layer_name),
+                read_paths(paths, &input_options),
+            ));
+        }
+    }
+    for layer in args.layers {
+        layers.push((
+            layer.options,
+            read_paths(super::glob::expand(&layer.path)?, &input_options),
+        ));
+    }
+    if args.dry_run {
+        let estimate = lines2pmtiles::estimate(layers, options, DRY_RUN_SAMPLES)?;
+        for zoom in &estimate.zooms {
+            println!(
+                "z{}: {} tiles, {} with features, sampled {}, about {}",
+                zoom.zoom,
+                HumanCount(zoom.num_tiles as u64),
+                HumanCount(zoom.num_tiles_with_features as u64),
+                zoom.num_sampled,
+                HumanBytes(zoom.bytes)
+            );
+        }
+        println!(
+            "About {} in total, taking about {} on {} thread{}",
+            HumanBytes(estimate.bytes()),
+            HumanDuration(estimate.duration()),
+            estimate.num_threads,
+            if estimate.num_threads == 1 { "" } else { "s" }
+        );
+        return Ok(());
+    }
+
+    if let Some(existing_path) = args.update {
+        let output_path = &output_paths[0];
+        if !args.shard.is_empty()
+            || output_paths.len() > 1
+            || OutputFormat::from_path(output_path) != OutputFormat::PMTiles
+            || output_path == "-"
+            || lines2pmtiles::output::is_object_store_url(output_path)
+        {
+            bail!("--update can only write a local PMTiles file");
+        }
+        // Updating in place is the point, so that doesn't need --force
+        if *output_path != existing_path {
+            super::prepare_output(output_path, args.force)?;
+        }
+        let existing = PMTiles::from_reader(BufReader::new(File::open(&existing_path)?))?;
+        // The existing file is read while writing, so don't overwrite it until the end
+        let temp_path = format!("{output_path}.tmp");
+        let mut file = BufWriter::new(File::create(&temp_path)?);
+        match (args.changed, args.regenerate_zooms) {
+            (Some(changed), None) => {
+                let Some(changed_area) =
+                    bounding_rect(lines2pmtiles::input::read_path(&changed, &input_options)?)?
+                else {
+                    bail!("{changed} has no features, so nothing needs updating");
+                };
+                lines2pmtiles::update_pmtiles(existing, layers, options, changed_area, &mut file)?;
+            }
+            (None, Some(_)) => {
+                lines2pmtiles::regenerate_zooms(existing, layers, options, &mut file)?;
+            }
+            _ => bail!("--update needs either --changed, with the new, changed, and removed features, or --regenerate-zooms"),
+        }
+        file.flush()?;
+        fs_err::rename(temp_path, output_path)?;
+        log::info!("Wrote {output_path}");
+        return Ok(());
+    }
+
+    if args.tile_stream {
+        let mut sink =
+            lines2pmtiles::output::TileStreamSink::new(BufWriter::new(std::io::stdout()));
+        lines2pmtiles::layers_to_sink(layers, options, &mut sink)?;
+        return Ok(());
+    }
+
+    if let Some(dir) = args.debug_geojson {
+        let compression = if options.canonical {
+            pmtiles2::Compression::None
+        } else {
+            options.archive.tile_compression
+        };
+        let mut sink =
+            lines2pmtiles::output::GeoJsonDebugSink::new(&dir, args.debug_pixels, compression);
+        lines2pmtiles::layers_to_sink(layers, options, &mut sink)?;
+        log::info!("Wrote tiles as GeoJSON to {dir}");
+        return Ok(());
+    }
+
+    let tile_url = args.tilejson;
+    let shards = args.shard;
+    if !shards.is_empty() {
+        if tile_url.is_some() {
+            bail!("--tilejson can't be used with --shard, since each shard needs its own tile URL");
+        }
+        for (_, path) in &shards {
+            if OutputFormat::from_path(path) != OutputFormat::PMTiles || path == "-" {
+                bail!("Shards can only be written to PMTiles files, not {path}");
+            }
+            super::prepare_output(path, args.force)?;
+        }
+        let mut outputs = Vec::new();
+        for (zooms, path) in &shards {
+            outputs.push((zooms.clone(), BufWriter::new(File::create(path)?)));
+        }
+        lines2pmtiles::layers_to_pmtiles_shards(layers, options, &mut outputs)?;
+        for (zooms, path) in shards {
+            log::info!("Wrote {path} with zooms {zooms:?}");
+        }
+        return Ok(());
+    }
+
+    if tile_url.is_some() && output_paths.len() > 1 {
+        bail!("--tilejson needs exactly one output to write the TileJSON file next to");
+    }
+    // Tiles are only generated once, then sent to every output
+    for path in &output_paths {
+        super::prepare_output(path, args.force)?;
+    }
+    let mut sinks = Vec::new();
+    for path in &output_paths {
+        sinks.push(lines2pmtiles::output::sink_for_path(
+            path,
+            &options.archive,
+        )?);
+    }
+    let (archive, stats) = lines2pmtiles::layers_to_sink_with_stats(layers, options, &mut sinks)?;
+    for path in &output_paths {
+        log::info!("Wrote {path}");
+    }
+    for zoom in &stats.zooms {
+        log::debug!(
+            "z{}: {} tiles, {} to {}, {} in the middle",
+            zoom.zoom,
+            HumanCount(zoom.num_tiles as u64),
+            HumanBytes(zoom.min_bytes as u64),
+            HumanBytes(zoom.max_bytes as u64),
+            HumanBytes(zoom.median_bytes as u64)
+        );
+    }
+    log::info!(
+        "Made {} tiles, {} in total, from {} features. Loading took {}, tiling {}, and writing {}",
+        HumanCount(stats.num_tiles() as u64),
+        HumanBytes(stats.bytes()),
+        HumanCount(stats.features_read as u64),
+        HumanDuration(stats.loading),
+        HumanDuration(stats.tiling),
+        HumanDuration(stats.writing)
+    );
+
+    if let Some(url) = tile_url {
+        let path =
+            lines2pmtiles::output::write_tilejson(&tilejson(&archive, &url), &output_paths[0])?;
+        log::info!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// The settings for particular input formats
+fn input_options(args: &ConvertArgs) -> InputOptions {
+    InputOptions {
+        layer: args.input_layer.clone(),
+        osm_tags: args
+            .osm_tag
+            .iter()
+            .map(|tag| TagFilter::parse(tag))
+            .collect(),
+        csv_geometry: match (&args.csv_wkt, &args.csv_od) {
+            (Some(column), _) => Some(CsvGeometry::Wkt(column.clone())),
+            (None, Some([origin_lon, origin_lat, destination_lon, destination_lat])) => {
+                Some(CsvGeometry::OriginDestination {
+                    origin_lon: origin_lon.clone(),
+                    origin_lat: origin_lat.clone(),
+                    destination_lon: destination_lon.clone(),
+                    destination_lat: destination_lat.clone(),
+                })
+            }
+            (None, None) => None,
+        },
+        archive_member: args.archive_member.clone(),
+        cache_dir: args.cache_dir.clone(),
+        sql: args.sql.clone(),
+        bbox: args
+            .bbox
+            .map(|bbox| [bbox.min().x, bbox.min().y, bbox.max().x, bbox.max().y]),
+    }
+}
+
+fn bounding_rect(features: FeatureIter) -> Result<Option<geo_types::Rect<f64>>> {
+    let mut result: Option<geo_types::Rect<f64>> = None;
+    for feature in features {
+        let Some(geometry) = feature?.geometry else {
+            continue;
+        };
+        let Some(rect) = geo_types::Geometry::<f64>::try_from(geometry)?.bounding_rect() else {
+            continue;
+        };
+        result = Some(match result {
+            Some(sum) => geo_types::Rect::new(
+                (sum.min().x.min(rect.min().x), sum.min().y.min(rect.min().y)),
+                (sum.max().x.max(rect.max().x), sum.max().y.max(rect.max().y)),
+            ),
+            None => rect,
+        });
+    }
+    Ok(result)
+}
+
+/// Parses a property name, rejecting blanks that can't be what was meant
+pub fn parse_key(value: &str) -> Result<String> {
+    if value.trim().is_empty() {
+        bail!("The property name can't be blank");
+    }
+    if value.trim() != value {
+        bail!("{value:?} has spaces around it");
+    }
+    Ok(value.to_string())
+}
+
+/// Parses sizes like `500KB` (1000s of bytes), `200KiB` (1024s), `2MB`, or just bytes
+pub fn parse_size(value: &str) -> Result<usize> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse()?;
+    let scale = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "kib" => 1024.0,
+        "m" | "mb" => 1e6,
+        "mib" => 1024.0 * 1024.0,
+        "g" | "gb" => 1e9,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        _ => bail!("{value} should be a number of bytes, KB, KiB, MB, MiB, GB, or GiB"),
+    };
+    let bytes = (number * scale).round() as usize;
+    if bytes == 0 {
+        bail!("The size can't be 0");
+    }
+    Ok(bytes)
+}
+
+/// Parses `key=value`, reading the value as JSON if possible
+fn parse_reverse_when(value: &str) -> Result<DirectionRule> {
+    let Some((key, raw)) = value.split_once('=') else {
+        bail!("{value} should look like key=value");
+    };
+    let value = serde_json::from_str(raw).unwrap_or_else(|_| raw.into());
+    Ok(DirectionRule::ReverseWhen {
+        key: key.to_string(),
+        value,
+    })
+}
+
+/// Parses `from_key,to_key`
+fn parse_ascending(value: &str) -> Result<DirectionRule> {
+    let Some((from_key, to_key)) = value.split_once(',') else {
+        bail!("{value} should look like from_key,to_key");
+    };
+    Ok(DirectionRule::Ascending {
+        from_key: from_key.to_string(),
+        to_key: to_key.to_string(),
+    })
+}
+
+/// Parses `origin_lon,origin_lat,destination_lon,destination_lat`
+fn parse_csv_od(value: &str) -> Result<[String; 4]> {
+    let columns = value
+        .split(',')
+        .map(parse_key)
+        .collect::<Result<Vec<_>>>()?;
+    let Ok(columns) = <[String; 4]>::try_from(columns) else {
+        bail!("{value} should look like origin_lon,origin_lat,destination_lon,destination_lat");
+    };
+    Ok(columns)
+}
+
+/// Parses `rrggbb`, with or without a `#`
+fn parse_color(value: &str) -> Result<[u8; 3]> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 || !hex.is_ascii() {
+        bail!("{value} should look like rrggbb");
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Parses `lon,lat`
+fn parse_center(value: &str) -> Result<geo_types::Point<f64>> {
+    let numbers = value
+        .split(',')
+        .map(|x| x.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let [lon, lat] = numbers[..] else {
+        bail!("{value} should look like lon,lat");
+    };
+    if !(-180.0..=180.0).contains(&lon) || !(-90.0..=90.0).contains(&lat) {
+        bail!("{value} should be in WGS84 degrees");
+    }
+    Ok(geo_types::Point::new(lon, lat))
+}
+
+/// Parses `minlon,minlat,maxlon,maxlat`
+fn parse_bbox(value: &str) -> Result<geo_types::Rect<f64>> {
+    let numbers = value
+        .split(',')
+        .map(|x| x.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let [min_lon, min_lat, max_lon, max_lat] = numbers[..] else {
+        bail!("{value} should look like minlon,minlat,maxlon,maxlat");
+    };
+    if min_lon >= max_lon || min_lat >= max_lat {
+        bail!("{value} needs the minimums before the maximums");
+    }
+    if min_lon < -180.0 || max_lon > 180.0 || min_lat < -90.0 || max_lat > 90.0 {
+        bail!("{value} should be in WGS84 degrees");
+    }
+    Ok(geo_types::Rect::new((min_lon, min_lat), (max_lon, max_lat)))
+}
+
+/// Parses `0-9=low.pmtiles` or `12=z12.pmtiles`
+fn parse_shard(value: &str) -> Result<(RangeInclusive<u32>, String)> {
+    let Some((zooms, path)) = value.split_once('=') else {
+        bail!("{value} should look like 0-9=low.pmtiles");
+    };
+    Ok((parse_zoom_range(zooms)?, path.to_string()))
+}
+
+/// Parses `10-14`, or just `12`
+fn parse_zoom(value: &str) -> Result<Zoom> {
+    if value == "g" {
+        return Ok(Zoom::Guess);
+    }
+    let zoom = value.parse()?;
+    if zoom > MAX_ZOOM {
+        bail!("{zoom} is past the highest zoom, {MAX_ZOOM}");
+    }
+    Ok(Zoom::Level(zoom))
+}
+
+pub fn parse_zoom_range(value: &str) -> Result<RangeInclusive<u32>> {
+    let (min, max) = value.split_once('-').unwrap_or((value, value));
+    let zooms = min.parse()?..=max.parse()?;
+    if zooms.is_empty() {
+        bail!("The zoom range {value} is empty");
+    }
+    Ok(zooms)
+}
+
+/// Reads each path in turn, only opening each one once the last is used up
+fn read_paths(paths: Vec<String>, input_options: &Arc<InputOptions>) -> FeatureIter {
+    let input_options = input_options.clone();
+    Box::new(paths.into_iter().flat_map(move |path| {
+        lines2pmtiles::input::read_path(&path, &input_options)
+            .unwrap_or_else(|err| Box::new(std::iter::once(Err(err))))
+    }))
+}
+
+/// Splits `roads.geojson=roads` into the path or pattern and the layer name. URLs and `-` are
+/// never split, since `=` can be part of a query string.
+fn split_layer_name(input: &str) -> (&str, Option<&str>) {
+    if super::glob::is_url_or_stdin(input) {
+        return (input, None);
+    }
+    match input.rsplit_once('=') {
+        Some((path, layer_name)) => (path, Some(layer_name)),
+        None => (input, None),
+    }
+}
+
+/// `data/roads.geojson` becomes `roads`, and so does `https://host/roads.geojson?token=abc`
+pub fn layer_name_from_path(path: &str) -> String {
+    let path = match path.split_once('?') {
+        Some((before_query, _)) if super::glob::is_url_or_stdin(path) => before_query,
+        _ => path,
+    };
+    let file_name = std::path::Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path);
+    file_name.split('.').next().unwrap_or(file_name).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("100B").unwrap(), 100);
+        assert_eq!(parse_size("500KB").unwrap(), 500_000);
+        assert_eq!(parse_size("500k").unwrap(), 500_000);
+        assert_eq!(parse_size("200KiB").unwrap(), 200 * 1024);
+        assert_eq!(parse_size("1.5MB").unwrap(), 1_500_000);
+        assert_eq!(parse_size("2 MiB").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_size("8gb").unwrap(), 8_000_000_000);
+        assert_eq!(parse_size("1GiB").unwrap(), 1024 * 1024 * 1024);
+        assert_eq!(
+            parse_size("5TB").unwrap_err().to_string(),
+            "5TB should be a number of bytes, KB, KiB, MB, MiB, GB, or GiB"
+        );
+        assert_eq!(
+            parse_size("0").unwrap_err().to_string(),
+            "The size can't be 0"
+        );
+        assert_eq!(
+            parse_size("0.1").unwrap_err().to_string(),
+            "The size can't be 0"
+        );
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("1.2.3MB").is_err());
+    }
+
+    #[test]
+    fn test_parse_csv_od() {
+        assert_eq!(
+            parse_csv_od("from_lon,from_lat,to_lon,to_lat").unwrap(),
+            ["from_lon", "from_lat", "to_lon", "to_lat"]
+        );
+        assert!(parse_csv_od("a,b,c").is_err());
+        assert!(parse_csv_od("a,b,c,d,e").is_err());
+        assert!(parse_csv_od("a,,c,d").is_err());
+    }
+
+    #[test]
+    fn test_split_layer_name() {
+        assert_eq!(split_layer_name("roads.geojson"), ("roads.geojson", None));
+        assert_eq!(
+            split_layer_name("roads.geojson=streets"),
+            ("roads.geojson", Some("streets"))
+        );
+        assert_eq!(
+            split_layer_name("data/*.geojson=all"),
+            ("data/*.geojson", Some("all"))
+        );
+        for input in [
+            "-",
+            "https://host/roads.geojson?token=abc",
+            "postgres://user@host/db?sslmode=require",
+        ] {
+            assert_eq!(split_layer_name(input), (input, None));
+        }
+    }
+
+    #[test]
+    fn test_layer_name_from_path() {
+        assert_eq!(layer_name_from_path("data/roads.geojson"), "roads");
+        assert_eq!(layer_name_from_path("roads.geojson.gz"), "roads");
+        assert_eq!(
+            layer_name_from_path("https://host/roads.geojson?token=a.b"),
+            "roads"
+        );
+        assert_eq!(
+            layer_name_from_path("postgres://user@host/db?sslmode=require"),
+            "db"
+        );
+    }
+}
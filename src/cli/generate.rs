@@ -0,0 +1,293 @@
+//! Shell completions and a man page, worked out from the same definitions that parse the command
+//! line, for packagers to install
+
+use std::fmt::Write;
+
+use clap::{Arg, Args, ValueEnum};
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+    shell: Shell,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Shell {
+    Bash,
+    Fish,
+    Zsh,
+}
+
+/// Prints a completion script for the shell to stdout
+pub fn completions(args: CompletionsArgs, mut command: clap::Command) {
+    // Fills in global flags and --help
+    command.build();
+    let script = match args.shell {
+        Shell::Bash => bash(&command),
+        Shell::Fish => fish(&command),
+        // zsh can run bash completions well enough
+        Shell::Zsh => format!(
+            "autoload -U +X bashcompinit && bashcompinit\n{}",
+            bash(&command)
+        ),
+    };
+    print!("{script}");
+}
+
+/// Prints a man page in roff to stdout
+pub fn manpage(mut command: clap::Command) {
+    command.build();
+    let name = command.get_name().to_string();
+    let mut out = String::new();
+    writeln!(out, ".TH {} 1", name.to_uppercase()).unwrap();
+    writeln!(out, ".SH NAME").unwrap();
+    writeln!(
+        out,
+        "{name} \\- {}",
+        roff(
+            &command
+                .get_about()
+                .map(|x| x.to_string())
+                .unwrap_or_default()
+        )
+    )
+    .unwrap();
+    writeln!(out, ".SH SYNOPSIS").unwrap();
+    writeln!(out, "\\fB{name}\\fR [\\fIOPTIONS\\fR] \\fICOMMAND\\fR").unwrap();
+    write_options(&mut out, "OPTIONS", &command);
+
+    for subcommand in visible_subcommands(&command) {
+        writeln!(out, ".SH {}", subcommand.get_name().to_uppercase()).unwrap();
+        writeln!(out, "\\fB{name} {}\\fR", subcommand.get_name()).unwrap();
+        let positionals: Vec<_> = subcommand
+            .get_positionals()
+            .map(|arg| format!("\\fI{}\\fR", arg.get_id().as_str().to_uppercase()))
+            .collect();
+        writeln!(out, "[\\fIOPTIONS\\fR] {}", positionals.join(" ")).unwrap();
+        if let Some(about) = subcommand.get_about() {
+            writeln!(out, ".PP\n{}", roff(&about.to_string())).unwrap();
+        }
+        write_options(&mut out, "", subcommand);
+    }
+    print!("{out}");
+}
+
+fn write_options(out: &mut String, heading: &str, command: &clap::Command) {
+    let args: Vec<&Arg> = visible_args(command).collect();
+    if args.is_empty() {
+        return;
+    }
+    if !heading.is_empty() {
+        writeln!(out, ".SH {heading}").unwrap();
+    }
+    for arg in args {
+        let mut names = Vec::new();
+        if let Some(short) = arg.get_short() {
+            names.push(format!("\\fB\\-{short}\\fR"));
+        }
+        if let Some(long) = arg.get_long() {
+            names.push(format!("\\fB\\-\\-{}\\fR", roff(long)));
+        }
+        if names.is_empty() {
+            names.push(format!("\\fI{}\\fR", arg.get_id().as_str().to_uppercase()));
+        }
+        writeln!(out, ".TP\n{}", names.join(", ")).unwrap();
+        if let Some(help) = arg.get_help() {
+            writeln!(out, "{}", roff(&help.to_string())).unwrap();
+        }
+        // Switches all default to off
+        if !arg.get_action().takes_values() {
+            continue;
+        }
+        let defaults: Vec<_> = arg
+            .get_default_values()
+            .iter()
+            .map(|value| value.to_string_lossy())
+            .collect();
+        if !defaults.is_empty() {
+            writeln!(out, "[default: {}]", roff(&defaults.join(", "))).unwrap();
+        }
+    }
+}
+
+fn bash(command: &clap::Command) -> String {
+    let name = command.get_name();
+    let function = format!("_{}", name.replace('-', "_"));
+    let subcommands: Vec<_> = visible_subcommands(command)
+        .map(|subcommand| subcommand.get_name())
+        .collect();
+
+    let mut out = String::new();
+    writeln!(out, "{function}() {{").unwrap();
+    writeln!(
+        out,
+        "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" subcommand=\"\" word"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "    for word in \"${{COMP_WORDS[@]:1:COMP_CWORD-1}}\"; do"
+    )
+    .unwrap();
+    writeln!(out, "        case \"$word\" in").unwrap();
+    writeln!(
+        out,
+        "            {}) subcommand=\"$word\"; break ;;",
+        subcommands.join("|")
+    )
+    .unwrap();
+    writeln!(out, "        esac").unwrap();
+    writeln!(out, "    done").unwrap();
+    writeln!(out, "    local words").unwrap();
+    writeln!(out, "    case \"$subcommand\" in").unwrap();
+    for subcommand in visible_subcommands(command) {
+        writeln!(
+            out,
+            "        {}) words=\"{}\" ;;",
+            subcommand.get_name(),
+            flags(subcommand.get_arguments()).join(" ")
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "        *) words=\"{} {}\" ;;",
+        subcommands.join(" "),
+        flags(command.get_arguments()).join(" ")
+    )
+    .unwrap();
+    writeln!(out, "    esac").unwrap();
+    writeln!(
+        out,
+        "    if [[ \"$cur\" == -* || -z \"$subcommand\" ]]; then"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "        COMPREPLY=($(compgen -W \"$words\" -- \"$cur\"))"
+    )
+    .unwrap();
+    writeln!(out, "    else").unwrap();
+    writeln!(out, "        COMPREPLY=($(compgen -f -- \"$cur\"))").unwrap();
+    writeln!(out, "    fi").unwrap();
+    writeln!(out, "}}").unwrap();
+    writeln!(out, "complete -o filenames -F {function} {name}").unwrap();
+    out
+}
+
+fn fish(command: &clap::Command) -> String {
+    let name = command.get_name();
+    let subcommands: Vec<_> = visible_subcommands(command)
+        .map(|subcommand| subcommand.get_name())
+        .collect();
+
+    let mut out = String::new();
+    for subcommand in visible_subcommands(command) {
+        write!(
+            out,
+            "complete -c {name} -n '__fish_use_subcommand' -f -a {}",
+            subcommand.get_name()
+        )
+        .unwrap();
+        if let Some(about) = subcommand.get_about() {
+            write!(
+                out,
+                " -d {}",
+                fish_quote(&first_sentence(&about.to_string()))
+            )
+            .unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+    let condition = format!("__fish_seen_subcommand_from {}", subcommands.join(" "));
+    for arg in visible_args(command).filter(|arg| !arg.is_positional()) {
+        fish_arg(&mut out, name, &format!("not {condition}"), arg);
+    }
+    for subcommand in visible_subcommands(command) {
+        let condition = format!("__fish_seen_subcommand_from {}", subcommand.get_name());
+        for arg in visible_args(subcommand).filter(|arg| !arg.is_positional()) {
+            fish_arg(&mut out, name, &condition, arg);
+        }
+    }
+    out
+}
+
+fn fish_arg(out: &mut String, name: &str, condition: &str, arg: &Arg) {
+    write!(out, "complete -c {name} -n '{condition}'").unwrap();
+    if let Some(short) = arg.get_short() {
+        write!(out, " -s {short}").unwrap();
+    }
+    if let Some(long) = arg.get_long() {
+        write!(out, " -l {long}").unwrap();
+    }
+    let values: Vec<_> = arg
+        .get_possible_values()
+        .iter()
+        .map(|value| value.get_name().to_string())
+        .collect();
+    if !values.is_empty() {
+        write!(out, " -x -a {}", fish_quote(&values.join(" "))).unwrap();
+    } else if arg.get_action().takes_values() {
+        write!(out, " -r").unwrap();
+    }
+    if let Some(help) = arg.get_help() {
+        write!(
+            out,
+            " -d {}",
+            fish_quote(&first_sentence(&help.to_string()))
+        )
+        .unwrap();
+    }
+    writeln!(out).unwrap();
+}
+
+fn visible_subcommands(command: &clap::Command) -> impl Iterator<Item = &clap::Command> {
+    command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set() && subcommand.get_name() != "help")
+}
+
+fn visible_args(command: &clap::Command) -> impl Iterator<Item = &Arg> {
+    command.get_arguments().filter(|arg| !arg.is_hide_set())
+}
+
+/// Every spelling of the flags, like -o and --output
+fn flags<'a>(args: impl Iterator<Item = &'a Arg>) -> Vec<String> {
+    let mut flags = Vec::new();
+    for arg in args.filter(|arg| !arg.is_hide_set()) {
+        if let Some(short) = arg.get_short() {
+            flags.push(format!("-{short}"));
+        }
+        if let Some(long) = arg.get_long() {
+            flags.push(format!("--{long}"));
+        }
+    }
+    flags
+}
+
+fn first_sentence(help: &str) -> String {
+    help.split(". ")
+        .next()
+        .unwrap_or(help)
+        .trim_end_matches('.')
+        .to_string()
+}
+
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Escapes text so roff doesn't read it as a request
+fn roff(value: &str) -> String {
+    let escaped = value.replace('\\', "\\e").replace('-', "\\-");
+    escaped
+        .lines()
+        .map(|line| {
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{line}")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,199 @@
+//! Expanding wildcards in input paths, for shells that don't, or patterns quoted to avoid hitting
+//! the limit on the length of a command line
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+/// Finds the paths matching `pattern`, where `*` matches any run of characters in a file or
+/// directory name, and `?` matches any one character. Paths without wildcards are returned as
+/// they are, even if they don't exist, so readers can say what's wrong with them. So are URLs and
+/// `-`, where a `?` starts a query string instead.
+pub fn expand(pattern: &str) -> Result<Vec<String>> {
+    if is_url_or_stdin(pattern) || !has_wildcards(pattern) {
+        return Ok(vec![pattern.to_string()]);
+    }
+
+    let mut matches = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let part = component.as_os_str().to_string_lossy();
+        if !has_wildcards(&part) {
+            for path in &mut matches {
+                path.push(component);
+            }
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for dir in matches {
+            let read_from = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir.as_path()
+            };
+            // Directories that can't be read just don't match
+            let Ok(entries) = fs_err::read_dir(read_from) else {
+                continue;
+            };
+            for entry in entries {
+                let name = entry?.file_name();
+                let Some(name) = name.to_str() else {
+                    continue;
+                };
+                // Like shells, wildcards don't match hidden files
+                if name.starts_with('.') && !part.starts_with('.') {
+                    continue;
+                }
+                if wildcard_match(&part, name) {
+                    next.push(dir.join(name));
+                }
+            }
+        }
+        matches = next;
+    }
+    // Literal parts after a wildcard were added without looking, and a trailing / only matches
+    // directories
+    if pattern.ends_with(std::path::is_separator) {
+        matches.retain(|path| path.is_dir());
+    } else {
+        matches.retain(|path| path.exists());
+    }
+
+    if matches.is_empty() {
+        bail!("{pattern} doesn't match any files");
+    }
+    let mut paths: Vec<String> = matches
+        .into_iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Inputs that aren't local paths, like `https://host/data.geojson?token=abc`,
+/// `postgres://host/db?sslmode=require`, or `-`
+pub fn is_url_or_stdin(value: &str) -> bool {
+    value == "-" || value.contains("://")
+}
+
+pub fn has_wildcards(value: &str) -> bool {
+    value.contains(['*', '?'])
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Where to resume after the last *, if what follows it stops matching
+    let mut backtrack = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // Let the * swallow one more character
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    n = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.geojson", "roads.geojson"));
+        assert!(wildcard_match("*", "roads.geojson"));
+        assert!(wildcard_match("road?.geojson", "roads.geojson"));
+        assert!(wildcard_match("r*s*.geojson", "roads.geojson"));
+        assert!(wildcard_match("*.geojson*", "roads.geojson"));
+        assert!(!wildcard_match("*.geojson", "roads.csv"));
+        assert!(!wildcard_match("road?.geojson", "road.geojson"));
+        assert!(!wildcard_match("roads", "roads.geojson"));
+        // The * has to backtrack past the first match
+        assert!(wildcard_match("*a.txt", "aaa.txt"));
+        assert!(!wildcard_match("*a.txt", "aab.txt"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = tempfile::tempdir().unwrap();
+        for path in [
+            "a.geojson",
+            "b.geojson",
+            "c.csv",
+            ".hidden.geojson",
+            "sub.txt",
+            "sub1/x.geojson",
+            "sub2/x.geojson",
+            "sub2/y.geojson",
+        ] {
+            let path = dir.path().join(path);
+            fs_err::create_dir_all(path.parent().unwrap()).unwrap();
+            fs_err::write(path, "").unwrap();
+        }
+        let root = dir.path().to_string_lossy();
+        let expand = |pattern: &str| -> Vec<String> {
+            expand(&format!("{root}/{pattern}"))
+                .unwrap()
+                .into_iter()
+                .map(|path| path[root.len() + 1..].to_string())
+                .collect()
+        };
+
+        // Sorted, and without hidden files
+        assert_eq!(expand("*.geojson"), ["a.geojson", "b.geojson"]);
+        assert_eq!(expand(".*.geojson"), [".hidden.geojson"]);
+        assert_eq!(expand("?.*"), ["a.geojson", "b.geojson", "c.csv"]);
+        assert_eq!(
+            expand("sub*/x.geojson"),
+            ["sub1/x.geojson", "sub2/x.geojson"]
+        );
+        assert_eq!(
+            expand("sub*/*"),
+            ["sub1/x.geojson", "sub2/x.geojson", "sub2/y.geojson"]
+        );
+        // Only sub2 has y.geojson, and sub.txt isn't a directory
+        assert_eq!(expand("sub*/y.geojson"), ["sub2/y.geojson"]);
+        assert_eq!(expand("sub*/"), ["sub1", "sub2"]);
+        assert_eq!(expand("sub*"), ["sub.txt", "sub1", "sub2"]);
+        // Paths without wildcards don't have to exist
+        assert_eq!(expand("missing.geojson"), ["missing.geojson"]);
+
+        for pattern in ["*.shp", "sub*/z.geojson"] {
+            let err = super::expand(&format!("{root}/{pattern}")).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("{root}/{pattern} doesn't match any files")
+            );
+        }
+    }
+
+    #[test]
+    fn test_urls_and_stdin() {
+        for input in [
+            "-",
+            "https://host/data.geojson?token=abc",
+            "http://host/*.geojson",
+            "postgres://user@host/db?sslmode=require",
+        ] {
+            assert!(is_url_or_stdin(input));
+            assert_eq!(expand(input).unwrap(), [input]);
+        }
+        assert!(!is_url_or_stdin("data/*.geojson"));
+    }
+}
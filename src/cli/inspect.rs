@@ -0,0 +1,56 @@
+//! `lines2pmtiles inspect`, summarizing an existing archive
+
+use std::collections::BTreeMap;
+use std::io::BufReader;
+
+use anyhow::Result;
+use clap::Args;
+use fs_err::File;
+use lines2pmtiles::human::{HumanBytes, HumanCount};
+use pmtiles2::PMTiles;
+
+/// Prints the header, metadata, and number of tiles at each zoom of a PMTiles file
+#[derive(Args)]
+pub struct InspectArgs {
+    path: String,
+}
+
+pub fn run(args: InspectArgs) -> Result<()> {
+    let mut pmtiles = PMTiles::from_reader(BufReader::new(File::open(&args.path)?))?;
+    println!("Tile type: {:?}", pmtiles.tile_type);
+    println!("Tile compression: {:?}", pmtiles.tile_compression);
+    println!("Internal compression: {:?}", pmtiles.internal_compression);
+    println!("Zooms: {}..={}", pmtiles.min_zoom, pmtiles.max_zoom);
+    println!(
+        "Bounds: {},{},{},{}",
+        pmtiles.min_longitude, pmtiles.min_latitude, pmtiles.max_longitude, pmtiles.max_latitude
+    );
+    println!(
+        "Center: {},{} at zoom {}",
+        pmtiles.center_longitude, pmtiles.center_latitude, pmtiles.center_zoom
+    );
+    if let Some(ref metadata) = pmtiles.meta_data {
+        println!("Metadata: {}", serde_json::to_string_pretty(metadata)?);
+    }
+
+    // The number of tiles and their total size at each zoom
+    let mut zooms: BTreeMap<u8, (u64, u64)> = BTreeMap::new();
+    let tile_ids: Vec<u64> = pmtiles.tile_ids().into_iter().cloned().collect();
+    for tile_id in tile_ids {
+        let (z, _, _) = pmtiles2::util::zxy(tile_id)?;
+        let size = pmtiles
+            .get_tile_by_id(tile_id)?
+            .map_or(0, |data| data.len());
+        let entry = zooms.entry(z).or_default();
+        entry.0 += 1;
+        entry.1 += size as u64;
+    }
+    for (z, (count, bytes)) in zooms {
+        println!(
+            "Zoom {z}: {} tiles, {}",
+            HumanCount(count),
+            HumanBytes(bytes)
+        );
+    }
+    Ok(())
+}
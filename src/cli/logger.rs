@@ -0,0 +1,50 @@
+//! Log messages go to stderr, since stdout can be the output
+
+use clap::Args;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+#[derive(Args)]
+pub struct LogArgs {
+    /// Only print errors, with no progress bars, even with --verbose
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Print more about what's happening. Repeat for even more.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Dependencies get chatty below info
+        metadata.level() <= Level::Info || metadata.target().starts_with("lines2pmtiles")
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() == Level::Info {
+            eprintln!("{}", record.args());
+        } else {
+            eprintln!("{}: {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init(args: &LogArgs) {
+    let level = if args.quiet {
+        LevelFilter::Error
+    } else {
+        match args.verbose {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    };
+    log::set_logger(&StderrLogger).expect("The logger is only set once");
+    log::set_max_level(level);
+}
@@ -0,0 +1,40 @@
+//! `lines2pmtiles merge`, combining archives
+
+use std::io::{BufReader, BufWriter, Write};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use fs_err::File;
+use pmtiles2::PMTiles;
+
+/// Combines PMTiles files with the same tile type, like separately built regions or layers. Where
+/// several have the same tile, the last one wins.
+#[derive(Args)]
+pub struct MergeArgs {
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    #[arg(short = 'o', long, value_name = "PATH")]
+    output: String,
+    /// Overwrite the output if it already exists
+    #[arg(long)]
+    force: bool,
+}
+
+pub fn run(args: MergeArgs) -> Result<()> {
+    if args.inputs.contains(&args.output) {
+        bail!(
+            "{} is read while merging, so it can't also be the output",
+            args.output
+        );
+    }
+    super::prepare_output(&args.output, args.force)?;
+    let mut inputs = Vec::new();
+    for path in &args.inputs {
+        inputs.push(PMTiles::from_reader(BufReader::new(File::open(path)?))?);
+    }
+    let mut file = BufWriter::new(File::create(&args.output)?);
+    lines2pmtiles::merge_pmtiles(inputs, &mut file)?;
+    file.flush()?;
+    log::info!("Wrote {}", args.output);
+    Ok(())
+}
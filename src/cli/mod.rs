@@ -0,0 +1,54 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use clap::ArgAction;
+use lines2pmtiles::output::{is_object_store_url, OutputFormat};
+
+pub mod config;
+pub mod convert;
+pub mod generate;
+pub mod glob;
+pub mod inspect;
+pub mod logger;
+pub mod merge;
+pub mod serve;
+
+/// Lets every flag also be set by an environment variable, like `LINES2PMTILES_MAX_ZOOM` for
+/// `--max-zoom`. Switches take values like `true` or `false`. The command line wins over the
+/// environment.
+pub fn with_env_vars(command: clap::Command) -> clap::Command {
+    command
+        .mut_args(|arg| match arg.get_long() {
+            // Counting doesn't work with one value
+            Some(long) if !matches!(arg.get_action(), ArgAction::Count) => {
+                let name = format!("LINES2PMTILES_{}", long.replace('-', "_").to_uppercase());
+                arg.env(name)
+            }
+            _ => arg,
+        })
+        .mut_subcommands(with_env_vars)
+}
+
+/// Checks a local output won't overwrite anything unless `force` is set, and creates the
+/// directories it goes in. Directory outputs only count as existing if they have files in them.
+pub fn prepare_output(path: &str, force: bool) -> Result<()> {
+    if path == "-" || is_object_store_url(path) {
+        return Ok(());
+    }
+    let path = Path::new(path);
+    let exists = if OutputFormat::from_path(&path.to_string_lossy()) == OutputFormat::Directory {
+        path.is_dir() && fs_err::read_dir(path)?.next().is_some()
+    } else {
+        path.exists()
+    };
+    if exists && !force {
+        bail!(
+            "{} already exists. Pass --force to overwrite it.",
+            path.display()
+        );
+    }
+    if let Some(parent) = path.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! `lines2pmtiles serve`, a small tile server for previewing archives locally
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use fs_err::File;
+use pmtiles2::{Compression, PMTiles, TileType};
+
+/// Serves a PMTiles file as `/{z}/{x}/{y}` tiles and TileJSON at `/tiles.json`, for pointing
+/// MapLibre at while iterating. The file is reopened for every request, so rebuilding it shows up
+/// right away. This isn't meant for production.
+#[derive(Args)]
+pub struct ServeArgs {
+    path: String,
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+}
+
+pub fn run(args: ServeArgs) -> Result<()> {
+    let listener = TcpListener::bind((args.host.as_str(), args.port))?;
+    log::info!(
+        "Serving {} at http://{}:{}/tiles.json",
+        args.path,
+        args.host,
+        args.port
+    );
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let path = args.path.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = handle(stream, &path) {
+                log::warn!("Request failed: {err}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, archive_path: &str) -> Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        bail!("Bad request {request_line:?}");
+    };
+    if method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", &[], &[]);
+    }
+    let target = target.split('?').next().unwrap_or(target);
+    let host = stream.local_addr()?;
+
+    let mut pmtiles = PMTiles::from_reader(BufReader::new(File::open(archive_path)?))?;
+    if target == "/tiles.json" {
+        let extension = lines2pmtiles::output::tile_extension(pmtiles.tile_type);
+        let url = format!("http://{host}/{{z}}/{{x}}/{{y}}.{extension}");
+        let json = lines2pmtiles::output::tilejson(&pmtiles, &url);
+        return respond(
+            &mut stream,
+            "200 OK",
+            &[("Content-Type", "application/json")],
+            &serde_json::to_vec(&json)?,
+        );
+    }
+
+    let Some((z, x, y)) = parse_tile_path(target) else {
+        return respond(&mut stream, "404 Not Found", &[], &[]);
+    };
+    let Some(data) = pmtiles.get_tile(x, y, z)? else {
+        // MapLibre treats this as an empty tile, without logging errors
+        return respond(&mut stream, "204 No Content", &[], &[]);
+    };
+    let mut headers = vec![("Content-Type", content_type(pmtiles.tile_type))];
+    match pmtiles.tile_compression {
+        Compression::GZip => headers.push(("Content-Encoding", "gzip")),
+        Compression::Brotli => headers.push(("Content-Encoding", "br")),
+        Compression::ZStd => headers.push(("Content-Encoding", "zstd")),
+        _ => {}
+    }
+    respond(&mut stream, "200 OK", &headers, &data)
+}
+
+/// Parses `/z/x/y`, ignoring any extension
+fn parse_tile_path(target: &str) -> Option<(u8, u64, u64)> {
+    let mut parts = target.trim_start_matches('/').split('/');
+    let z = parts.next()?.parse().ok()?;
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.split('.').next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((z, x, y))
+}
+
+fn content_type(tile_type: TileType) -> &'static str {
+    match tile_type {
+        TileType::Mvt => "application/vnd.mapbox-vector-tile",
+        TileType::Png => "image/png",
+        TileType::Jpeg => "image/jpeg",
+        TileType::WebP => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<()> {
+    let mut response = Vec::new();
+    write!(response, "HTTP/1.1 {status}\r\n")?;
+    write!(response, "Access-Control-Allow-Origin: *\r\n")?;
+    write!(response, "Content-Length: {}\r\n", body.len())?;
+    write!(response, "Connection: close\r\n")?;
+    for (key, value) in headers {
+        write!(response, "{key}: {value}\r\n")?;
+    }
+    write!(response, "\r\n")?;
+    response.write_all(body)?;
+    stream.write_all(&response)?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::io::Write;
+
+use anyhow::Result;
+use flate2::write::GzEncoder;
+use pmtiles2::util::compress_all;
+use pmtiles2::Compression;
+
+use crate::error::invalid_options;
+
+/// Picks a compression level for whichever codec is used. `Options::compression_level` overrides
+/// this.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum CompressionPreset {
+    /// For quick previews while iterating locally
+    Fast,
+    /// Each codec's usual level
+    #[default]
+    Default,
+    /// The smallest tiles, for publishing, no matter how long it takes
+    Best,
+}
+
+impl CompressionPreset {
+    fn level(self, compression: Compression) -> Option<i32> {
+        match (compression, self) {
+            (Compression::GZip, CompressionPreset::Fast) => Some(1),
+            (Compression::GZip, CompressionPreset::Default) => Some(6),
+            (Compression::GZip, CompressionPreset::Best) => Some(9),
+            (Compression::Brotli, CompressionPreset::Fast) => Some(1),
+            (Compression::Brotli, CompressionPreset::Default | CompressionPreset::Best) => Some(11),
+            (Compression::ZStd, CompressionPreset::Fast) => Some(1),
+            (Compression::ZStd, CompressionPreset::Default) => Some(3),
+            (Compression::ZStd, CompressionPreset::Best) => Some(19),
+            _ => None,
+        }
+    }
+}
+
+/// How to compress tiles, settled before any are made
+#[derive(Clone, Copy)]
+pub struct TileCompressor {
+    pub compression: Compression,
+    level: Option<i32>,
+}
+
+impl TileCompressor {
+    /// Checks an explicit `level` makes sense for `compression`
+    pub fn new(
+        compression: Compression,
+        preset: CompressionPreset,
+        level: Option<i32>,
+    ) -> Result<Self> {
+        if let Some(level) = level {
+            let range = match compression {
+                Compression::GZip => 0..=9,
+                Compression::Brotli => 0..=11,
+                Compression::ZStd => zstd::compression_level_range(),
+                _ => {
+                    return Err(invalid_options(format!(
+                        "A compression level can't be set for {compression:?}"
+                    )))
+                }
+            };
+            if !range.contains(&level) {
+                return Err(invalid_options(format!(
+                    "The {compression:?} compression level {level} should be in {range:?}"
+                )));
+            }
+        }
+        Ok(Self {
+            compression,
+            level: level.or_else(|| preset.level(compression)),
+        })
+    }
+
+    pub fn none() -> Self {
+        Self {
+            compression: Compression::None,
+            level: None,
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match (self.compression, self.level) {
+            (Compression::GZip, Some(level)) => {
+                let mut encoder =
+                    GzEncoder::new(Vec::new(), flate2::Compression::new(level as u32));
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            (Compression::Brotli, Some(level)) => {
+                // The same buffer size and window as pmtiles2 uses
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, level as u32, 24);
+                encoder.write_all(data)?;
+                Ok(encoder.into_inner())
+            }
+            (Compression::ZStd, Some(level)) => Ok(zstd::bulk::compress(data, level)?),
+            _ => Ok(compress_all(self.compression, data)?),
+        }
+    }
+}
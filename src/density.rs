@@ -0,0 +1,221 @@
+use std::io::Write;
+
+use crate::spill::FeatureRef;
+use crate::{Layer, TreeFeature};
+use anyhow::Result;
+use flate2::write::ZlibEncoder;
+use geo::LinesIter;
+use geo_types::{Coord, Geometry, Line};
+use mvt::{MapGrid, TileId};
+use pointy::Transform;
+
+/// Draws PNG heatmap tiles of line density instead of vector tiles. Each pixel adds up the sort
+/// key of every feature crossing it, counting each feature once, or the number of features if
+/// there's no sort key.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DensityRaster {
+    /// The width and height of each tile in pixels
+    pub tile_size: u32,
+    /// Pixels reaching this total are drawn at full intensity. By default, that's the weight of
+    /// the heaviest single feature.
+    pub max_value: Option<f64>,
+    /// RGB colors for the faintest and most intense pixels. Empty pixels stay transparent.
+    pub low_color: [u8; 3],
+    pub high_color: [u8; 3],
+}
+
+impl Default for DensityRaster {
+    fn default() -> Self {
+        Self {
+            tile_size: 256,
+            max_value: None,
+            low_color: [255, 237, 160],
+            high_color: [240, 59, 32],
+        }
+    }
+}
+
+impl DensityRaster {
+    /// `max_value`, or the largest weight in any layer if it's unset
+    pub(crate) fn scale(&self, layers: &[Layer]) -> Result<f64> {
+        if let Some(max_value) = self.max_value {
+            return Ok(max_value);
+        }
+        let mut max_value: f64 = 0.0;
+        for layer in layers {
+            layer.features.for_each_feature(|feature| {
+                max_value = max_value.max(weight(feature, layer));
+            })?;
+        }
+        Ok(max_value)
+    }
+}
+
+/// Returns the encoded PNG, or nothing if no pixels are touched
+pub fn make_density_tile(
+    current_tile_id: TileId,
+    layers: Vec<(&Layer, Vec<FeatureRef<'_>>)>,
+    raster: &DensityRaster,
+    scale: f64,
+) -> Result<Option<Vec<u8>>> {
+    let transform = MapGrid::default().tile_transform(current_tile_id);
+    let size = raster.tile_size as usize;
+    let mut totals = vec![0.0; size * size];
+    // The last feature to touch each pixel, so each feature counts once per pixel
+    let mut touched_by = vec![usize::MAX; size * size];
+    let mut any = false;
+
+    let mut pixels = Pixels {
+        transform,
+        size,
+        touched: Vec::new(),
+    };
+    for (idx, (layer, feature)) in layers
+        .iter()
+        .flat_map(|(layer, features)| features.iter().map(move |f| (*layer, f)))
+        .enumerate()
+    {
+        pixels.touched.clear();
+        match feature.geometry {
+            Geometry::Point(pt) => pixels.mark_point(pt.0),
+            Geometry::MultiPoint(ref multi_point) => {
+                for pt in multi_point {
+                    pixels.mark_point(pt.0);
+                }
+            }
+            Geometry::LineString(ref line_string) => pixels.mark_lines(line_string.lines_iter()),
+            Geometry::MultiLineString(ref multi_line_string) => {
+                pixels.mark_lines(multi_line_string.lines_iter())
+            }
+            // Only the outline of polygons counts
+            Geometry::Polygon(ref polygon) => pixels.mark_lines(polygon.lines_iter()),
+            Geometry::MultiPolygon(ref multi_polygon) => {
+                pixels.mark_lines(multi_polygon.lines_iter())
+            }
+            _ => continue,
+        }
+        let value = weight(feature, layer);
+        for pixel in &pixels.touched {
+            if touched_by[*pixel] != idx {
+                touched_by[*pixel] = idx;
+                totals[*pixel] += value;
+                any = true;
+            }
+        }
+    }
+
+    if !any {
+        return Ok(None);
+    }
+
+    let mut rgba = Vec::with_capacity(size * size * 4);
+    for total in totals {
+        if total <= 0.0 {
+            rgba.extend([0, 0, 0, 0]);
+            continue;
+        }
+        // Square root, so faint lines still show up next to busy ones
+        let t = if scale > 0.0 {
+            (total / scale).min(1.0).sqrt()
+        } else {
+            1.0
+        };
+        for (low, high) in raster.low_color.iter().zip(raster.high_color.iter()) {
+            rgba.push((*low as f64 + t * (*high as f64 - *low as f64)).round() as u8);
+        }
+        rgba.push((64.0 + t * 191.0).round() as u8);
+    }
+    Ok(Some(encode_png(raster.tile_size, raster.tile_size, &rgba)?))
+}
+
+fn weight(feature: &TreeFeature, layer: &Layer) -> f64 {
+    match layer.sort_by_key {
+        Some(ref key) => feature.get_sort_key(key).unwrap_or(0) as f64,
+        None => 1.0,
+    }
+}
+
+struct Pixels {
+    transform: Transform<f64>,
+    size: usize,
+    /// Indices into the tile's pixels, maybe repeated
+    touched: Vec<usize>,
+}
+
+impl Pixels {
+    fn mark_point(&mut self, pt: Coord) {
+        let pt = self.transform * (pt.x, pt.y);
+        self.mark_tile_pt(pt.x, pt.y);
+    }
+
+    fn mark_lines(&mut self, lines: impl Iterator<Item = Line>) {
+        for line in lines {
+            self.mark_line(line.start, line.end);
+        }
+    }
+
+    fn mark_line(&mut self, start: Coord, end: Coord) {
+        // Walk along the segment in half-pixel steps, so no pixels along it are skipped
+        let start = self.transform * (start.x, start.y);
+        let end = self.transform * (end.x, end.y);
+        // Segments entirely off to one side of the tile don't need to be walked
+        if (start.x < 0.0 && end.x < 0.0)
+            || (start.x >= 1.0 && end.x >= 1.0)
+            || (start.y < 0.0 && end.y < 0.0)
+            || (start.y >= 1.0 && end.y >= 1.0)
+        {
+            return;
+        }
+        let pixels_crossed =
+            ((end.x - start.x).abs().max((end.y - start.y).abs()) * self.size as f64).ceil();
+        let steps = (2.0 * pixels_crossed).max(1.0) as usize;
+        for i in 0..=steps {
+            let t = i as f64 / steps as f64;
+            self.mark_tile_pt(
+                start.x + t * (end.x - start.x),
+                start.y + t * (end.y - start.y),
+            );
+        }
+    }
+
+    /// Takes 0-1 tile coordinates
+    fn mark_tile_pt(&mut self, x: f64, y: f64) {
+        if !(0.0..1.0).contains(&x) || !(0.0..1.0).contains(&y) {
+            return;
+        }
+        let (px, py) = (
+            (x * self.size as f64) as usize,
+            (y * self.size as f64) as usize,
+        );
+        self.touched.push(py * self.size + px);
+    }
+}
+
+/// Encodes 8-bit RGBA pixels, row by row from the top, as a PNG
+fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>> {
+    // Each row starts with a filter type byte. 0 means no filtering.
+    let mut zlib = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    for row in rgba.chunks(width as usize * 4) {
+        zlib.write_all(&[0])?;
+        zlib.write_all(row)?;
+    }
+    let idat = zlib.finish()?;
+
+    let mut ihdr = Vec::new();
+    ihdr.extend(width.to_be_bytes());
+    ihdr.extend(height.to_be_bytes());
+    // 8 bits per channel, RGBA, then the default compression, filtering, and no interlacing
+    ihdr.extend([8, 6, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for (chunk_type, data) in [(b"IHDR", &ihdr), (b"IDAT", &idat), (b"IEND", &Vec::new())] {
+        png.extend((data.len() as u32).to_be_bytes());
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(chunk_type);
+        crc.update(data);
+        png.extend(chunk_type);
+        png.extend(data);
+        png.extend(crc.finalize().to_be_bytes());
+    }
+    Ok(png)
+}
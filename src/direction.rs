@@ -0,0 +1,75 @@
+use std::cmp::Ordering;
+
+use geo_types::Geometry;
+use serde_json::Value;
+
+use crate::TreeFeature;
+
+/// How to decide which way LineStrings should point, so direction-dependent styling (like arrows)
+/// is consistent
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DirectionRule {
+    /// Reverse LineStrings whose `key` property equals `value`, like `oneway = -1`
+    ReverseWhen { key: String, value: Value },
+    /// Orient LineStrings so the `from_key` property is less than or equal to the `to_key`
+    /// property, like always going from the lower node ID to the higher. When a LineString is
+    /// reversed, the two property values are swapped too.
+    Ascending { from_key: String, to_key: String },
+}
+
+impl DirectionRule {
+    pub(crate) fn apply(&self, feature: &mut TreeFeature) {
+        if !matches!(
+            feature.geometry,
+            Geometry::LineString(_) | Geometry::MultiLineString(_)
+        ) {
+            return;
+        }
+        let Some(ref mut props) = feature.properties else {
+            return;
+        };
+
+        match self {
+            DirectionRule::ReverseWhen { key, value } => {
+                if props.get(key) == Some(value) {
+                    reverse(&mut feature.geometry);
+                }
+            }
+            DirectionRule::Ascending { from_key, to_key } => {
+                let (Some(from), Some(to)) = (props.get(from_key), props.get(to_key)) else {
+                    return;
+                };
+                if compare(from, to) == Some(Ordering::Greater) {
+                    let from = props.remove(from_key).unwrap();
+                    let to = props.insert(to_key.clone(), from).unwrap();
+                    props.insert(from_key.clone(), to);
+                    reverse(&mut feature.geometry);
+                }
+            }
+        }
+    }
+}
+
+/// Numbers are compared numerically and strings lexicographically; anything else can't be ordered
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn reverse(geometry: &mut Geometry<f64>) {
+    match geometry {
+        Geometry::LineString(line_string) => line_string.0.reverse(),
+        Geometry::MultiLineString(multi_line_string) => {
+            multi_line_string.0.reverse();
+            for line_string in &mut multi_line_string.0 {
+                line_string.0.reverse();
+            }
+        }
+        _ => {}
+    }
+}
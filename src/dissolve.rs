@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use geo_types::{Geometry, MultiLineString};
+use serde_json::Value;
+
+use crate::TreeFeature;
+
+/// Merges all LineStrings (and MultiLineStrings) sharing the same value for `key` into one
+/// MultiLineString. The merged feature only keeps `key` and, if `sort_by_key` is set, the sum of
+/// that property over all the merged features, so the size limit drops whole groups. Features
+/// without the key or with other geometry types are passed through unchanged.
+pub fn dissolve(
+    features: Vec<TreeFeature>,
+    key: &str,
+    sort_by_key: Option<&str>,
+) -> Vec<TreeFeature> {
+    let mut output = Vec::new();
+    // Keep groups in the order they're first seen, so output is deterministic
+    let mut group_index: HashMap<String, usize> = HashMap::new();
+    let mut groups: Vec<(Value, Vec<geo_types::LineString>, f64)> = Vec::new();
+
+    for f in features {
+        let Some(value) = f
+            .properties
+            .as_ref()
+            .and_then(|props| props.get(key))
+            .filter(|value| !value.is_null())
+            .cloned()
+        else {
+            output.push(f);
+            continue;
+        };
+        let sort_value = sort_by_key
+            .and_then(|sort_key| f.properties.as_ref()?.get(sort_key)?.as_f64())
+            .unwrap_or(0.0);
+
+        let lines = match f.geometry {
+            Geometry::LineString(line_string) => vec![line_string],
+            Geometry::MultiLineString(multi_line_string) => multi_line_string.0,
+            _ => {
+                output.push(f);
+                continue;
+            }
+        };
+
+        // serde_json::Value can't be hashed, so group by its serialized form
+        let idx = *group_index.entry(value.to_string()).or_insert_with(|| {
+            groups.push((value, Vec::new(), 0.0));
+            groups.len() - 1
+        });
+        groups[idx].1.extend(lines);
+        groups[idx].2 += sort_value;
+    }
+
+    for (value, lines, sort_total) in groups {
+        let mut properties = geojson::JsonObject::new();
+        properties.insert(key.to_string(), value);
+        if let Some(sort_key) = sort_by_key {
+            properties.insert(sort_key.to_string(), sort_total.into());
+        }
+        output.push(TreeFeature {
+            geometry: Geometry::MultiLineString(MultiLineString::new(lines)),
+            properties: Some(properties),
+        });
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use geo_types::{LineString, Point};
+    use serde_json::json;
+
+    use super::*;
+
+    fn feature(geometry: impl Into<Geometry>, properties: Value) -> TreeFeature {
+        TreeFeature {
+            geometry: geometry.into(),
+            properties: properties.as_object().cloned(),
+        }
+    }
+
+    fn line(x: f64) -> LineString {
+        LineString::from(vec![(x, 0.0), (x, 1.0)])
+    }
+
+    fn summary(features: &[TreeFeature]) -> Vec<(&Geometry, Value)> {
+        features
+            .iter()
+            .map(|f| (&f.geometry, json!(f.properties)))
+            .collect()
+    }
+
+    #[test]
+    fn test_dissolve() {
+        let features = vec![
+            feature(line(1.0), json!({ "name": "A", "count": 1.5, "other": 1 })),
+            feature(line(2.0), json!({ "name": "B", "count": 2 })),
+            feature(
+                MultiLineString::new(vec![line(3.0), line(4.0)]),
+                json!({ "name": "A", "count": 3 }),
+            ),
+            // Without the key, or a line to merge, these pass through
+            feature(line(5.0), json!({ "count": 10 })),
+            feature(line(6.0), json!({ "name": null })),
+            feature(Point::new(7.0, 0.0), json!({ "name": "A", "count": 100 })),
+            feature(line(8.0), json!({ "name": "B" })),
+        ];
+
+        let output = dissolve(features, "name", Some("count"));
+        let a =
+            Geometry::MultiLineString(MultiLineString::new(vec![line(1.0), line(3.0), line(4.0)]));
+        let b = Geometry::MultiLineString(MultiLineString::new(vec![line(2.0), line(8.0)]));
+        assert_eq!(
+            summary(&output),
+            [
+                (&line(5.0).into(), json!({ "count": 10 })),
+                (&line(6.0).into(), json!({ "name": null })),
+                (
+                    &Point::new(7.0, 0.0).into(),
+                    json!({ "name": "A", "count": 100 })
+                ),
+                (&a, json!({ "name": "A", "count": 4.5 })),
+                (&b, json!({ "name": "B", "count": 2.0 })),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dissolve_without_sort_key() {
+        let features = vec![
+            feature(line(1.0), json!({ "name": 2, "count": 1 })),
+            feature(line(2.0), json!({ "name": 2, "count": 2 })),
+        ];
+        let output = dissolve(features, "name", None);
+        let merged = Geometry::MultiLineString(MultiLineString::new(vec![line(1.0), line(2.0)]));
+        assert_eq!(summary(&output), [(&merged, json!({ "name": 2 }))]);
+    }
+
+    #[test]
+    fn test_dissolve_group_order() {
+        // Groups come out in the order they're first seen, whatever the hash order is
+        let names = ["m", "c", "x", "a", "q", "c", "z", "a", "b"];
+        let features = || {
+            names
+                .iter()
+                .enumerate()
+                .map(|(idx, name)| feature(line(idx as f64), json!({ "name": name })))
+                .collect::<Vec<_>>()
+        };
+        for _ in 0..5 {
+            let output: Vec<Value> = dissolve(features(), "name", None)
+                .into_iter()
+                .map(|f| f.properties.unwrap()["name"].clone())
+                .collect();
+            assert_eq!(output, ["m", "c", "x", "a", "q", "z", "b"]);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+
+/// Why a conversion failed, returned by the functions that make and update archives. Problems deep
+/// inside, like a reader from `input` or a sink from `output` failing, end up in `Io` if they're
+/// I/O errors and `Other` if not.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConversionError {
+    /// Reading or writing a file failed
+    Io(std::io::Error),
+    /// The input couldn't give the feature at this position in the layer, like when it isn't
+    /// valid GeoJSON
+    Parse {
+        layer: String,
+        index: usize,
+        source: anyhow::Error,
+    },
+    /// The feature at this position in the layer can't be tiled, like when it has no geometry.
+    /// `Options::skip_invalid` leaves these out instead.
+    InvalidGeometry {
+        layer: String,
+        index: usize,
+        reason: String,
+    },
+    /// The options can't work, on their own or with these features
+    InvalidOptions(String),
+    /// Making a tile failed. `tile` is its `z/x/y`.
+    Encoding {
+        tile: String,
+        source: anyhow::Error,
+    },
+    /// `Options::cancel` was set
+    Cancelled,
+    Other(anyhow::Error),
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Parse { layer, index, .. } => {
+                write!(f, "Feature {index} in layer {layer} couldn't be read")
+            }
+            Self::InvalidGeometry {
+                layer,
+                index,
+                reason,
+            } => write!(
+                f,
+                "Feature {index} in layer {layer} can't be tiled: {reason}"
+            ),
+            Self::InvalidOptions(message) => write!(f, "{message}"),
+            Self::Encoding { tile, .. } => write!(f, "Making tile {tile} failed"),
+            Self::Cancelled => write!(f, "The conversion was cancelled"),
+            Self::Other(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => err.source(),
+            Self::Parse { source, .. } | Self::Encoding { source, .. } => Some(source.as_ref()),
+            Self::InvalidGeometry { .. } | Self::InvalidOptions(_) | Self::Cancelled => None,
+            Self::Other(err) => err.source(),
+        }
+    }
+}
+
+/// Internally errors are `anyhow::Error`, with a `ConversionError` inside wherever the kind is
+/// known
+impl From<anyhow::Error> for ConversionError {
+    fn from(err: anyhow::Error) -> Self {
+        // Only unwrap errors with no context added, so the context isn't lost
+        let outer: &(dyn std::error::Error + 'static) = err.as_ref();
+        let err = if outer.is::<ConversionError>() {
+            match err.downcast() {
+                Ok(err) => return err,
+                Err(err) => err,
+            }
+        } else if outer.is::<std::io::Error>() {
+            match err.downcast() {
+                Ok(err) => return Self::Io(err),
+                Err(err) => err,
+            }
+        } else {
+            err
+        };
+        Self::Other(err)
+    }
+}
+
+impl From<std::io::Error> for ConversionError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+pub(crate) fn invalid_options(message: impl Into<String>) -> anyhow::Error {
+    ConversionError::InvalidOptions(message.into()).into()
+}
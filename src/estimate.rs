@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use rayon::prelude::*;
+
+use crate::clock::Instant;
+use crate::{
+    borrow_layers, load_inputs, tiles_covering, ConversionError, LayerOptions, Options, Tiler,
+};
+
+/// What `estimate` found for one zoom
+pub struct ZoomEstimate {
+    pub zoom: u32,
+    /// Every tile covering the bounds
+    pub num_tiles: usize,
+    /// Tiles touching at least one feature. Some of these can still come out empty.
+    pub num_tiles_with_features: usize,
+    /// How many of those were actually made
+    pub num_sampled: usize,
+    /// The compressed size of all the tiles at this zoom, scaled up from the sample
+    pub bytes: u64,
+    /// The time to make all the tiles at this zoom on one thread, scaled up from the sample
+    pub cpu_time: Duration,
+}
+
+/// Roughly how big an archive would be and how long it'd take to make, from a sample of tiles
+pub struct Estimate {
+    pub zooms: Vec<ZoomEstimate>,
+    /// How many threads tiles are made on
+    pub num_threads: usize,
+}
+
+impl Estimate {
+    /// The total size of the tiles, leaving out the much smaller directories and metadata
+    pub fn bytes(&self) -> u64 {
+        self.zooms.iter().map(|zoom| zoom.bytes).sum()
+    }
+
+    /// The wall clock time to make every tile, after the features are loaded
+    pub fn duration(&self) -> Duration {
+        let cpu_time: Duration = self.zooms.iter().map(|zoom| zoom.cpu_time).sum();
+        cpu_time / self.num_threads.max(1) as u32
+    }
+}
+
+/// Loads the features and works out every tile that `layers_to_pmtiles` would make, but only makes
+/// up to `samples_per_zoom` of them at each zoom, spread evenly across the ones with features.
+/// Tiles vary a lot, so more samples give a steadier estimate.
+pub fn estimate<I: Iterator<Item = Result<geojson::Feature>>>(
+    inputs: Vec<(LayerOptions, I)>,
+    options: Options,
+    samples_per_zoom: usize,
+) -> Result<Estimate, ConversionError> {
+    let loaded = load_inputs(inputs, &options)?;
+    let (tiler, _) = Tiler::new(borrow_layers(&loaded), options)?;
+
+    Ok(tiler.install(|| sample_zooms(&tiler, samples_per_zoom))?)
+}
+
+fn sample_zooms(tiler: &Tiler, samples_per_zoom: usize) -> Result<Estimate> {
+    let mut zooms = Vec::new();
+    for zoom in &tiler.zoom_levels {
+        let tiles = tiles_covering(&[*zoom], &tiler.bbox)?;
+        let num_tiles = tiles.len();
+        let with_features: Vec<_> = tiles
+            .into_par_iter()
+            .map(|tile_id| {
+                let features = tiler.features_in_tile(tile_id)?;
+                let any = features.iter().any(|(_, features)| !features.is_empty());
+                Ok(any.then_some(tile_id))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        let step = with_features.len().div_ceil(samples_per_zoom.max(1)).max(1);
+        let samples: Vec<_> = with_features.iter().step_by(step).collect();
+        let sampled = samples
+            .into_par_iter()
+            .map(|tile_id| {
+                let start = Instant::now();
+                let tile = tiler.encode_tile(*tile_id)?;
+                Ok((tile.map_or(0, |(_, data)| data.len()), start.elapsed()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let scale = if sampled.is_empty() {
+            0.0
+        } else {
+            with_features.len() as f64 / sampled.len() as f64
+        };
+        let bytes: usize = sampled.iter().map(|(bytes, _)| bytes).sum();
+        let cpu_time: Duration = sampled.iter().map(|(_, time)| *time).sum();
+        zooms.push(ZoomEstimate {
+            zoom: *zoom,
+            num_tiles,
+            num_tiles_with_features: with_features.len(),
+            num_sampled: sampled.len(),
+            bytes: (bytes as f64 * scale) as u64,
+            cpu_time: cpu_time.mul_f64(scale),
+        });
+    }
+
+    Ok(Estimate {
+        zooms,
+        num_threads: rayon::current_num_threads(),
+    })
+}
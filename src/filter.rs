@@ -0,0 +1,398 @@
+use std::cmp::Ordering;
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use crate::ConversionError;
+
+/// Decides which features to keep, from an expression over their properties like
+/// `count > 10 && highway != "footway"`.
+///
+/// Property names are bare words, and `$type` is the geometry type, like `"LineString"`. Values
+/// are numbers, strings in single or double quotes, `true`, `false`, and `null`. A missing property
+/// is `null`. Comparisons are `==`, `!=`, `<`, `<=`, `>`, and `>=`, and ordering only works
+/// between two numbers or two strings. They combine with `&&`, `||`, `!`, and parentheses. A
+/// property on its own is true unless it's missing, `null`, `false`, `0`, or `""`.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Literal(Value),
+    Property(String),
+    GeometryType,
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    String(String),
+    Number(f64),
+    Op(&'static str),
+}
+
+/// Longer operators go first, so `<=` isn't read as `<`
+const OPS: [&str; 12] = [
+    "&&", "||", "==", "!=", "<=", ">=", "=", "<", ">", "!", "(", ")",
+];
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Self, ConversionError> {
+        Self::parse_expr(source).map_err(|err| ConversionError::InvalidOptions(err.to_string()))
+    }
+
+    fn parse_expr(source: &str) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            bail!("Unexpected {} in the filter {source}", describe(token));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// The expression this was parsed from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// True if a feature with these properties and geometry should be kept
+    pub fn matches(&self, feature: &geojson::Feature) -> bool {
+        truthy(&self.expr.eval(feature))
+    }
+}
+
+/// As the expression it was parsed from
+#[cfg(feature = "serde")]
+impl serde::Serialize for Filter {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&self.source)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Filter {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(d)?;
+        Self::parse_expr(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Expr {
+    fn eval(&self, feature: &geojson::Feature) -> Value {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Property(key) => feature
+                .properties
+                .as_ref()
+                .and_then(|props| props.get(key))
+                .cloned()
+                .unwrap_or(Value::Null),
+            Expr::GeometryType => feature
+                .geometry
+                .as_ref()
+                .map_or(Value::Null, |geometry| geometry.value.type_name().into()),
+            Expr::Not(expr) => (!truthy(&expr.eval(feature))).into(),
+            Expr::And(a, b) => (truthy(&a.eval(feature)) && truthy(&b.eval(feature))).into(),
+            Expr::Or(a, b) => (truthy(&a.eval(feature)) || truthy(&b.eval(feature))).into(),
+            Expr::Compare(a, op, b) => {
+                let (a, b) = (a.eval(feature), b.eval(feature));
+                let ordering = compare(&a, &b);
+                match op {
+                    CompareOp::Eq => ordering == Some(Ordering::Equal) || a == b,
+                    CompareOp::Ne => !(ordering == Some(Ordering::Equal) || a == b),
+                    CompareOp::Lt => ordering == Some(Ordering::Less),
+                    CompareOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    CompareOp::Gt => ordering == Some(Ordering::Greater),
+                    CompareOp::Ge => {
+                        matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+                    }
+                }
+                .into()
+            }
+        }
+    }
+}
+
+/// Numbers compare as numbers, so `1 == 1.0`
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(x) => *x,
+        Value::Number(x) => x.as_f64() != Some(0.0),
+        Value::String(x) => !x.is_empty(),
+        Value::Array(_) | Value::Object(_) => true,
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '"' || c == '\'' {
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, x)) if x == c => break i + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, x)) => value.push(x),
+                        None => bail!("The filter {source} ends in the middle of a string"),
+                    },
+                    Some((_, x)) => value.push(x),
+                    None => bail!("The filter {source} has a string with no closing {c}"),
+                }
+            };
+            tokens.push(Token::String(value));
+            rest = &rest[end..];
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            let end = rest[1..]
+                .find(|x: char| !(x.is_ascii_alphanumeric() || x == '.'))
+                .map_or(rest.len(), |i| i + 1);
+            let Ok(number) = rest[..end].parse() else {
+                bail!("{} in the filter {source} isn't a number", &rest[..end]);
+            };
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let end = rest
+                .find(|x: char| !(x.is_alphanumeric() || x == '_' || x == ':' || x == '$'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            bail!("Unexpected {c} in the filter {source}");
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(x) => x.clone(),
+        Token::String(x) => format!("{x:?}"),
+        Token::Number(x) => x.to_string(),
+        Token::Op(x) => x.to_string(),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn eat(&mut self, op: &'static str) -> bool {
+        if self.tokens.get(self.pos) == Some(&Token::Op(op)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let left = self.value()?;
+        let op = match self.tokens.get(self.pos) {
+            Some(Token::Op("==" | "=")) => CompareOp::Eq,
+            Some(Token::Op("!=")) => CompareOp::Ne,
+            Some(Token::Op("<")) => CompareOp::Lt,
+            Some(Token::Op("<=")) => CompareOp::Le,
+            Some(Token::Op(">")) => CompareOp::Gt,
+            Some(Token::Op(">=")) => CompareOp::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.value()?;
+        Ok(Expr::Compare(Box::new(left), op, Box::new(right)))
+    }
+
+    fn value(&mut self) -> Result<Expr> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            bail!("The filter ends too soon");
+        };
+        self.pos += 1;
+        Ok(match token {
+            Token::Op("(") => {
+                let expr = self.or()?;
+                if !self.eat(")") {
+                    bail!("The filter is missing a )");
+                }
+                expr
+            }
+            Token::Word(word) => match word.as_str() {
+                "true" => Expr::Literal(true.into()),
+                "false" => Expr::Literal(false.into()),
+                "null" => Expr::Literal(Value::Null),
+                "$type" => Expr::GeometryType,
+                _ => Expr::Property(word),
+            },
+            Token::String(x) => Expr::Literal(x.into()),
+            Token::Number(x) => Expr::Literal(x.into()),
+            Token::Op(op) => bail!("Expected a value in the filter, not {op}"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn feature(properties: Value) -> geojson::Feature {
+        geojson::Feature {
+            geometry: Some(geojson::Geometry::new(geojson::Value::LineString(vec![
+                vec![0.0, 0.0],
+                vec![1.0, 1.0],
+            ]))),
+            properties: properties.as_object().cloned(),
+            ..Default::default()
+        }
+    }
+
+    fn matches(source: &str, properties: Value) -> bool {
+        Filter::parse(source).unwrap().matches(&feature(properties))
+    }
+
+    fn error(source: &str) -> String {
+        Filter::parse_expr(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_precedence() {
+        let props = json!({ "a": true, "b": false, "c": false });
+        // && binds tighter than ||
+        assert!(matches("a || b && c", props.clone()));
+        assert!(!matches("(a || b) && c", props.clone()));
+        // ! binds tighter than &&
+        assert!(matches("!b && a", props.clone()));
+        assert!(!matches("!(b || a)", props.clone()));
+        // Comparisons bind tighter than everything else
+        assert!(matches("!b == true", props.clone()));
+        assert!(matches("count > 1 || a", props));
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let props = json!({ "count": 10, "highway": "primary", "zero": 0 });
+        assert!(matches("count >= 10 && count <= 10", props.clone()));
+        assert!(matches("count == 10.0", props.clone()));
+        assert!(matches("count = 10", props.clone()));
+        assert!(!matches("count < 10", props.clone()));
+        assert!(matches("highway > \"footway\"", props.clone()));
+        assert!(matches("highway != 'footway'", props.clone()));
+        // Ordering strings against numbers is never true
+        assert!(!matches("highway > 1", props.clone()));
+        assert!(!matches("highway < 1", props.clone()));
+        assert!(matches("missing == null", props.clone()));
+        assert!(!matches("missing", props.clone()));
+        assert!(!matches("zero", props.clone()));
+        assert!(matches("$type == \"LineString\"", props.clone()));
+        assert!(matches("count > -1.5", props));
+    }
+
+    #[test]
+    fn test_quoting() {
+        assert!(matches(r#"name == 'it\'s'"#, json!({ "name": "it's" })));
+        assert!(matches(
+            r#"name == "say \"hi\"""#,
+            json!({ "name": "say \"hi\"" })
+        ));
+        assert!(matches(r#"name == '"'"#, json!({ "name": "\"" })));
+        assert!(matches(r#"name == "a\\b""#, json!({ "name": "a\\b" })));
+        assert!(matches(r#"name == "a && b""#, json!({ "name": "a && b" })));
+        assert!(matches("addr:street == ''", json!({ "addr:street": "" })));
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("a<=1").unwrap(),
+            vec![
+                Token::Word("a".to_string()),
+                Token::Op("<="),
+                Token::Number(1.0)
+            ]
+        );
+        assert_eq!(
+            tokenize(" !x_1  ").unwrap(),
+            vec![Token::Op("!"), Token::Word("x_1".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            error("name == \"abc"),
+            "The filter name == \"abc has a string with no closing \""
+        );
+        assert_eq!(
+            error("name == 'abc\\"),
+            "The filter name == 'abc\\ ends in the middle of a string"
+        );
+        assert_eq!(error("1.2.3"), "1.2.3 in the filter 1.2.3 isn't a number");
+        assert_eq!(error("a # b"), "Unexpected # in the filter a # b");
+        assert_eq!(error("a b"), "Unexpected b in the filter a b");
+        assert_eq!(error("a == 'b' c"), "Unexpected c in the filter a == 'b' c");
+        assert_eq!(error("(a || b"), "The filter is missing a )");
+        assert_eq!(error("a =="), "The filter ends too soon");
+        assert_eq!(error(""), "The filter ends too soon");
+        assert_eq!(error("a && )"), "Expected a value in the filter, not )");
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::spill::FeatureRef;
+use crate::Options;
+use anyhow::Result;
+use geo_types::{Coord, Geometry};
+use mvt::{GeomEncoder, GeomType, MapGrid, Tile, TileId};
+use pointy::Transform;
+
+/// Replaces the raw features at low zooms with a square grid of polygons, each carrying the
+/// aggregated sort key (or the number of features, if there's no sort key).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GridAggregation {
+    /// Zoom levels up to and including this one are aggregated
+    pub max_zoom: u32,
+    pub layer_name: String,
+    /// How many grid cells to divide each side of a tile into
+    pub cells_per_tile: u32,
+}
+
+impl GridAggregation {
+    /// The property holding the aggregated value in each cell
+    pub fn value_key<'a>(&self, options: &'a Options) -> &'a str {
+        options.sort_by_key.as_deref().unwrap_or("count")
+    }
+}
+
+pub fn make_grid_tile(
+    current_tile_id: TileId,
+    features: Vec<FeatureRef<'_>>,
+    grid: &GridAggregation,
+    options: &Options,
+) -> Result<Option<(TileId, Tile)>> {
+    let transform = MapGrid::default().tile_transform(current_tile_id);
+    let n = grid.cells_per_tile;
+
+    // (cell x, cell y) => (total value, number of features)
+    let mut cells: HashMap<(u32, u32), (f64, usize)> = HashMap::new();
+    for feature in features {
+        // Not the rounded sort key, so fractional values add up exactly
+        let weight = match options.sort_by_key {
+            Some(ref key) => feature
+                .properties
+                .as_ref()
+                .and_then(|properties| properties.get(key)?.as_f64())
+                .unwrap_or(0.0),
+            None => 1.0,
+        };
+        // A feature only counts once per cell, no matter how many of its vertices land there
+        let mut touched = HashSet::new();
+        match feature.geometry {
+            Geometry::Point(pt) => mark_cell(&mut touched, pt.0, &transform, n),
+            Geometry::LineString(ref line_string) => {
+                mark_line(&mut touched, line_string, &transform, n)
+            }
+            Geometry::MultiLineString(ref multi_line_string) => {
+                for line_string in multi_line_string {
+                    mark_line(&mut touched, line_string, &transform, n);
+                }
+            }
+            _ => continue,
+        }
+        for cell in touched {
+            let entry = cells.entry(cell).or_insert((0.0, 0));
+            entry.0 += weight;
+            entry.1 += 1;
+        }
+    }
+
+    if cells.is_empty() {
+        return Ok(None);
+    }
+
+    // Sort cells, so output is deterministic
+    let mut cells: Vec<_> = cells.into_iter().collect();
+    cells.sort_by_key(|(cell, _)| *cell);
+
+    let mut tile = Tile::new(options.extent);
+    let mut layer = tile.create_layer(&grid.layer_name);
+    let cell_size = options.extent as f64 / n as f64;
+    for ((x, y), (value, num_features)) in cells {
+        let (x1, y1) = (x as f64 * cell_size, y as f64 * cell_size);
+        let (x2, y2) = (x1 + cell_size, y1 + cell_size);
+        // This winding order has positive area in tile coordinates, making it an exterior ring
+        let encoded = GeomEncoder::new(GeomType::Polygon, Transform::default())
+            .point(x1, y1)?
+            .point(x2, y1)?
+            .point(x2, y2)?
+            .point(x1, y2)?
+            .encode()?;
+
+        let id = layer.num_features() as u64;
+        let mut write_feature = layer.into_feature(encoded);
+        write_feature.set_id(id);
+        write_feature.add_tag_double(grid.value_key(options), value);
+        write_feature.add_tag_uint("num_features", num_features as u64);
+        layer = write_feature.into_layer();
+    }
+    tile.add_layer(layer)?;
+
+    Ok(Some((current_tile_id, tile)))
+}
+
+fn mark_line(
+    touched: &mut HashSet<(u32, u32)>,
+    line_string: &geo_types::LineString,
+    transform: &Transform<f64>,
+    n: u32,
+) {
+    for line in line_string.lines() {
+        // Walk along each segment in steps smaller than a cell, so long segments don't skip the
+        // cells between their endpoints
+        let start = *transform * (line.start.x, line.start.y);
+        let end = *transform * (line.end.x, line.end.y);
+        let cells_crossed =
+            ((end.x - start.x).abs().max((end.y - start.y).abs()) * n as f64).ceil();
+        let steps = (2.0 * cells_crossed).max(1.0) as usize;
+        for i in 0..=steps {
+            let t = i as f64 / steps as f64;
+            let x = start.x + t * (end.x - start.x);
+            let y = start.y + t * (end.y - start.y);
+            mark_tile_pt(touched, x, y, n);
+        }
+    }
+}
+
+fn mark_cell(touched: &mut HashSet<(u32, u32)>, pt: Coord, transform: &Transform<f64>, n: u32) {
+    let pt = *transform * (pt.x, pt.y);
+    mark_tile_pt(touched, pt.x, pt.y, n);
+}
+
+/// Takes 0-1 tile coordinates
+fn mark_tile_pt(touched: &mut HashSet<(u32, u32)>, x: f64, y: f64, n: u32) {
+    // Parts of the feature outside this tile belong to cells in a neighboring tile
+    if !(0.0..1.0).contains(&x) || !(0.0..1.0).contains(&y) {
+        return;
+    }
+    touched.insert(((x * n as f64) as u32, (y * n as f64) as u32));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use geo_types::{LineString, Point};
+    use serde_json::json;
+
+    use super::*;
+    use crate::input::TileDecoder;
+    use crate::TreeFeature;
+
+    fn feature(geometry: Geometry, weight: Option<f64>) -> FeatureRef<'static> {
+        let properties =
+            weight.map(|weight| json!({ "weight": weight }).as_object().unwrap().clone());
+        Cow::Owned(TreeFeature::project(geometry, properties))
+    }
+
+    /// Each cell's x and y, value, and number of features
+    fn cells(features: Vec<FeatureRef<'_>>, options: &Options) -> Vec<((u32, u32), f64, u64)> {
+        let grid = GridAggregation {
+            max_zoom: 0,
+            layer_name: "grid".to_string(),
+            cells_per_tile: 4,
+        };
+        let tile_id = TileId::new(0, 0, 0).unwrap();
+        let (_, tile) = make_grid_tile(tile_id, features, &grid, options)
+            .unwrap()
+            .unwrap();
+        let mut decoder = TileDecoder::new(None);
+        decoder.pixel_coords = true;
+        decoder.decode(&tile.to_bytes().unwrap(), 0, 0, 0).unwrap();
+
+        let cell_size = options.extent as f64 / 4.0;
+        decoder
+            .features
+            .into_iter()
+            .map(|feature| {
+                let geojson::Value::Polygon(rings) = feature.geometry.unwrap().value else {
+                    panic!("Cells should be polygons");
+                };
+                let min = |axis: usize| {
+                    let min = rings[0].iter().map(|pt| pt[axis]).fold(f64::MAX, f64::min);
+                    (min / cell_size).round() as u32
+                };
+                let properties = feature.properties.unwrap();
+                (
+                    (min(0), min(1)),
+                    properties[grid.value_key(options)].as_f64().unwrap(),
+                    properties["num_features"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_make_grid_tile() {
+        // At zoom 0 with 4 cells a side, each cell is 90 degrees wide, and the equator is between
+        // rows 1 and 2
+        let features = || {
+            vec![
+                feature(Point::new(-135.0, 10.0).into(), Some(1.5)),
+                feature(Point::new(-100.0, 20.0).into(), Some(2.25)),
+                // One segment across every column
+                feature(
+                    LineString::from(vec![(-170.0, -10.0), (170.0, -10.0)]).into(),
+                    Some(0.5),
+                ),
+                // Only counted once in its cell
+                feature(
+                    LineString::from(vec![(100.0, 10.0), (110.0, 12.0), (120.0, 11.0)]).into(),
+                    Some(4.0),
+                ),
+                feature(Point::new(120.0, 40.0).into(), None),
+            ]
+        };
+
+        let options = Options {
+            sort_by_key: Some("weight".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            cells(features(), &options),
+            [
+                ((0, 1), 3.75, 2),
+                ((0, 2), 0.5, 1),
+                ((1, 2), 0.5, 1),
+                ((2, 2), 0.5, 1),
+                ((3, 1), 4.0, 2),
+                ((3, 2), 0.5, 1),
+            ]
+        );
+
+        // Without a sort key, each feature counts as 1
+        assert_eq!(
+            cells(features(), &Options::default()),
+            [
+                ((0, 1), 2.0, 2),
+                ((0, 2), 1.0, 1),
+                ((1, 2), 1.0, 1),
+                ((2, 2), 1.0, 1),
+                ((3, 1), 2.0, 2),
+                ((3, 2), 1.0, 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Readable counts, sizes, and durations for logs and summaries, formatted like indicatif does, so
+//! they don't need the `progress` feature
+
+use std::fmt;
+use std::time::Duration;
+
+/// A count with thousands separators, like `1,234,567`
+pub struct HumanCount(pub u64);
+
+/// A size in binary units, like `1.50 MiB`
+pub struct HumanBytes(pub u64);
+
+/// A duration in its largest whole unit, like `3 minutes`
+pub struct HumanDuration(pub Duration);
+
+impl fmt::Display for HumanCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.0.to_string();
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                write!(f, ",")?;
+            }
+            write!(f, "{digit}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for HumanBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut size = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{size:.2} {}", UNITS[unit])
+    }
+}
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [(&str, u64); 5] = [
+            ("day", 24 * 60 * 60),
+            ("hour", 60 * 60),
+            ("minute", 60),
+            ("second", 1),
+            ("millisecond", 0),
+        ];
+        let seconds = self.0.as_secs();
+        for (name, length) in UNITS {
+            let n = match length {
+                0 => self.0.as_millis() as u64,
+                _ if seconds < length => continue,
+                _ => seconds / length,
+            };
+            let plural = if n == 1 { "" } else { "s" };
+            return write!(f, "{n} {name}{plural}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use std::io::{Cursor, Read};
+
+use anyhow::{bail, Result};
+use fs_err::File;
+use zip::ZipArchive;
+
+use super::shp::read_shapefile_from;
+use super::{read_stream, read_via_temp_file, FeatureIter, Format, InputOptions};
+
+/// Reads one file from a zip archive: `InputOptions::archive_member` if it's set, or otherwise
+/// the only file in the archive in a format that can be read. Shapefiles are read along with the
+/// `.dbf` and `.prj` next to them.
+pub fn read_zip(path: &str, input_options: &InputOptions) -> Result<FeatureIter> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+
+    let member = match input_options.archive_member {
+        Some(ref member) => {
+            if archive.index_for_name(member).is_none() {
+                bail!("{path} doesn't contain {member}");
+            }
+            member.clone()
+        }
+        None => {
+            let candidates: Vec<&str> = archive
+                .file_names()
+                .filter(|name| !name.ends_with('/') && !name.starts_with("__MACOSX/"))
+                .filter(|name| Format::detect(name).is_some())
+                .collect();
+            match candidates.as_slice() {
+                [member] => member.to_string(),
+                [] => bail!("{path} doesn't contain any files in a supported format"),
+                _ => bail!(
+                    "{path} contains multiple files, so specify which one to read: {}",
+                    candidates.join(", ")
+                ),
+            }
+        }
+    };
+
+    let format = Format::from_path(&member);
+    if let Format::Shapefile = format {
+        let shp = read_member(&mut archive, &member)?;
+        let stem = &member[..member.len() - ".shp".len()];
+        let Some(dbf) = find_sibling(&archive, stem, ".dbf") else {
+            bail!("{path} has {member}, but no .dbf file for it");
+        };
+        let dbf = read_member(&mut archive, &dbf)?;
+        let prj = match find_sibling(&archive, stem, ".prj") {
+            Some(prj) => Some(String::from_utf8(read_member(&mut archive, &prj)?)?),
+            None => None,
+        };
+        return read_shapefile_from(Cursor::new(shp), Cursor::new(dbf), prj.as_deref());
+    }
+
+    // Extract into memory, rather than leaving files next to the archive
+    let bytes = read_member(&mut archive, &member)?;
+    if format.is_streamable() {
+        return read_stream(Cursor::new(bytes), &member, format, input_options);
+    }
+    read_via_temp_file(Cursor::new(bytes), &member, input_options)
+}
+
+fn read_member(archive: &mut ZipArchive<File>, name: &str) -> Result<Vec<u8>> {
+    let mut file = archive.by_name(name)?;
+    let mut bytes = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// The extension's case might not match the `.shp`
+fn find_sibling(archive: &ZipArchive<File>, stem: &str, ext: &str) -> Option<String> {
+    let want = format!("{stem}{ext}").to_lowercase();
+    archive
+        .file_names()
+        .find(|name| name.to_lowercase() == want)
+        .map(|name| name.to_string())
+}
@@ -0,0 +1,116 @@
+use anyhow::{bail, Result};
+use arrow_array::{cast::AsArray, types::*, Array, RecordBatch};
+use arrow_schema::DataType;
+use geozero::{wkb::Wkb, ToGeo};
+use serde_json::Value;
+
+/// Turns Arrow record batches with a WKB geometry column into features. Every other column becomes
+/// a property.
+pub struct RecordBatchFeatures<I> {
+    batches: I,
+    geometry_column: String,
+    current: Option<(RecordBatch, usize)>,
+    row: usize,
+}
+
+impl<I: Iterator<Item = Result<RecordBatch>>> RecordBatchFeatures<I> {
+    pub fn new(batches: I, geometry_column: String) -> Self {
+        Self {
+            batches,
+            geometry_column,
+            current: None,
+            row: 0,
+        }
+    }
+
+    fn convert_row(&self, batch: &RecordBatch, geometry_idx: usize) -> Result<geojson::Feature> {
+        let column = batch.column(geometry_idx);
+        let wkb = match column.data_type() {
+            DataType::Binary => column.as_binary::<i32>().value(self.row),
+            DataType::LargeBinary => column.as_binary::<i64>().value(self.row),
+            DataType::BinaryView => column.as_binary_view().value(self.row),
+            other => bail!(
+                "Geometry column {} has type {other}, but only WKB is supported",
+                self.geometry_column
+            ),
+        };
+        let geometry = if column.is_null(self.row) {
+            None
+        } else {
+            Some(geojson::Geometry::from(&Wkb(wkb).to_geo()?))
+        };
+
+        let mut properties = geojson::JsonObject::new();
+        for (idx, field) in batch.schema().fields().iter().enumerate() {
+            if idx == geometry_idx {
+                continue;
+            }
+            let value = array_value_to_json(batch.column(idx).as_ref(), self.row)?;
+            if !value.is_null() {
+                properties.insert(field.name().to_string(), value);
+            }
+        }
+
+        Ok(geojson::Feature {
+            bbox: None,
+            geometry,
+            id: None,
+            properties: Some(properties),
+            foreign_members: None,
+        })
+    }
+}
+
+impl<I: Iterator<Item = Result<RecordBatch>>> Iterator for RecordBatchFeatures<I> {
+    type Item = Result<geojson::Feature>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((ref batch, geometry_idx)) = self.current {
+                if self.row < batch.num_rows() {
+                    let result = self.convert_row(batch, geometry_idx);
+                    self.row += 1;
+                    return Some(result);
+                }
+            }
+
+            let batch = match self.batches.next()? {
+                Ok(batch) => batch,
+                Err(err) => return Some(Err(err)),
+            };
+            let Some((geometry_idx, _)) = batch.schema().column_with_name(&self.geometry_column)
+            else {
+                return Some(Err(anyhow::anyhow!(
+                    "No geometry column called {}",
+                    self.geometry_column
+                )));
+            };
+            self.current = Some((batch, geometry_idx));
+            self.row = 0;
+        }
+    }
+}
+
+fn array_value_to_json(array: &dyn Array, row: usize) -> Result<Value> {
+    if array.is_null(row) {
+        return Ok(Value::Null);
+    }
+    Ok(match array.data_type() {
+        DataType::Boolean => array.as_boolean().value(row).into(),
+        DataType::Int8 => array.as_primitive::<Int8Type>().value(row).into(),
+        DataType::Int16 => array.as_primitive::<Int16Type>().value(row).into(),
+        DataType::Int32 => array.as_primitive::<Int32Type>().value(row).into(),
+        DataType::Int64 => array.as_primitive::<Int64Type>().value(row).into(),
+        DataType::UInt8 => array.as_primitive::<UInt8Type>().value(row).into(),
+        DataType::UInt16 => array.as_primitive::<UInt16Type>().value(row).into(),
+        DataType::UInt32 => array.as_primitive::<UInt32Type>().value(row).into(),
+        DataType::UInt64 => array.as_primitive::<UInt64Type>().value(row).into(),
+        DataType::Float32 => array.as_primitive::<Float32Type>().value(row).into(),
+        DataType::Float64 => array.as_primitive::<Float64Type>().value(row).into(),
+        DataType::Utf8 => array.as_string::<i32>().value(row).into(),
+        DataType::LargeUtf8 => array.as_string::<i64>().value(row).into(),
+        DataType::Utf8View => array.as_string_view().value(row).into(),
+        // Dates, lists, structs, etc are encoded as strings, like nested GeoJSON properties
+        _ => arrow_cast::display::array_value_to_string(array, row)?.into(),
+    })
+}
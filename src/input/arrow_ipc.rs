@@ -0,0 +1,59 @@
+use std::io::{BufRead, BufReader, Read};
+
+use anyhow::{bail, Result};
+use arrow_ipc::reader::StreamReader;
+use arrow_schema::{DataType, Schema};
+
+use super::arrow::RecordBatchFeatures;
+use super::FeatureIter;
+
+/// Reads an Arrow IPC file (like Feather) or stream, with a WKB geometry column in WGS84. The
+/// geometry column is the one marked as `geoarrow.wkb`, or else one called `geometry`, `geom`, or
+/// `wkb_geometry`. Since this only needs to read forwards, it works through a pipe too.
+pub fn read_arrow_ipc<R: Read + 'static>(reader: R) -> Result<FeatureIter> {
+    let mut reader = BufReader::new(reader);
+    // The file format is the stream format with some magic bytes and padding before, and a footer
+    // after that isn't needed to read everything
+    if reader.fill_buf()?.starts_with(b"ARROW1") {
+        reader.consume(6);
+        loop {
+            let buf = reader.fill_buf()?;
+            let padding = buf.iter().take_while(|x| **x == 0).count();
+            let done = padding < buf.len() || buf.is_empty();
+            reader.consume(padding);
+            if done {
+                break;
+            }
+        }
+    }
+    let stream = StreamReader::try_new(reader, None)?;
+    let geometry_column = find_geometry_column(&stream.schema())?;
+    let batches = stream.map(|batch| batch.map_err(anyhow::Error::from));
+    Ok(Box::new(RecordBatchFeatures::new(batches, geometry_column)))
+}
+
+fn find_geometry_column(schema: &Schema) -> Result<String> {
+    let is_binary = |data_type: &DataType| {
+        matches!(
+            data_type,
+            DataType::Binary | DataType::LargeBinary | DataType::BinaryView
+        )
+    };
+    if let Some(field) = schema.fields().iter().find(|field| {
+        field
+            .metadata()
+            .get("ARROW:extension:name")
+            .map(|x| x.as_str())
+            == Some("geoarrow.wkb")
+    }) {
+        return Ok(field.name().to_string());
+    }
+    for name in ["geometry", "geom", "wkb_geometry"] {
+        if let Ok(field) = schema.field_with_name(name) {
+            if is_binary(field.data_type()) {
+                return Ok(name.to_string());
+            }
+        }
+    }
+    bail!("Arrow input has no WKB geometry column")
+}
@@ -0,0 +1,65 @@
+use std::io::{BufRead, BufReader, Read};
+
+use anyhow::Result;
+use flate2::read::MultiGzDecoder;
+use fs_err::File;
+
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Checks the magic bytes at the start of a file, no matter what its extension is
+    pub fn detect(path: &str) -> Result<Option<Compression>> {
+        let mut magic = [0; 4];
+        let mut file = File::open(path)?;
+        let mut len = 0;
+        // Very short files might not have 4 bytes
+        while len < magic.len() {
+            let n = file.read(&mut magic[len..])?;
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+        Ok(Self::from_magic(&magic[..len]))
+    }
+
+    pub fn from_magic(magic: &[u8]) -> Option<Compression> {
+        match magic {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn decompress<R: Read + 'static>(&self, reader: R) -> Result<Box<dyn Read>> {
+        Ok(match self {
+            // Handles files made of several concatenated gzip streams too
+            Compression::Gzip => Box::new(MultiGzDecoder::new(BufReader::new(reader))),
+            Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+        })
+    }
+}
+
+/// Transparently decompresses a stream, if it starts with gzip or zstd magic bytes
+pub fn decompress_stream<R: BufRead + 'static>(mut reader: R) -> Result<Box<dyn BufRead>> {
+    Ok(match Compression::from_magic(reader.fill_buf()?) {
+        Some(compression) => Box::new(BufReader::new(compression.decompress(reader)?)),
+        None => Box::new(reader),
+    })
+}
+
+/// `roads.geojson.gz` is really `roads.geojson`
+pub fn strip_extension(path: &str) -> &str {
+    for ext in [".gz", ".gzip", ".zst", ".zstd"] {
+        let split = path.len().saturating_sub(ext.len());
+        if let (Some(stem), Some(suffix)) = (path.get(..split), path.get(split..)) {
+            if suffix.eq_ignore_ascii_case(ext) {
+                return stem;
+            }
+        }
+    }
+    path
+}
@@ -0,0 +1,154 @@
+use std::io::Read;
+
+use anyhow::{bail, Context, Result};
+use csv::StringRecord;
+use geo_types::{Geometry, LineString};
+use serde_json::Value;
+use wkt::TryFromWkt;
+
+use super::FeatureIter;
+
+/// Which columns of a CSV file describe the geometry
+pub enum CsvGeometry {
+    /// One column with WKT, like `LINESTRING(-0.1 51.5, -0.2 51.6)`
+    Wkt(String),
+    /// Four columns forming a straight line from an origin to a destination
+    OriginDestination {
+        origin_lon: String,
+        origin_lat: String,
+        destination_lon: String,
+        destination_lat: String,
+    },
+}
+
+/// Reads a comma-separated file, or tab-separated if the path ends in `.tsv`. All columns not used
+/// for geometry become properties, parsed as numbers when possible. If `geometry` isn't specified,
+/// a `wkt` or `geometry` column is used.
+pub fn read_csv(path: &str, geometry: Option<&CsvGeometry>) -> Result<FeatureIter> {
+    read_csv_from(fs_err::File::open(path)?, path, geometry)
+}
+
+/// Like `read_csv`, but from any reader. `path` is only used to detect TSV and describe errors.
+pub(crate) fn read_csv_from<R: Read + 'static>(
+    input: R,
+    path: &str,
+    geometry: Option<&CsvGeometry>,
+) -> Result<FeatureIter> {
+    let delimiter = if path.to_lowercase().ends_with(".tsv") {
+        b'\t'
+    } else {
+        b','
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(input);
+    let headers = reader.headers()?.clone();
+
+    let find = |name: &str| -> Result<usize> {
+        match headers.iter().position(|h| h == name) {
+            Some(idx) => Ok(idx),
+            None => bail!(
+                "{path} has no {name} column. Columns: {}",
+                headers.iter().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    };
+    let columns = match geometry {
+        Some(CsvGeometry::Wkt(column)) => GeometryColumns::Wkt(find(column)?),
+        Some(CsvGeometry::OriginDestination {
+            origin_lon,
+            origin_lat,
+            destination_lon,
+            destination_lat,
+        }) => GeometryColumns::OriginDestination([
+            find(origin_lon)?,
+            find(origin_lat)?,
+            find(destination_lon)?,
+            find(destination_lat)?,
+        ]),
+        None => {
+            let Some(idx) = headers
+                .iter()
+                .position(|h| h.eq_ignore_ascii_case("wkt") || h.eq_ignore_ascii_case("geometry"))
+            else {
+                bail!("{path} has no wkt or geometry column, so specify which columns to use");
+            };
+            GeometryColumns::Wkt(idx)
+        }
+    };
+
+    Ok(Box::new(reader.into_records().enumerate().map(
+        move |(row, record)| {
+            // The header is line 1
+            convert(record?, &headers, &columns).with_context(|| format!("line {}", row + 2))
+        },
+    )))
+}
+
+enum GeometryColumns {
+    Wkt(usize),
+    OriginDestination([usize; 4]),
+}
+
+impl GeometryColumns {
+    fn contains(&self, idx: usize) -> bool {
+        match self {
+            GeometryColumns::Wkt(x) => *x == idx,
+            GeometryColumns::OriginDestination(xs) => xs.contains(&idx),
+        }
+    }
+}
+
+fn convert(
+    record: StringRecord,
+    headers: &StringRecord,
+    columns: &GeometryColumns,
+) -> Result<geojson::Feature> {
+    let geometry = match columns {
+        GeometryColumns::Wkt(idx) => match Geometry::try_from_wkt_str(&record[*idx]) {
+            Ok(geometry) => geometry,
+            Err(err) => bail!("Bad WKT: {err}"),
+        },
+        GeometryColumns::OriginDestination(idxs) => {
+            let mut coords = [0.0; 4];
+            for (coord, idx) in coords.iter_mut().zip(idxs) {
+                *coord = record[*idx]
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Bad coordinate in {}", &headers[*idx]))?;
+            }
+            Geometry::LineString(LineString::from(vec![
+                (coords[0], coords[1]),
+                (coords[2], coords[3]),
+            ]))
+        }
+    };
+
+    let mut properties = geojson::JsonObject::new();
+    for (idx, (key, value)) in headers.iter().zip(record.iter()).enumerate() {
+        if !columns.contains(idx) && !value.is_empty() {
+            properties.insert(key.to_string(), parse_value(value));
+        }
+    }
+
+    Ok(geojson::Feature {
+        bbox: None,
+        geometry: Some(geojson::Geometry::from(&geometry)),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    })
+}
+
+/// CSV is untyped, so treat anything that looks like a number as one
+fn parse_value(value: &str) -> Value {
+    if let Ok(x) = value.parse::<i64>() {
+        return x.into();
+    }
+    if let Ok(x) = value.parse::<f64>() {
+        if x.is_finite() {
+            return x.into();
+        }
+    }
+    value.into()
+}
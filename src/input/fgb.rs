@@ -0,0 +1,123 @@
+use std::io::{Read, Seek};
+
+use anyhow::Result;
+use fallible_streaming_iterator::FallibleStreamingIterator;
+use flatgeobuf::{reader_trait::Seekable, FeatureIter, FgbReader};
+use geozero::{FeatureProperties, ToGeo};
+
+use super::properties::JsonProperties;
+
+/// Streams features from a FlatGeobuf file, without any GeoJSON parsing. If `bbox` is specified
+/// as WGS84 `[min_lon, min_lat, max_lon, max_lat]`, the file's packed Hilbert R-tree is used to
+/// seek directly to the features intersecting it, skipping everything else. Files without an
+/// index are read in full.
+pub struct FlatGeobufReader<R> {
+    iter: FeatureIter<R, Seekable>,
+}
+
+impl<R: Read + Seek> FlatGeobufReader<R> {
+    pub fn new(reader: R, bbox: Option<[f64; 4]>) -> Result<Self> {
+        let fgb = FgbReader::open(reader)?;
+        let header = fgb.header();
+        // Files without an index, or without features, can only be read in full
+        let indexed = header.index_node_size() > 0 && header.features_count() > 0;
+        let iter = match bbox {
+            Some([min_x, min_y, max_x, max_y]) if indexed => {
+                fgb.select_bbox(min_x, min_y, max_x, max_y)?
+            }
+            _ => fgb.select_all()?,
+        };
+        Ok(Self { iter })
+    }
+}
+
+impl<R: Read + Seek> Iterator for FlatGeobufReader<R> {
+    type Item = Result<geojson::Feature>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let feature = match self.iter.next() {
+            Ok(Some(feature)) => feature,
+            Ok(None) => return None,
+            Err(err) => return Some(Err(err.into())),
+        };
+        Some(convert(feature))
+    }
+}
+
+fn convert(feature: &flatgeobuf::FgbFeature) -> Result<geojson::Feature> {
+    let geometry = feature.to_geo()?;
+    let mut properties = JsonProperties::default();
+    feature.process_properties(&mut properties)?;
+    Ok(geojson::Feature {
+        bbox: None,
+        geometry: Some(geojson::Geometry::from(&geometry)),
+        id: None,
+        properties: Some(properties.0),
+        foreign_members: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use flatgeobuf::{ColumnType, FgbWriter, GeometryType};
+    use geozero::{ColumnValue, PropertyProcessor};
+
+    use super::*;
+    use crate::input::{read_path, InputOptions};
+
+    /// A FlatGeobuf file with a short line near each of `lons`, named by its index
+    fn write_lines(lons: &[f64]) -> Vec<u8> {
+        let mut fgb = FgbWriter::create("lines", GeometryType::LineString).unwrap();
+        fgb.add_column("name", ColumnType::String, |_, _| {});
+        for (idx, lon) in lons.iter().enumerate() {
+            let line = geo_types::LineString::from(vec![(*lon, 51.0), (lon + 0.1, 51.1)]);
+            fgb.add_feature_geom(geo_types::Geometry::LineString(line), |feature| {
+                feature
+                    .property(0, "name", &ColumnValue::String(&idx.to_string()))
+                    .unwrap();
+            })
+            .unwrap();
+        }
+        let mut bytes = Vec::new();
+        fgb.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn names(features: impl Iterator<Item = Result<geojson::Feature>>) -> Vec<String> {
+        let mut names: Vec<String> = features
+            .map(|feature| {
+                let feature = feature.unwrap();
+                feature
+                    .property("name")
+                    .unwrap()
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_bbox() {
+        let bytes = write_lines(&[-3.0, -1.0, 1.0, 3.0]);
+        let read = |bbox| names(FlatGeobufReader::new(Cursor::new(bytes.clone()), bbox).unwrap());
+        assert_eq!(read(None), ["0", "1", "2", "3"]);
+        assert_eq!(read(Some([-1.5, 50.0, 1.5, 52.0])), ["1", "2"]);
+        assert_eq!(read(Some([10.0, 50.0, 11.0, 52.0])), Vec::<String>::new());
+
+        // read_path passes the bbox on
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lines.fgb");
+        fs_err::write(&path, &bytes).unwrap();
+        let input_options = InputOptions {
+            bbox: Some([-1.5, 50.0, 1.5, 52.0]),
+            ..Default::default()
+        };
+        let features = read_path(path.to_str().unwrap(), &input_options).unwrap();
+        assert_eq!(names(features), ["1", "2"]);
+    }
+}
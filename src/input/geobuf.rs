@@ -0,0 +1,300 @@
+use anyhow::{bail, Result};
+use geojson::{feature::Id, Feature, Geometry, JsonObject, Value as GeoValue};
+use serde_json::Value;
+
+use super::protobuf::{zigzag, Message};
+use super::FeatureIter;
+
+/// Reads a [geobuf](https://github.com/mapbox/geobuf) file, containing a FeatureCollection, one
+/// Feature, or one Geometry.
+pub fn read_geobuf(path: &str) -> Result<FeatureIter> {
+    let bytes = fs_err::read(path)?;
+
+    let mut header = Header {
+        keys: Vec::new(),
+        dimensions: 2,
+        factor: 1e6,
+    };
+    let mut features = Vec::new();
+    for field in Message::new(&bytes) {
+        match field? {
+            (1, value) => header.keys.push(value.as_str()?.to_string()),
+            (2, value) => {
+                header.dimensions = value.as_u64()? as usize;
+                if header.dimensions < 2 {
+                    bail!("{path} has {} dimensions", header.dimensions);
+                }
+            }
+            (3, value) => header.factor = 10.0_f64.powi(value.as_u64()? as i32),
+            (4, value) => {
+                for field in Message::new(value.as_bytes()?) {
+                    if let (1, value) = field? {
+                        features.push(header.feature(value.as_bytes()?)?);
+                    }
+                }
+            }
+            (5, value) => features.push(header.feature(value.as_bytes()?)?),
+            (6, value) => features.push(Feature {
+                bbox: None,
+                geometry: header.geometry(value.as_bytes()?)?.map(Geometry::new),
+                id: None,
+                properties: None,
+                foreign_members: None,
+            }),
+            _ => {}
+        }
+    }
+    Ok(Box::new(features.into_iter().map(Ok)))
+}
+
+struct Header {
+    /// All property names, shared by every feature
+    keys: Vec<String>,
+    dimensions: usize,
+    /// Coordinates are stored as integers, multiplied by this
+    factor: f64,
+}
+
+impl Header {
+    fn feature(&self, bytes: &[u8]) -> Result<Feature> {
+        let mut geometry = None;
+        let mut id = None;
+        let mut values = Vec::new();
+        let mut property_indices = Vec::new();
+        for field in Message::new(bytes) {
+            match field? {
+                (1, value) => geometry = self.geometry(value.as_bytes()?)?,
+                (11, value) => id = Some(Id::String(value.as_str()?.to_string())),
+                (12, value) => id = Some(Id::Number(zigzag(value.as_u64()?).into())),
+                (13, value) => values.push(read_value(value.as_bytes()?)?),
+                (14, value) => value.read_packed_varints(&mut property_indices)?,
+                _ => {}
+            }
+        }
+
+        // Pairs of (index into keys, index into values)
+        let mut properties = JsonObject::new();
+        for pair in property_indices.chunks(2) {
+            let (Some(key), Some(value)) = (
+                self.keys.get(pair[0] as usize),
+                pair.get(1).and_then(|idx| values.get(*idx as usize)),
+            ) else {
+                bail!("Geobuf feature has a bad property index");
+            };
+            properties.insert(key.clone(), value.clone());
+        }
+
+        Ok(Feature {
+            bbox: None,
+            geometry: geometry.map(Geometry::new),
+            id,
+            properties: Some(properties),
+            foreign_members: None,
+        })
+    }
+
+    fn geometry(&self, bytes: &[u8]) -> Result<Option<GeoValue>> {
+        let mut geometry_type = 0;
+        let mut lengths = Vec::new();
+        let mut raw_coords = Vec::new();
+        let mut members = Vec::new();
+        for field in Message::new(bytes) {
+            match field? {
+                (1, value) => geometry_type = value.as_u64()?,
+                (2, value) => value.read_packed_varints(&mut lengths)?,
+                (3, value) => value.read_packed_varints(&mut raw_coords)?,
+                (4, value) => {
+                    if let Some(member) = self.geometry(value.as_bytes()?)? {
+                        members.push(Geometry::new(member));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let coords: Vec<i64> = raw_coords.into_iter().map(zigzag).collect();
+        let lengths: Vec<usize> = lengths.into_iter().map(|x| x as usize).collect();
+        let dim = self.dimensions;
+
+        Ok(Some(match geometry_type {
+            0 => GeoValue::Point(self.point(&coords)),
+            1 => GeoValue::MultiPoint(self.line(&coords, false)),
+            2 => GeoValue::LineString(self.line(&coords, false)),
+            3 => GeoValue::MultiLineString(self.lines(&coords, &lengths, false)),
+            4 => GeoValue::Polygon(self.lines(&coords, &lengths, true)),
+            5 => {
+                if lengths.is_empty() {
+                    return Ok(Some(GeoValue::MultiPolygon(vec![vec![
+                        self.line(&coords, true)
+                    ]])));
+                }
+                // [number of polygons, then for each: number of rings, then each ring's length]
+                let mut polygons = Vec::new();
+                let mut lengths = lengths.into_iter().skip(1);
+                let mut start = 0_usize;
+                while let Some(num_rings) = lengths.next() {
+                    let mut polygon = Vec::new();
+                    for len in lengths.by_ref().take(num_rings) {
+                        let end = start
+                            .saturating_add(len.saturating_mul(dim))
+                            .min(coords.len());
+                        polygon.push(self.line(&coords[start..end], true));
+                        start = end;
+                    }
+                    polygons.push(polygon);
+                }
+                GeoValue::MultiPolygon(polygons)
+            }
+            6 => GeoValue::GeometryCollection(members),
+            x => bail!("Unknown geobuf geometry type {x}"),
+        }))
+    }
+
+    fn point(&self, coords: &[i64]) -> Vec<f64> {
+        coords.iter().map(|x| *x as f64 / self.factor).collect()
+    }
+
+    /// Each line's coordinates are delta-encoded. Rings leave out the closing point.
+    fn line(&self, coords: &[i64], closed: bool) -> Vec<Vec<f64>> {
+        let mut current = vec![0; self.dimensions];
+        let mut points: Vec<Vec<f64>> = coords
+            .chunks_exact(self.dimensions)
+            .map(|delta| {
+                for (x, dx) in current.iter_mut().zip(delta) {
+                    *x += dx;
+                }
+                self.point(&current)
+            })
+            .collect();
+        if closed && !points.is_empty() {
+            points.push(points[0].clone());
+        }
+        points
+    }
+
+    fn lines(&self, coords: &[i64], lengths: &[usize], closed: bool) -> Vec<Vec<Vec<f64>>> {
+        if lengths.is_empty() {
+            return vec![self.line(coords, closed)];
+        }
+        let mut start = 0_usize;
+        lengths
+            .iter()
+            .map(|len| {
+                let end = start
+                    .saturating_add(len.saturating_mul(self.dimensions))
+                    .min(coords.len());
+                let line = self.line(&coords[start..end], closed);
+                start = end;
+                line
+            })
+            .collect()
+    }
+}
+
+fn read_value(bytes: &[u8]) -> Result<Value> {
+    let mut value = Value::Null;
+    for field in Message::new(bytes) {
+        value = match field? {
+            (1, x) => x.as_str()?.into(),
+            (2, x) => x.as_f64()?.into(),
+            (3, x) => x.as_u64()?.into(),
+            (4, x) => (-(x.as_u64()? as i64)).into(),
+            (5, x) => (x.as_u64()? != 0).into(),
+            (6, x) => serde_json::from_str(x.as_str()?)?,
+            _ => continue,
+        };
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::protobuf::tests::{bytes_field, varint, varint_field};
+
+    fn header() -> Header {
+        Header {
+            keys: vec!["name".to_string(), "count".to_string()],
+            dimensions: 2,
+            factor: 1e6,
+        }
+    }
+
+    /// Packs varints into one length-delimited field
+    fn packed_field(number: u32, values: &[u64]) -> Vec<u8> {
+        bytes_field(
+            number,
+            &values.iter().flat_map(|x| varint(*x)).collect::<Vec<_>>(),
+        )
+    }
+
+    fn zigzag_encode(x: i64) -> u64 {
+        ((x << 1) ^ (x >> 63)) as u64
+    }
+
+    #[test]
+    fn test_feature() {
+        // A LineString from (1, 2) to (1.5, 1), delta-encoded
+        let coords: Vec<u64> = [1_000_000, 2_000_000, 500_000, -1_000_000]
+            .into_iter()
+            .map(zigzag_encode)
+            .collect();
+        let geometry = [varint_field(1, 2), packed_field(3, &coords)].concat();
+        let feature = [
+            bytes_field(1, &geometry),
+            varint_field(12, zigzag_encode(-7)),
+            bytes_field(13, &bytes_field(1, b"Main St")),
+            bytes_field(13, &varint_field(3, 42)),
+            packed_field(14, &[0, 0, 1, 1]),
+        ]
+        .concat();
+
+        let feature = header().feature(&feature).unwrap();
+        assert_eq!(
+            feature.geometry.unwrap().value,
+            GeoValue::LineString(vec![vec![1.0, 2.0], vec![1.5, 1.0]])
+        );
+        assert_eq!(feature.id, Some(Id::Number((-7).into())));
+        let properties = feature.properties.unwrap();
+        assert_eq!(properties["name"], "Main St");
+        assert_eq!(properties["count"], 42);
+    }
+
+    #[test]
+    fn test_bad_property_index() {
+        let feature = [
+            bytes_field(13, &varint_field(3, 1)),
+            packed_field(14, &[5, 0]),
+        ]
+        .concat();
+        assert_eq!(
+            header().feature(&feature).unwrap_err().to_string(),
+            "Geobuf feature has a bad property index"
+        );
+    }
+
+    #[test]
+    fn test_huge_ring_lengths() {
+        // Lengths past the coordinates just use up what's there
+        let geometry = [
+            varint_field(1, 3),
+            packed_field(2, &[u64::MAX, 1]),
+            packed_field(3, &[2, 2, 2, 2]),
+        ]
+        .concat();
+        let geometry = header().geometry(&geometry).unwrap().unwrap();
+        assert_eq!(
+            geometry,
+            GeoValue::MultiLineString(vec![vec![vec![1e-6, 1e-6], vec![2e-6, 2e-6]], vec![]])
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        let geometry = [varint_field(1, 2), packed_field(3, &[2, 2])].concat();
+        let truncated = &bytes_field(1, &geometry)[..geometry.len()];
+        assert_eq!(
+            header().feature(truncated).unwrap_err().to_string(),
+            "Protobuf message is truncated"
+        );
+    }
+}
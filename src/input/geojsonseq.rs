@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::str::FromStr;
+
+use anyhow::Result;
+use geojson::{Feature, GeoJson};
+use rayon::prelude::*;
+
+/// How many lines to read before parsing them all in parallel
+const BATCH_SIZE: usize = 10_000;
+
+/// Reads newline-delimited GeoJSON, including RFC 8142 sequences with record separators. Each
+/// record can be a Feature, a bare Geometry, or even a whole FeatureCollection. Batches of lines
+/// are parsed in parallel, but only one batch is held in memory at a time.
+pub struct GeoJsonSeqReader<R> {
+    reader: R,
+    parsed: VecDeque<Result<Feature>>,
+    done: bool,
+}
+
+impl<R: BufRead> GeoJsonSeqReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            parsed: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    fn read_batch(&mut self) {
+        let mut lines = Vec::new();
+        let mut io_error = None;
+        while lines.len() < BATCH_SIZE {
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => {
+                    self.done = true;
+                    break;
+                }
+                Ok(_) => {
+                    // The RFC 8142 record separator
+                    let line = line.trim_start_matches('\x1e').trim();
+                    if !line.is_empty() {
+                        lines.push(line.to_string());
+                    }
+                }
+                Err(err) => {
+                    self.done = true;
+                    io_error = Some(Err(err.into()));
+                    break;
+                }
+            }
+        }
+
+        let batch: Vec<Vec<Result<Feature>>> = lines.par_iter().map(|line| parse(line)).collect();
+        self.parsed.extend(batch.into_iter().flatten());
+        self.parsed.extend(io_error);
+    }
+}
+
+impl<R: BufRead> Iterator for GeoJsonSeqReader<R> {
+    type Item = Result<Feature>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.parsed.is_empty() && !self.done {
+            self.read_batch();
+        }
+        self.parsed.pop_front()
+    }
+}
+
+fn parse(line: &str) -> Vec<Result<Feature>> {
+    match GeoJson::from_str(line) {
+        Ok(GeoJson::Feature(f)) => vec![Ok(f)],
+        Ok(GeoJson::Geometry(geometry)) => vec![Ok(Feature::from(geometry))],
+        Ok(GeoJson::FeatureCollection(fc)) => fc.features.into_iter().map(Ok).collect(),
+        Err(err) => vec![Err(err.into())],
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::{bail, Result};
+use fs_err::File;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde_json::Value;
+
+use super::arrow::RecordBatchFeatures;
+use super::FeatureIter;
+
+/// Reads a GeoParquet file. The geometry column must be WKB-encoded and in WGS84.
+pub fn read_geoparquet(path: &str) -> Result<FeatureIter> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?.into_parts().0)?;
+
+    let geo_metadata = builder
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .and_then(|kv| kv.iter().find(|kv| kv.key == "geo"))
+        .and_then(|kv| kv.value.as_ref());
+    let Some(geo_metadata) = geo_metadata else {
+        bail!("{path} is missing GeoParquet metadata");
+    };
+    let geo_metadata: Value = serde_json::from_str(geo_metadata)?;
+
+    let Some(geometry_column) = geo_metadata["primary_column"].as_str() else {
+        bail!("{path} doesn't specify a primary geometry column");
+    };
+    let column_metadata = &geo_metadata["columns"][geometry_column];
+    if column_metadata["encoding"] != "WKB" {
+        bail!(
+            "{path} uses {} geometry encoding, but only WKB is supported",
+            column_metadata["encoding"]
+        );
+    }
+    check_crs(&column_metadata["crs"])?;
+
+    let batches = builder
+        .build()?
+        .map(|batch| batch.map_err(anyhow::Error::from));
+    Ok(Box::new(RecordBatchFeatures::new(
+        batches,
+        geometry_column.to_string(),
+    )))
+}
+
+/// A missing or null CRS means WGS84 longitude/latitude. There's no reprojection support, so
+/// anything else is an error.
+fn check_crs(crs: &Value) -> Result<()> {
+    if crs.is_null() {
+        return Ok(());
+    }
+    let id = &crs["id"];
+    let code = match &id["code"] {
+        Value::Number(x) => x.to_string(),
+        Value::String(x) => x.clone(),
+        _ => String::new(),
+    };
+    match (id["authority"].as_str(), code.as_str()) {
+        (Some("EPSG"), "4326") | (Some("OGC"), "CRS84") => Ok(()),
+        _ => bail!("GeoParquet input must be in WGS84; reproject it first"),
+    }
+}
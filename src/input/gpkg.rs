@@ -0,0 +1,138 @@
+use anyhow::{bail, Result};
+use geozero::{wkb::Wkb, ToGeo};
+use rusqlite::{types::ValueRef, Connection, OpenFlags};
+use serde_json::Value;
+
+use super::reproject::Reprojector;
+use super::FeatureIter;
+
+/// Reads one layer (feature table) from a GeoPackage. If `layer` isn't specified, the file must
+/// only have one. Geometry in other coordinate systems is reprojected to WGS84.
+pub fn read_geopackage(path: &str, layer: Option<&str>) -> Result<FeatureIter> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let mut layers: Vec<(String, String, i64)> = conn
+        .prepare("SELECT table_name, column_name, srs_id FROM gpkg_geometry_columns")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let all_names = || {
+        layers
+            .iter()
+            .map(|(name, _, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let (table, geometry_column, srs_id) = match layer {
+        Some(layer) => match layers.iter().position(|(name, _, _)| name == layer) {
+            Some(idx) => layers.remove(idx),
+            None => bail!("{path} has no layer {layer}; choose from {}", all_names()),
+        },
+        None => {
+            if layers.len() != 1 {
+                bail!("{path} has multiple layers; choose from {}", all_names());
+            }
+            layers.remove(0)
+        }
+    };
+
+    let reprojector = if srs_id == 4326 {
+        None
+    } else {
+        let wkt: String = conn.query_row(
+            "SELECT definition FROM gpkg_spatial_ref_sys WHERE srs_id = ?",
+            [srs_id],
+            |row| row.get(0),
+        )?;
+        Reprojector::from_wkt(&wkt)?
+    };
+
+    // Use the primary key as the feature ID, rather than a property
+    let mut primary_key = None;
+    let mut columns = Vec::new();
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info(\"{table}\")"))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let name: String = row.get(1)?;
+        let is_pk: i64 = row.get(5)?;
+        if is_pk != 0 && primary_key.is_none() {
+            primary_key = Some(name);
+        } else if name != geometry_column {
+            columns.push(name);
+        }
+    }
+    drop(rows);
+    drop(stmt);
+
+    let select: Vec<String> = primary_key
+        .iter()
+        .chain(std::iter::once(&geometry_column))
+        .chain(columns.iter())
+        .map(|c| format!("\"{c}\""))
+        .collect();
+    let offset = if primary_key.is_some() { 1 } else { 0 };
+
+    // The statement borrows the connection, so read everything upfront
+    let mut features = Vec::new();
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM \"{table}\"", select.join(", ")))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let ValueRef::Blob(blob) = row.get_ref(offset)? else {
+            // No geometry
+            continue;
+        };
+        let mut geometry = Wkb(gpkg_blob_to_wkb(blob)?).to_geo()?;
+        if let Some(ref reprojector) = reprojector {
+            reprojector.apply(&mut geometry)?;
+        }
+
+        let mut properties = geojson::JsonObject::new();
+        for (idx, name) in columns.iter().enumerate() {
+            let value = match row.get_ref(offset + 1 + idx)? {
+                ValueRef::Null | ValueRef::Blob(_) => continue,
+                ValueRef::Integer(x) => Value::from(x),
+                ValueRef::Real(x) => Value::from(x),
+                ValueRef::Text(x) => Value::from(String::from_utf8_lossy(x)),
+            };
+            properties.insert(name.clone(), value);
+        }
+
+        let id = if primary_key.is_some() {
+            match row.get_ref(0)? {
+                ValueRef::Integer(x) => Some(geojson::feature::Id::Number(x.into())),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        features.push(Ok(geojson::Feature {
+            bbox: None,
+            geometry: Some(geojson::Geometry::from(&geometry)),
+            id,
+            properties: Some(properties),
+            foreign_members: None,
+        }));
+    }
+
+    Ok(Box::new(features.into_iter()))
+}
+
+/// Strips the GeoPackage header from a geometry blob, leaving standard WKB
+fn gpkg_blob_to_wkb(blob: &[u8]) -> Result<&[u8]> {
+    if blob.len() < 8 || &blob[0..2] != b"GP" {
+        bail!("Invalid GeoPackage geometry blob");
+    }
+    let flags = blob[3];
+    let envelope_bytes = match (flags >> 1) & 0b111 {
+        0 => 0,
+        1 => 32,
+        2 | 3 => 48,
+        4 => 64,
+        x => bail!("Invalid GeoPackage envelope type {x}"),
+    };
+    let start = 8 + envelope_bytes;
+    if blob.len() < start {
+        bail!("Truncated GeoPackage geometry blob");
+    }
+    Ok(&blob[start..])
+}
@@ -0,0 +1,80 @@
+use std::io::BufReader;
+
+use anyhow::Result;
+use geo_types::{Geometry, LineString};
+use gpx::Waypoint;
+use serde_json::Value;
+
+use super::FeatureIter;
+
+/// Reads every track segment and route from a GPX file as a LineString. Properties come from the
+/// track or route (`name`, `description`, `type`, etc), plus the time of the first and last points
+/// when they're recorded.
+pub fn read_gpx(path: &str) -> Result<FeatureIter> {
+    let gpx = gpx::read(BufReader::new(fs_err::File::open(path)?))?;
+
+    let mut features = Vec::new();
+    for (track_idx, track) in gpx.tracks.into_iter().enumerate() {
+        let mut metadata = geojson::JsonObject::new();
+        metadata.insert("track".to_string(), track_idx.into());
+        insert_some(&mut metadata, "name", track.name);
+        insert_some(&mut metadata, "description", track.description);
+        insert_some(&mut metadata, "comment", track.comment);
+        insert_some(&mut metadata, "source", track.source);
+        insert_some(&mut metadata, "type", track.type_);
+        insert_some(&mut metadata, "number", track.number);
+
+        for (segment_idx, segment) in track.segments.into_iter().enumerate() {
+            let mut properties = metadata.clone();
+            properties.insert("segment".to_string(), segment_idx.into());
+            if let Some(feature) = to_feature(&segment.points, properties)? {
+                features.push(feature);
+            }
+        }
+    }
+    for (route_idx, route) in gpx.routes.into_iter().enumerate() {
+        let mut properties = geojson::JsonObject::new();
+        properties.insert("route".to_string(), route_idx.into());
+        insert_some(&mut properties, "name", route.name);
+        insert_some(&mut properties, "description", route.description);
+        insert_some(&mut properties, "comment", route.comment);
+        insert_some(&mut properties, "source", route.source);
+        insert_some(&mut properties, "type", route.type_);
+        insert_some(&mut properties, "number", route.number);
+        if let Some(feature) = to_feature(&route.points, properties)? {
+            features.push(feature);
+        }
+    }
+
+    Ok(Box::new(features.into_iter().map(Ok)))
+}
+
+fn to_feature(
+    points: &[Waypoint],
+    mut properties: geojson::JsonObject,
+) -> Result<Option<geojson::Feature>> {
+    if points.len() < 2 {
+        return Ok(None);
+    }
+    if let Some(time) = points.first().and_then(|pt| pt.time) {
+        properties.insert("start_time".to_string(), time.format()?.into());
+    }
+    if let Some(time) = points.last().and_then(|pt| pt.time) {
+        properties.insert("end_time".to_string(), time.format()?.into());
+    }
+
+    let geometry = Geometry::LineString(LineString::from_iter(points.iter().map(|pt| pt.point())));
+    Ok(Some(geojson::Feature {
+        bbox: None,
+        geometry: Some(geojson::Geometry::from(&geometry)),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    }))
+}
+
+fn insert_some<T: Into<Value>>(properties: &mut geojson::JsonObject, key: &str, value: Option<T>) {
+    if let Some(value) = value {
+        properties.insert(key.to_string(), value.into());
+    }
+}
@@ -0,0 +1,70 @@
+use std::io::{BufReader, Write};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use super::{compression, read_path, read_stream, read_via_temp_file};
+use super::{FeatureIter, Format, InputOptions};
+
+/// Reads input from an HTTP(S) URL, detecting the format from the end of the URL's path. If
+/// `InputOptions::cache_dir` is set, the file is downloaded there first and reused by later runs.
+/// Otherwise, formats that can be streamed are read while downloading.
+pub fn read_url(url: &str, input_options: &InputOptions) -> Result<FeatureIter> {
+    let file_name = file_name_from_url(url);
+
+    if let Some(ref cache_dir) = input_options.cache_dir {
+        let path = cache_dir.join(format!("{}-{file_name}", sanitize(url)));
+        if path.exists() {
+            log::info!("Using cached {}", path.display());
+        } else {
+            fs_err::create_dir_all(cache_dir)?;
+            download(url, &path)?;
+        }
+        let Some(path) = path.to_str() else {
+            bail!("Cache path {} isn't valid UTF-8", path.display());
+        };
+        return read_path(path, input_options);
+    }
+
+    let reader = compression::decompress_stream(BufReader::new(
+        ureq::get(url).call()?.into_body().into_reader(),
+    ))?;
+    let inner_name = compression::strip_extension(file_name);
+    let format = Format::from_path(inner_name);
+    if format.is_streamable() {
+        return read_stream(reader, url, format, input_options);
+    }
+    if let Format::Shapefile = format {
+        bail!("Shapefiles need their .dbf and .prj files too, so download them in a .zip");
+    }
+    read_via_temp_file(reader, inner_name, input_options)
+}
+
+/// Downloads to a partial file first, so an interrupted download isn't mistaken for a cached one
+fn download(url: &str, path: &Path) -> Result<()> {
+    log::info!("Downloading {url} to {}", path.display());
+    let partial = path.with_extension("part");
+    let mut file = fs_err::File::create(&partial)?;
+    std::io::copy(
+        &mut ureq::get(url).call()?.into_body().into_reader(),
+        &mut file,
+    )?;
+    file.flush()?;
+    fs_err::rename(partial, path)?;
+    Ok(())
+}
+
+/// `https://example.com/data/roads.geojson?version=2` is `roads.geojson`
+fn file_name_from_url(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    match path.rsplit('/').next() {
+        Some(name) if !name.is_empty() => name,
+        _ => "download",
+    }
+}
+
+fn sanitize(url: &str) -> String {
+    url.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
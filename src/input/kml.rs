@@ -0,0 +1,122 @@
+use anyhow::Result;
+use geo_types::{Geometry, GeometryCollection, MultiLineString};
+use kml::types::{Element, Placemark};
+use kml::{Kml, KmlReader};
+use serde_json::Value;
+
+use super::FeatureIter;
+
+/// Reads every Placemark from a KML file, or a zipped `.kmz`. Properties come from the name,
+/// description, and any `ExtendedData`.
+pub fn read_kml(path: &str) -> Result<FeatureIter> {
+    let kml: Kml<f64> = if path.to_lowercase().ends_with(".kmz") {
+        KmlReader::from_kmz_path(path)?.read()?
+    } else {
+        KmlReader::from_path(path)?.read()?
+    };
+
+    let mut placemarks = Vec::new();
+    collect_placemarks(kml, &mut placemarks);
+    Ok(Box::new(
+        placemarks
+            .into_iter()
+            .filter_map(|placemark| convert(placemark).transpose()),
+    ))
+}
+
+/// Placemarks can be nested in any number of Documents and Folders
+fn collect_placemarks(kml: Kml<f64>, placemarks: &mut Vec<Placemark<f64>>) {
+    match kml {
+        Kml::KmlDocument(doc) => {
+            for element in doc.elements {
+                collect_placemarks(element, placemarks);
+            }
+        }
+        Kml::Document { elements, .. } => {
+            for element in elements {
+                collect_placemarks(element, placemarks);
+            }
+        }
+        Kml::Folder(folder) => {
+            for element in folder.elements {
+                collect_placemarks(element, placemarks);
+            }
+        }
+        Kml::Placemark(placemark) => placemarks.push(placemark),
+        _ => {}
+    }
+}
+
+fn convert(placemark: Placemark<f64>) -> Result<Option<geojson::Feature>> {
+    let Some(geometry) = placemark.geometry else {
+        return Ok(None);
+    };
+    let geometry = match Geometry::try_from(geometry)? {
+        // Only LineStrings are tiled from a GeometryCollection, so turn one that only has them into
+        // a MultiLineString
+        Geometry::GeometryCollection(GeometryCollection(members))
+            if members.iter().all(|g| matches!(g, Geometry::LineString(_))) =>
+        {
+            Geometry::MultiLineString(MultiLineString::new(
+                members
+                    .into_iter()
+                    .filter_map(|g| match g {
+                        Geometry::LineString(line_string) => Some(line_string),
+                        _ => None,
+                    })
+                    .collect(),
+            ))
+        }
+        geometry => geometry,
+    };
+
+    let mut properties = geojson::JsonObject::new();
+    if let Some(name) = placemark.name {
+        properties.insert("name".to_string(), name.into());
+    }
+    if let Some(description) = placemark.description {
+        properties.insert("description".to_string(), description.into());
+    }
+    for child in &placemark.children {
+        if child.name == "ExtendedData" {
+            read_extended_data(child, &mut properties);
+        }
+    }
+
+    Ok(Some(geojson::Feature {
+        bbox: None,
+        geometry: Some(geojson::Geometry::from(&geometry)),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    }))
+}
+
+/// Handles both `<Data name="x"><value>...</value></Data>` and
+/// `<SchemaData><SimpleData name="x">...</SimpleData></SchemaData>`
+fn read_extended_data(element: &Element, properties: &mut geojson::JsonObject) {
+    for child in &element.children {
+        match child.name.as_str() {
+            "Data" => {
+                let value = child
+                    .children
+                    .iter()
+                    .find(|x| x.name == "value")
+                    .and_then(|x| x.content.clone());
+                if let (Some(name), Some(value)) = (child.attrs.get("name"), value) {
+                    properties.insert(name.clone(), Value::String(value));
+                }
+            }
+            "SchemaData" => {
+                for simple in &child.children {
+                    if let (Some(name), Some(value)) =
+                        (simple.attrs.get("name"), simple.content.clone())
+                    {
+                        properties.insert(name.clone(), Value::String(value));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use anyhow::{bail, Result};
+use rusqlite::{Connection, OpenFlags};
+
+use super::vector_tile::TileDecoder;
+use super::FeatureIter;
+
+/// Decodes every vector tile at the highest zoom of an MBTiles file back into features, so they
+/// can be tiled again with different options. If `layer` is set, only that layer is read.
+/// Features clipped at tile boundaries come back as separate pieces.
+pub fn read_mbtiles(path: &str, layer: Option<&str>) -> Result<FeatureIter> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let max_zoom: Option<u8> =
+        conn.query_row("SELECT MAX(zoom_level) FROM tiles", [], |row| row.get(0))?;
+    let Some(z) = max_zoom else {
+        bail!("{path} has no tiles");
+    };
+    log::info!("Decoding tiles from {path} at zoom {z}");
+
+    let mut decoder = TileDecoder::new(layer.map(|x| x.to_string()));
+    let mut stmt =
+        conn.prepare("SELECT tile_column, tile_row, tile_data FROM tiles WHERE zoom_level = ?1")?;
+    let mut rows = stmt.query([z])?;
+    while let Some(row) = rows.next()? {
+        let x: u32 = row.get(0)?;
+        let tms_y: u32 = row.get(1)?;
+        let data: Vec<u8> = row.get(2)?;
+        // MBTiles rows count from the bottom
+        let Some(y) = ((1 << z) - 1_u32).checked_sub(tms_y) else {
+            bail!("{path} has a tile with an invalid row {tms_y} at zoom {z}");
+        };
+        decoder.decode(&data, z, x as u64, y as u64)?;
+    }
+    Ok(Box::new(decoder.features.into_iter().map(Ok)))
+}
@@ -0,0 +1,306 @@
+//! Readers for the input formats besides plain GeoJSON. Each one produces WGS84
+//! `geojson::Feature`s, which feed into the same pipeline as `geojson_to_pmtiles_writer`.
+
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use fs_err::File;
+use geojson::FeatureReader;
+use tempfile::TempPath;
+
+mod archive;
+#[cfg(any(feature = "arrow-ipc", feature = "geoparquet"))]
+mod arrow;
+#[cfg(feature = "arrow-ipc")]
+mod arrow_ipc;
+mod compression;
+mod delimited;
+mod fgb;
+mod geobuf;
+mod geojsonseq;
+#[cfg(feature = "geoparquet")]
+mod geoparquet;
+#[cfg(feature = "sqlite")]
+mod gpkg;
+mod gpx;
+#[cfg(feature = "http")]
+mod http;
+mod kml;
+#[cfg(feature = "sqlite")]
+mod mbtiles;
+mod osm;
+mod pmtiles;
+#[cfg(feature = "postgis")]
+mod postgis;
+mod properties;
+mod protobuf;
+mod reproject;
+mod shp;
+mod stdin;
+mod topojson;
+mod vector_tile;
+
+pub use self::archive::read_zip;
+#[cfg(feature = "arrow-ipc")]
+pub use self::arrow_ipc::read_arrow_ipc;
+use self::compression::Compression;
+use self::delimited::read_csv_from;
+pub use self::delimited::{read_csv, CsvGeometry};
+pub use self::fgb::FlatGeobufReader;
+pub use self::geobuf::read_geobuf;
+pub use self::geojsonseq::GeoJsonSeqReader;
+#[cfg(feature = "geoparquet")]
+pub use self::geoparquet::read_geoparquet;
+#[cfg(feature = "sqlite")]
+pub use self::gpkg::read_geopackage;
+pub use self::gpx::read_gpx;
+#[cfg(feature = "http")]
+pub use self::http::read_url;
+pub use self::kml::read_kml;
+#[cfg(feature = "sqlite")]
+pub use self::mbtiles::read_mbtiles;
+pub use self::osm::{read_osm_pbf, TagFilter};
+pub use self::pmtiles::read_pmtiles;
+#[cfg(feature = "postgis")]
+pub use self::postgis::read_postgis;
+pub(crate) use self::protobuf::Message;
+pub use self::shp::read_shapefile;
+pub use self::stdin::read_stdin;
+pub use self::topojson::read_topojson;
+pub(crate) use self::vector_tile::TileDecoder;
+
+/// A stream of WGS84 features from any input format
+pub type FeatureIter = Box<dyn Iterator<Item = Result<geojson::Feature>>>;
+
+/// Settings that only apply to some input formats
+#[derive(Default)]
+pub struct InputOptions {
+    /// For GeoPackages, MBTiles, PMTiles, or TopoJSON with multiple layers (or objects), which one to read
+    pub layer: Option<String>,
+    /// For OSM input, which ways to keep. If empty, keep all of them.
+    pub osm_tags: Vec<TagFilter>,
+    /// For CSV and TSV input, which columns hold the geometry. If unset, a `wkt` or `geometry`
+    /// column is used.
+    pub csv_geometry: Option<CsvGeometry>,
+    /// For zip input, which file inside to read. If unset, the archive must contain only one file
+    /// in a supported format.
+    pub archive_member: Option<String>,
+    /// For URL input, a directory to keep downloads in, so later runs don't download again
+    pub cache_dir: Option<PathBuf>,
+    /// For PostGIS input, the query to run
+    pub sql: Option<String>,
+    /// For FlatGeobuf input, only read features intersecting this WGS84
+    /// `[min_lon, min_lat, max_lon, max_lat]`, using the file's spatial index. Other formats are
+    /// read in full.
+    pub bbox: Option<[f64; 4]>,
+}
+
+/// Opens any supported input, detecting the format from the file extension. The path can also be
+/// an HTTP(S) URL, a PostGIS connection string like `postgres://user@host/db` with
+/// `InputOptions::sql`, or `-` to read stdin. Gzip or zstd
+/// compressed files are detected and decompressed on the fly, with any `.gz` or `.zst` extension
+/// ignored for detecting the format.
+pub fn read_path(path: &str, input_options: &InputOptions) -> Result<FeatureIter> {
+    if path == "-" {
+        return read_stdin(input_options);
+    }
+    if path.starts_with("http://") || path.starts_with("https://") {
+        #[cfg(feature = "http")]
+        return read_url(path, input_options);
+        #[cfg(not(feature = "http"))]
+        bail!("Reading from URLs needs the http feature");
+    }
+    if path.starts_with("postgres://") || path.starts_with("postgresql://") {
+        let Some(ref sql) = input_options.sql else {
+            bail!("Reading from PostGIS needs a query");
+        };
+        #[cfg(feature = "postgis")]
+        return read_postgis(path, sql);
+        #[cfg(not(feature = "postgis"))]
+        bail!("Reading from PostGIS needs the postgis feature, so can't run {sql}");
+    }
+    if let Some(compression) = Compression::detect(path)? {
+        return read_compressed(path, compression, input_options);
+    }
+
+    match Format::from_path(path) {
+        #[cfg(feature = "geoparquet")]
+        Format::GeoParquet => read_geoparquet(path),
+        #[cfg(not(feature = "geoparquet"))]
+        Format::GeoParquet => bail!("Reading GeoParquet needs the geoparquet feature"),
+        Format::OsmPbf => read_osm_pbf(path, &input_options.osm_tags),
+        Format::Gpx => read_gpx(path),
+        Format::TopoJson => read_topojson(path, input_options.layer.as_deref()),
+        Format::Kml => read_kml(path),
+        Format::Geobuf => read_geobuf(path),
+        Format::Shapefile => read_shapefile(path),
+        #[cfg(feature = "sqlite")]
+        Format::GeoPackage => read_geopackage(path, input_options.layer.as_deref()),
+        #[cfg(not(feature = "sqlite"))]
+        Format::GeoPackage => bail!("Reading GeoPackages needs the sqlite feature"),
+        #[cfg(feature = "sqlite")]
+        Format::MBTiles => read_mbtiles(path, input_options.layer.as_deref()),
+        #[cfg(not(feature = "sqlite"))]
+        Format::MBTiles => bail!("Reading MBTiles needs the sqlite feature"),
+        Format::PMTiles => read_pmtiles(path, input_options.layer.as_deref()),
+        Format::FlatGeobuf => Ok(Box::new(FlatGeobufReader::new(
+            BufReader::new(File::open(path)?),
+            input_options.bbox,
+        )?)),
+        Format::Zip => read_zip(path, input_options),
+        format => read_stream(File::open(path)?, path, format, input_options),
+    }
+}
+
+/// The formats that can be read from a stream. `path` is only used for error messages and
+/// details like the CSV delimiter.
+fn read_stream<R: Read + 'static>(
+    reader: R,
+    path: &str,
+    format: Format,
+    input_options: &InputOptions,
+) -> Result<FeatureIter> {
+    match format {
+        Format::Csv => read_csv_from(reader, path, input_options.csv_geometry.as_ref()),
+        Format::GeoJsonSeq => Ok(Box::new(GeoJsonSeqReader::new(BufReader::new(reader)))),
+        #[cfg(feature = "arrow-ipc")]
+        Format::ArrowIpc => read_arrow_ipc(reader),
+        #[cfg(not(feature = "arrow-ipc"))]
+        Format::ArrowIpc => bail!("Reading Arrow IPC needs the arrow-ipc feature"),
+        Format::GeoJson => Ok(Box::new(
+            FeatureReader::from_reader(BufReader::new(reader))
+                .features()
+                .map(|f| f.map_err(anyhow::Error::from)),
+        )),
+        _ => bail!("{path} can't be read from a stream"),
+    }
+}
+
+fn read_compressed(
+    path: &str,
+    compression: Compression,
+    input_options: &InputOptions,
+) -> Result<FeatureIter> {
+    let inner_path = compression::strip_extension(path);
+    let format = Format::from_path(inner_path);
+    let reader = compression.decompress(File::open(path)?)?;
+    if format.is_streamable() {
+        return read_stream(reader, inner_path, format, input_options);
+    }
+    if let Format::Shapefile = format {
+        bail!("Shapefiles need their .dbf and .prj files too, so compress them together in a .zip");
+    }
+    read_via_temp_file(reader, inner_path, input_options)
+}
+
+/// For formats that need a real file, copy the input to a temporary one. The file name is kept as
+/// a suffix, so the format is detected the same way.
+fn read_via_temp_file(
+    mut reader: impl Read,
+    inner_path: &str,
+    input_options: &InputOptions,
+) -> Result<FeatureIter> {
+    let file_name = Path::new(inner_path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let mut temp = tempfile::Builder::new().suffix(file_name).tempfile()?;
+    std::io::copy(&mut reader, &mut temp)?;
+    let temp_path = temp.into_temp_path();
+    let Some(temp_path_str) = temp_path.to_str() else {
+        bail!("Temporary file {} isn't valid UTF-8", temp_path.display());
+    };
+    let features = read_path(temp_path_str, input_options)?;
+    Ok(Box::new(WithTempFile {
+        features,
+        _temp_path: temp_path,
+    }))
+}
+
+/// Some readers keep the file open while iterating, so only delete it after they're done
+struct WithTempFile {
+    features: FeatureIter,
+    _temp_path: TempPath,
+}
+
+impl Iterator for WithTempFile {
+    type Item = Result<geojson::Feature>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.features.next()
+    }
+}
+
+enum Format {
+    GeoParquet,
+    OsmPbf,
+    Csv,
+    Gpx,
+    TopoJson,
+    Kml,
+    Geobuf,
+    Shapefile,
+    GeoPackage,
+    MBTiles,
+    PMTiles,
+    FlatGeobuf,
+    Zip,
+    ArrowIpc,
+    GeoJsonSeq,
+    GeoJson,
+}
+
+impl Format {
+    /// Anything unrecognized is treated as GeoJSON
+    fn from_path(path: &str) -> Self {
+        Self::detect(path).unwrap_or(Format::GeoJson)
+    }
+
+    fn detect(path: &str) -> Option<Self> {
+        let lowercase = path.to_lowercase();
+        let has_ext = |exts: &[&str]| exts.iter().any(|ext| lowercase.ends_with(ext));
+        Some(if has_ext(&[".parquet", ".geoparquet"]) {
+            Format::GeoParquet
+        } else if has_ext(&[".osm.pbf"]) {
+            Format::OsmPbf
+        } else if has_ext(&[".csv", ".tsv"]) {
+            Format::Csv
+        } else if has_ext(&[".gpx"]) {
+            Format::Gpx
+        } else if has_ext(&[".topojson"]) {
+            Format::TopoJson
+        } else if has_ext(&[".kml", ".kmz"]) {
+            Format::Kml
+        } else if has_ext(&[".pbf", ".geobuf"]) {
+            Format::Geobuf
+        } else if has_ext(&[".shp"]) {
+            Format::Shapefile
+        } else if has_ext(&[".gpkg"]) {
+            Format::GeoPackage
+        } else if has_ext(&[".mbtiles"]) {
+            Format::MBTiles
+        } else if has_ext(&[".pmtiles"]) {
+            Format::PMTiles
+        } else if has_ext(&[".fgb"]) {
+            Format::FlatGeobuf
+        } else if has_ext(&[".zip"]) {
+            Format::Zip
+        } else if has_ext(&[".arrow", ".arrows", ".feather", ".ipc"]) {
+            Format::ArrowIpc
+        } else if has_ext(&[".geojsonl", ".geojsons", ".geojsonseq", ".ndjson", ".jsonl"]) {
+            Format::GeoJsonSeq
+        } else if has_ext(&[".geojson", ".json"]) {
+            Format::GeoJson
+        } else {
+            return None;
+        })
+    }
+
+    fn is_streamable(&self) -> bool {
+        matches!(
+            self,
+            Format::Csv | Format::ArrowIpc | Format::GeoJsonSeq | Format::GeoJson
+        )
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use geojson::{feature::Id, Feature, Geometry, JsonObject, Value};
+use osmpbf::{Element, IndexedReader, Way};
+
+use super::FeatureIter;
+
+/// Matches OSM ways by tag. Parsed from `key`, `key=*`, or `key=value1,value2`.
+pub struct TagFilter {
+    key: String,
+    values: Option<Vec<String>>,
+}
+
+impl TagFilter {
+    pub fn parse(filter: &str) -> Self {
+        match filter.split_once('=') {
+            None | Some((_, "*")) => Self {
+                key: filter.trim_end_matches("=*").to_string(),
+                values: None,
+            },
+            Some((key, values)) => Self {
+                key: key.to_string(),
+                values: Some(values.split(',').map(|v| v.to_string()).collect()),
+            },
+        }
+    }
+
+    fn matches(&self, way: &Way) -> bool {
+        way.tags().any(|(k, v)| {
+            k == self.key
+                && self
+                    .values
+                    .as_ref()
+                    .map(|values| values.iter().any(|x| x == v))
+                    .unwrap_or(true)
+        })
+    }
+}
+
+/// Reads ways from an `.osm.pbf` file as LineStrings, with their tags as properties. Only ways
+/// matching any of the filters are kept; with no filters, every way is.
+pub fn read_osm_pbf(path: &str, filters: &[TagFilter]) -> Result<FeatureIter> {
+    let mut reader = IndexedReader::from_path(path)?;
+
+    // The reader first finds matching ways, then only the nodes they need
+    let mut ways: Vec<(i64, Vec<i64>, JsonObject)> = Vec::new();
+    let mut nodes: HashMap<i64, (f64, f64)> = HashMap::new();
+    reader.read_ways_and_deps(
+        |way| filters.is_empty() || filters.iter().any(|filter| filter.matches(way)),
+        |element| match element {
+            Element::Way(way) => {
+                let tags = way.tags().map(|(k, v)| (k.to_string(), v.into())).collect();
+                ways.push((way.id(), way.refs().collect(), tags));
+            }
+            Element::Node(node) => {
+                nodes.insert(node.id(), (node.lon(), node.lat()));
+            }
+            Element::DenseNode(node) => {
+                nodes.insert(node.id(), (node.lon(), node.lat()));
+            }
+            Element::Relation(_) => {}
+        },
+    )?;
+
+    Ok(Box::new(ways.into_iter().filter_map(
+        move |(id, refs, tags)| {
+            // Extracts might be missing some nodes; just use what's there
+            let pts: Vec<Vec<f64>> = refs
+                .into_iter()
+                .filter_map(|node| nodes.get(&node).map(|(lon, lat)| vec![*lon, *lat]))
+                .collect();
+            if pts.len() < 2 {
+                return None;
+            }
+            Some(Ok(Feature {
+                bbox: None,
+                geometry: Some(Geometry::new(Value::LineString(pts))),
+                id: Some(Id::Number(id.into())),
+                properties: Some(tags),
+                foreign_members: None,
+            }))
+        },
+    )))
+}
@@ -0,0 +1,39 @@
+use std::io::BufReader;
+
+use anyhow::{bail, Result};
+use fs_err::File;
+use pmtiles2::{util::decompress_all, util::zxy, PMTiles, TileType};
+
+use super::vector_tile::TileDecoder;
+use super::FeatureIter;
+
+/// Decodes every vector tile at the highest zoom of a PMTiles archive back into features, so they
+/// can be tiled again with different options. If `layer` is set, only that layer is read.
+/// Features clipped at tile boundaries come back as separate pieces.
+pub fn read_pmtiles(path: &str, layer: Option<&str>) -> Result<FeatureIter> {
+    let mut pmtiles = PMTiles::from_reader(BufReader::new(File::open(path)?))?;
+    if pmtiles.tile_type != TileType::Mvt {
+        bail!("{path} has {:?} tiles, not vector tiles", pmtiles.tile_type);
+    }
+    let z = pmtiles.max_zoom;
+    log::info!("Decoding tiles from {path} at zoom {z}");
+
+    let mut tile_ids = Vec::new();
+    for tile_id in pmtiles.tile_ids() {
+        let (tile_z, x, y) = zxy(*tile_id)?;
+        if tile_z == z {
+            tile_ids.push((*tile_id, x, y));
+        }
+    }
+    tile_ids.sort();
+
+    let mut decoder = TileDecoder::new(layer.map(|x| x.to_string()));
+    for (tile_id, x, y) in tile_ids {
+        let Some(data) = pmtiles.get_tile_by_id(tile_id)? else {
+            continue;
+        };
+        let data = decompress_all(pmtiles.tile_compression, &data)?;
+        decoder.decode(&data, z, x, y)?;
+    }
+    Ok(Box::new(decoder.features.into_iter().map(Ok)))
+}
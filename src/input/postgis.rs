@@ -0,0 +1,130 @@
+use std::error::Error;
+use std::sync::mpsc;
+
+use anyhow::{bail, Result};
+use geozero::{wkb::Ewkb, ToGeo};
+use postgres::fallible_iterator::FallibleIterator;
+use postgres::types::{FromSql, Type};
+use postgres::{Client, NoTls, Row};
+use serde_json::Value;
+
+use super::FeatureIter;
+
+/// Streams the results of a query from PostGIS, like `SELECT geom, count FROM edges`. The first
+/// PostGIS geometry column is used, and must be in WGS84; use `ST_Transform(geom, 4326)` in the
+/// query otherwise. Boolean, numeric, text, and JSON columns become properties; cast anything else
+/// to text in the query. Connections don't use TLS.
+pub fn read_postgis(connection: &str, sql: &str) -> Result<FeatureIter> {
+    let mut client = Client::connect(connection, NoTls)?;
+    // Find the geometry column before streaming, to fail fast with a nice error
+    let statement = client.prepare(sql)?;
+    let Some(geometry_column) = statement
+        .columns()
+        .iter()
+        .position(|c| c.type_().name() == "geometry")
+    else {
+        bail!("The query doesn't return any geometry columns");
+    };
+
+    // The rows borrow the client, so query in a background thread and send each feature back.
+    // Only a batch is buffered at a time, so a huge table isn't held in memory.
+    let (tx, rx) = mpsc::sync_channel(10_000);
+    std::thread::spawn(move || {
+        let mut rows = match client.query_raw(&statement, std::iter::empty::<i32>()) {
+            Ok(rows) => rows,
+            Err(err) => {
+                let _ = tx.send(Err(err.into()));
+                return;
+            }
+        };
+        loop {
+            let result = match rows.next() {
+                Ok(Some(row)) => convert(&row, geometry_column).transpose(),
+                Ok(None) => break,
+                Err(err) => Some(Err(err.into())),
+            };
+            // If the receiver is gone, stop querying
+            if let Some(result) = result {
+                if tx.send(result).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    Ok(Box::new(rx.into_iter()))
+}
+
+fn convert(row: &Row, geometry_column: usize) -> Result<Option<geojson::Feature>> {
+    let Some(RawBytes(ewkb)) = row.try_get(geometry_column)? else {
+        // No geometry
+        return Ok(None);
+    };
+    if let Some(srid) = ewkb_srid(&ewkb) {
+        if srid != 4326 && srid != 0 {
+            bail!("Geometry has SRID {srid}; use ST_Transform(geom, 4326) in the query");
+        }
+    }
+    let geometry = Ewkb(ewkb).to_geo()?;
+
+    let mut properties = geojson::JsonObject::new();
+    for (idx, column) in row.columns().iter().enumerate() {
+        if idx == geometry_column {
+            continue;
+        }
+        let value: Option<Value> = match *column.type_() {
+            Type::BOOL => row.try_get::<_, Option<bool>>(idx)?.map(Value::from),
+            Type::INT2 => row.try_get::<_, Option<i16>>(idx)?.map(Value::from),
+            Type::INT4 => row.try_get::<_, Option<i32>>(idx)?.map(Value::from),
+            Type::INT8 => row.try_get::<_, Option<i64>>(idx)?.map(Value::from),
+            Type::FLOAT4 => row.try_get::<_, Option<f32>>(idx)?.map(Value::from),
+            Type::FLOAT8 => row.try_get::<_, Option<f64>>(idx)?.map(Value::from),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => {
+                row.try_get::<_, Option<String>>(idx)?.map(Value::from)
+            }
+            Type::JSON | Type::JSONB => row.try_get(idx)?,
+            // Other geometry columns or unsupported types
+            _ => continue,
+        };
+        if let Some(value) = value {
+            properties.insert(column.name().to_string(), value);
+        }
+    }
+
+    Ok(Some(geojson::Feature {
+        bbox: None,
+        geometry: Some(geojson::Geometry::from(&geometry)),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    }))
+}
+
+/// PostGIS geometry is a custom type, sent as EWKB
+struct RawBytes(Vec<u8>);
+
+impl<'a> FromSql<'a> for RawBytes {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        Ok(Self(raw.to_vec()))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty.name() == "geometry"
+    }
+}
+
+/// EWKB optionally has the SRID after the geometry type
+fn ewkb_srid(ewkb: &[u8]) -> Option<u32> {
+    let read_u32 = |bytes: &[u8]| -> Option<u32> {
+        let bytes: [u8; 4] = bytes.try_into().ok()?;
+        Some(if ewkb[0] == 0 {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let geometry_type = read_u32(ewkb.get(1..5)?)?;
+    if geometry_type & 0x2000_0000 == 0 {
+        return None;
+    }
+    read_u32(ewkb.get(5..9)?)
+}
@@ -0,0 +1,40 @@
+use geozero::{ColumnValue, PropertyProcessor};
+use serde_json::Value;
+
+/// Collects properties from any geozero source into a GeoJSON-style map, keeping numeric types
+#[derive(Default)]
+pub struct JsonProperties(pub geojson::JsonObject);
+
+impl PropertyProcessor for JsonProperties {
+    fn property(
+        &mut self,
+        _idx: usize,
+        name: &str,
+        value: &ColumnValue,
+    ) -> geozero::error::Result<bool> {
+        self.0.insert(name.to_string(), column_value_to_json(value));
+        // Don't abort
+        Ok(false)
+    }
+}
+
+pub fn column_value_to_json(value: &ColumnValue) -> Value {
+    match value {
+        ColumnValue::Byte(x) => (*x).into(),
+        ColumnValue::UByte(x) => (*x).into(),
+        ColumnValue::Bool(x) => (*x).into(),
+        ColumnValue::Short(x) => (*x).into(),
+        ColumnValue::UShort(x) => (*x).into(),
+        ColumnValue::Int(x) => (*x).into(),
+        ColumnValue::UInt(x) => (*x).into(),
+        ColumnValue::Long(x) => (*x).into(),
+        ColumnValue::ULong(x) => (*x).into(),
+        ColumnValue::Float(x) => (*x).into(),
+        ColumnValue::Double(x) => (*x).into(),
+        ColumnValue::String(x) | ColumnValue::DateTime(x) => (*x).into(),
+        // Fall back to the raw string if it's not valid JSON
+        ColumnValue::Json(x) => serde_json::from_str(x).unwrap_or_else(|_| (*x).into()),
+        // Binary can't be represented in tiles
+        ColumnValue::Binary(_) => Value::Null,
+    }
+}
@@ -0,0 +1,256 @@
+//! Just enough protobuf decoding for the formats built on it, without needing generated code
+
+use anyhow::{bail, Result};
+
+/// One field of a message. Nested messages, strings, and packed arrays are all `Bytes`.
+pub enum Field<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Iterates over the (field number, value) pairs of one message
+pub struct Message<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Message<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let Some(byte) = self.buf.get(self.pos) else {
+                bail!("Protobuf message ends in the middle of a varint");
+            };
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("Protobuf varint is too long")
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len);
+        let Some(bytes) = end.and_then(|end| self.buf.get(self.pos..end)) else {
+            bail!("Protobuf message is truncated");
+        };
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_field(&mut self) -> Result<(u32, Field<'a>)> {
+        let key = self.read_varint()?;
+        let field = match key & 0x7 {
+            0 => Field::Varint(self.read_varint()?),
+            1 => Field::Fixed64(u64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            2 => {
+                let len = self.read_varint()? as usize;
+                Field::Bytes(self.take(len)?)
+            }
+            5 => Field::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            x => bail!("Unsupported protobuf wire type {x}"),
+        };
+        Ok(((key >> 3) as u32, field))
+    }
+}
+
+impl<'a> Iterator for Message<'a> {
+    type Item = Result<(u32, Field<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.buf.len() {
+            return None;
+        }
+        let result = self.read_field();
+        if result.is_err() {
+            // Don't keep reading garbage
+            self.pos = self.buf.len();
+        }
+        Some(result)
+    }
+}
+
+impl<'a> Field<'a> {
+    pub fn as_u64(&self) -> Result<u64> {
+        match self {
+            Field::Varint(x) | Field::Fixed64(x) => Ok(*x),
+            Field::Fixed32(x) => Ok(*x as u64),
+            Field::Bytes(_) => bail!("Expected a number in protobuf, got bytes"),
+        }
+    }
+
+    pub fn as_f64(&self) -> Result<f64> {
+        match self {
+            Field::Fixed64(x) => Ok(f64::from_bits(*x)),
+            Field::Fixed32(x) => Ok(f32::from_bits(*x) as f64),
+            _ => bail!("Expected a floating point number in protobuf"),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<&'a [u8]> {
+        match self {
+            Field::Bytes(x) => Ok(x),
+            _ => bail!("Expected bytes in protobuf, got a number"),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&'a str> {
+        Ok(std::str::from_utf8(self.as_bytes()?)?)
+    }
+
+    /// Handles a repeated field whether or not it's packed. Appends to `out`, since unpacked
+    /// values arrive one field at a time.
+    pub fn read_packed_varints(&self, out: &mut Vec<u64>) -> Result<()> {
+        match self {
+            Field::Bytes(bytes) => {
+                let mut msg = Message::new(bytes);
+                while msg.pos < bytes.len() {
+                    out.push(msg.read_varint()?);
+                }
+            }
+            Field::Varint(x) => out.push(*x),
+            _ => bail!("Expected varints in protobuf"),
+        }
+        Ok(())
+    }
+}
+
+pub fn zigzag(x: u64) -> i64 {
+    ((x >> 1) as i64) ^ -((x & 1) as i64)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn varint(mut x: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        while x >= 0x80 {
+            out.push((x as u8 & 0x7f) | 0x80);
+            x >>= 7;
+        }
+        out.push(x as u8);
+        out
+    }
+
+    pub(crate) fn varint_field(number: u32, value: u64) -> Vec<u8> {
+        [varint((number as u64) << 3), varint(value)].concat()
+    }
+
+    pub(crate) fn bytes_field(number: u32, value: &[u8]) -> Vec<u8> {
+        [
+            varint(((number as u64) << 3) | 2),
+            varint(value.len() as u64),
+            value.to_vec(),
+        ]
+        .concat()
+    }
+
+    fn read_all(buf: &[u8]) -> Result<Vec<(u32, Field<'_>)>> {
+        Message::new(buf).collect()
+    }
+
+    fn error(buf: &[u8]) -> String {
+        read_all(buf).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_varints() {
+        for x in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let buf = varint_field(1, x);
+            let fields = read_all(&buf).unwrap();
+            assert_eq!(fields.len(), 1);
+            assert_eq!(fields[0].0, 1);
+            assert_eq!(fields[0].1.as_u64().unwrap(), x);
+        }
+        assert_eq!(varint(300), [0xac, 0x02]);
+        assert_eq!(varint(u64::MAX).len(), 10);
+    }
+
+    #[test]
+    fn test_field_numbers() {
+        let buf = [
+            varint_field(1, 5),
+            bytes_field(15, b"ab"),
+            varint_field(16, 7),
+        ]
+        .concat();
+        let fields = read_all(&buf).unwrap();
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[1].0, 15);
+        assert_eq!(fields[1].1.as_str().unwrap(), "ab");
+        assert_eq!(fields[2].0, 16);
+        assert_eq!(fields[2].1.as_u64().unwrap(), 7);
+    }
+
+    #[test]
+    fn test_fixed() {
+        let mut buf = varint((1 << 3) | 1);
+        buf.extend(1.5_f64.to_le_bytes());
+        buf.extend(varint((2 << 3) | 5));
+        buf.extend(2.5_f32.to_le_bytes());
+        let fields = read_all(&buf).unwrap();
+        assert_eq!(fields[0].1.as_f64().unwrap(), 1.5);
+        assert_eq!(fields[1].1.as_f64().unwrap(), 2.5);
+    }
+
+    #[test]
+    fn test_packed() {
+        let packed: Vec<u8> = [1, 300, u64::MAX].into_iter().flat_map(varint).collect();
+        let buf = [bytes_field(1, &packed), varint_field(1, 4)].concat();
+        let mut values = Vec::new();
+        for field in Message::new(&buf) {
+            field.unwrap().1.read_packed_varints(&mut values).unwrap();
+        }
+        assert_eq!(values, [1, 300, u64::MAX, 4]);
+    }
+
+    #[test]
+    fn test_zigzag() {
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zigzag(1), -1);
+        assert_eq!(zigzag(2), 1);
+        assert_eq!(zigzag(3), -2);
+        assert_eq!(zigzag(u64::MAX), i64::MIN);
+        assert_eq!(zigzag(u64::MAX - 1), i64::MAX);
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert_eq!(
+            error(&[0x08, 0x80]),
+            "Protobuf message ends in the middle of a varint"
+        );
+        assert_eq!(
+            error(&[0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            "Protobuf varint is too long"
+        );
+        // A length past the end of the message
+        let mut buf = bytes_field(1, b"abc");
+        buf.pop();
+        assert_eq!(error(&buf), "Protobuf message is truncated");
+        // A length so big that adding it to the position overflows
+        let buf = [varint((1 << 3) | 2), varint(u64::MAX), b"abc".to_vec()].concat();
+        assert_eq!(error(&buf), "Protobuf message is truncated");
+        assert_eq!(
+            error(&[(1 << 3) | 1, 0, 0]),
+            "Protobuf message is truncated"
+        );
+        assert_eq!(error(&[(1 << 3) | 3]), "Unsupported protobuf wire type 3");
+    }
+
+    #[test]
+    fn test_stops_after_error() {
+        let buf = [vec![(1 << 3) | 3], varint_field(2, 1)].concat();
+        let mut message = Message::new(&buf);
+        assert!(message.next().unwrap().is_err());
+        assert!(message.next().is_none());
+    }
+}
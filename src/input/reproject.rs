@@ -0,0 +1,57 @@
+use std::cell::RefCell;
+
+use anyhow::{anyhow, Result};
+use geo::algorithm::map_coords::MapCoordsInPlace;
+use geo_types::{Coord, Geometry};
+use proj4rs::Proj;
+
+/// Transforms geometry from some other coordinate system into WGS84 longitude/latitude
+pub struct Reprojector {
+    src: Proj,
+    dst: Proj,
+}
+
+impl Reprojector {
+    /// Parses a WKT coordinate system, like in a shapefile's `.prj`. Returns `None` if it's
+    /// already WGS84.
+    pub fn from_wkt(wkt: &str) -> Result<Option<Self>> {
+        let projstring = proj4wkt::wkt_to_projstring(wkt.trim())
+            .map_err(|err| anyhow!("Can't parse WKT: {err:?}"))?;
+        Self::from_projstring(&projstring)
+    }
+
+    /// Returns `None` if the projection is already WGS84.
+    pub fn from_projstring(projstring: &str) -> Result<Option<Self>> {
+        let src = Proj::from_proj_string(projstring)?;
+        if src.is_latlong() && (projstring.contains("WGS84") || projstring.contains("wgs84")) {
+            return Ok(None);
+        }
+        let dst = Proj::from_proj_string("+proj=longlat +datum=WGS84 +no_defs")?;
+        Ok(Some(Self { src, dst }))
+    }
+
+    pub fn apply(&self, geometry: &mut Geometry<f64>) -> Result<()> {
+        // try_map_coords_in_place hits a recursion limit on Geometry, and map_coords_in_place needs
+        // a Copy closure, so stash the first error in a RefCell
+        let error = RefCell::new(None);
+        let error_ref = &error;
+        geometry.map_coords_in_place(|c| {
+            let mut pt = if self.src.is_latlong() {
+                (c.x.to_radians(), c.y.to_radians(), 0.0)
+            } else {
+                (c.x, c.y, 0.0)
+            };
+            if let Err(err) = proj4rs::transform::transform(&self.src, &self.dst, &mut pt) {
+                error_ref.borrow_mut().get_or_insert(err);
+            }
+            Coord {
+                x: pt.0.to_degrees(),
+                y: pt.1.to_degrees(),
+            }
+        });
+        match error.into_inner() {
+            Some(err) => Err(err.into()),
+            None => Ok(()),
+        }
+    }
+}
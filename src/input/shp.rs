@@ -0,0 +1,96 @@
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+use shapefile::dbase::{self, FieldValue, Record};
+use shapefile::{Shape, ShapeReader};
+
+use super::reproject::Reprojector;
+use super::FeatureIter;
+
+/// Reads a shapefile, along with its `.dbf` attributes. If there's a `.prj` file, the geometry is
+/// reprojected to WGS84; otherwise it's assumed to be WGS84 already.
+pub fn read_shapefile(path: &str) -> Result<FeatureIter> {
+    let prj_path = Path::new(path).with_extension("prj");
+    let prj = if prj_path.exists() {
+        Some(fs_err::read_to_string(prj_path)?)
+    } else {
+        None
+    };
+    read_all(shapefile::Reader::from_path(path)?, prj.as_deref())
+}
+
+/// Like `read_shapefile`, but from the contents of each file
+pub(crate) fn read_shapefile_from<T: Read + Seek, D: Read + Seek>(
+    shp: T,
+    dbf: D,
+    prj: Option<&str>,
+) -> Result<FeatureIter> {
+    let reader = shapefile::Reader::new(ShapeReader::new(shp)?, dbase::Reader::new(dbf)?);
+    read_all(reader, prj)
+}
+
+fn read_all<T: Read + Seek, D: Read + Seek>(
+    mut reader: shapefile::Reader<T, D>,
+    prj: Option<&str>,
+) -> Result<FeatureIter> {
+    let reprojector = match prj {
+        Some(wkt) => Reprojector::from_wkt(wkt)?,
+        None => None,
+    };
+
+    // The reader's iterator borrows it, so just read everything upfront
+    let shapes_and_records = reader.read()?;
+    Ok(Box::new(shapes_and_records.into_iter().filter_map(
+        move |(shape, record)| convert(shape, record, reprojector.as_ref()).transpose(),
+    )))
+}
+
+fn convert(
+    shape: Shape,
+    record: Record,
+    reprojector: Option<&Reprojector>,
+) -> Result<Option<geojson::Feature>> {
+    // These have no geometry
+    if let Shape::NullShape = shape {
+        return Ok(None);
+    }
+    let mut geometry: geo_types::Geometry<f64> = match shape.try_into() {
+        Ok(geometry) => geometry,
+        Err(err) => bail!("Can't convert a shape to geometry: {err}"),
+    };
+    if let Some(reprojector) = reprojector {
+        reprojector.apply(&mut geometry)?;
+    }
+
+    let mut properties = geojson::JsonObject::new();
+    for (key, value) in record {
+        let value = field_value_to_json(value);
+        if !value.is_null() {
+            properties.insert(key, value);
+        }
+    }
+
+    Ok(Some(geojson::Feature {
+        bbox: None,
+        geometry: Some(geojson::Geometry::from(&geometry)),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    }))
+}
+
+fn field_value_to_json(value: FieldValue) -> Value {
+    match value {
+        FieldValue::Character(x) => x.into(),
+        FieldValue::Numeric(x) => x.into(),
+        FieldValue::Logical(x) => x.into(),
+        FieldValue::Date(x) => x.map(|d| d.to_string()).into(),
+        FieldValue::Float(x) => x.into(),
+        FieldValue::Integer(x) => x.into(),
+        FieldValue::Currency(x) | FieldValue::Double(x) => x.into(),
+        FieldValue::DateTime(x) => format!("{:?}", x).into(),
+        FieldValue::Memo(x) => x.into(),
+    }
+}
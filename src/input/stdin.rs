@@ -0,0 +1,41 @@
+use std::io::{BufRead, BufReader, Cursor, Read};
+
+use anyhow::Result;
+
+use super::compression;
+use super::{read_stream, FeatureIter, Format, InputOptions};
+
+/// Reads GeoJSON, a GeoJSON sequence, or an Arrow IPC stream from stdin, possibly gzip or zstd
+/// compressed. There's no file extension, so the format is guessed from the start: Arrow has magic
+/// bytes, and if the first line is a complete feature by itself, it's a sequence.
+pub fn read_stdin(input_options: &InputOptions) -> Result<FeatureIter> {
+    let mut reader = compression::decompress_stream(BufReader::new(std::io::stdin().lock()))?;
+    let start = reader.fill_buf()?;
+    // Arrow streams start with a continuation marker
+    if start.starts_with(b"ARROW1") || start.starts_with(&[0xff; 4]) {
+        return read_stream(reader, "stdin", Format::ArrowIpc, input_options);
+    }
+
+    let mut first_line = Vec::new();
+    reader.read_until(b'\n', &mut first_line)?;
+    let format = if is_feature(&first_line) {
+        Format::GeoJsonSeq
+    } else {
+        Format::GeoJson
+    };
+    read_stream(
+        Cursor::new(first_line).chain(reader),
+        "stdin",
+        format,
+        input_options,
+    )
+}
+
+fn is_feature(line: &[u8]) -> bool {
+    // Sequences might use the record separator
+    let line = line.strip_prefix(b"\x1e").unwrap_or(line);
+    match serde_json::from_slice::<serde_json::Value>(line) {
+        Ok(value) => value["type"] == "Feature",
+        Err(_) => false,
+    }
+}
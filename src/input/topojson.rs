@@ -0,0 +1,215 @@
+use std::io::BufReader;
+
+use anyhow::{bail, Context, Result};
+use geojson::{feature::Id, Feature, Geometry, JsonObject, Value as GeoValue};
+use serde_json::Value;
+
+use super::FeatureIter;
+
+/// Reads a TopoJSON file, decoding the shared arcs back into full geometries. If `object` is set,
+/// only the geometries under that named object are read; otherwise all of them are.
+pub fn read_topojson(path: &str, object: Option<&str>) -> Result<FeatureIter> {
+    let topology: Value = serde_json::from_reader(BufReader::new(fs_err::File::open(path)?))?;
+    if topology["type"] != "Topology" {
+        bail!("{path} isn't a TopoJSON Topology");
+    }
+    let Some(objects) = topology["objects"].as_object() else {
+        bail!("{path} has no objects");
+    };
+
+    let transform = match topology.get("transform") {
+        Some(transform) => Some(Transform {
+            scale: parse_pair(&transform["scale"]).context("bad transform scale")?,
+            translate: parse_pair(&transform["translate"]).context("bad transform translate")?,
+        }),
+        None => None,
+    };
+    let arcs = decode_arcs(&topology["arcs"], transform.as_ref())?;
+    let topo = Topo { arcs, transform };
+
+    let selected: Vec<&Value> = match object {
+        Some(name) => match objects.get(name) {
+            Some(obj) => vec![obj],
+            None => bail!(
+                "{path} has no object {name}. Objects: {}",
+                objects.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        },
+        None => objects.values().collect(),
+    };
+
+    let mut features = Vec::new();
+    for obj in selected {
+        topo.collect_features(obj, &mut features)?;
+    }
+    Ok(Box::new(features.into_iter().map(Ok)))
+}
+
+struct Transform {
+    scale: [f64; 2],
+    translate: [f64; 2],
+}
+
+struct Topo {
+    /// Absolute coordinates
+    arcs: Vec<Vec<Vec<f64>>>,
+    transform: Option<Transform>,
+}
+
+impl Topo {
+    /// Top-level GeometryCollections are flattened into one feature per member, like a
+    /// FeatureCollection
+    fn collect_features(&self, obj: &Value, features: &mut Vec<Feature>) -> Result<()> {
+        if obj["type"] == "GeometryCollection" {
+            for member in obj["geometries"].as_array().into_iter().flatten() {
+                self.collect_features(member, features)?;
+            }
+            return Ok(());
+        }
+
+        let Some(value) = self.geometry(obj)? else {
+            return Ok(());
+        };
+        let id = match obj.get("id") {
+            Some(Value::String(x)) => Some(Id::String(x.clone())),
+            Some(Value::Number(x)) => Some(Id::Number(x.clone())),
+            _ => None,
+        };
+        let properties: Option<JsonObject> = obj["properties"].as_object().cloned();
+        features.push(Feature {
+            bbox: None,
+            geometry: Some(Geometry::new(value)),
+            id,
+            properties,
+            foreign_members: None,
+        });
+        Ok(())
+    }
+
+    fn geometry(&self, obj: &Value) -> Result<Option<GeoValue>> {
+        let value = match obj["type"].as_str() {
+            Some("Point") => GeoValue::Point(self.position(&obj["coordinates"])?),
+            Some("MultiPoint") => GeoValue::MultiPoint(
+                as_array(&obj["coordinates"])?
+                    .iter()
+                    .map(|pt| self.position(pt))
+                    .collect::<Result<_>>()?,
+            ),
+            Some("LineString") => GeoValue::LineString(self.line(&obj["arcs"])?),
+            Some("MultiLineString") => GeoValue::MultiLineString(self.lines(&obj["arcs"])?),
+            Some("Polygon") => GeoValue::Polygon(self.lines(&obj["arcs"])?),
+            Some("MultiPolygon") => GeoValue::MultiPolygon(
+                as_array(&obj["arcs"])?
+                    .iter()
+                    .map(|polygon| self.lines(polygon))
+                    .collect::<Result<_>>()?,
+            ),
+            Some("GeometryCollection") => GeoValue::GeometryCollection(
+                as_array(&obj["geometries"])?
+                    .iter()
+                    .filter_map(|member| self.geometry(member).transpose())
+                    .map(|value| value.map(Geometry::new))
+                    .collect::<Result<_>>()?,
+            ),
+            // A null geometry
+            None => return Ok(None),
+            Some(x) => bail!("Unknown TopoJSON geometry type {x}"),
+        };
+        Ok(Some(value))
+    }
+
+    /// Points aren't delta-encoded, but are still quantized
+    fn position(&self, value: &Value) -> Result<Vec<f64>> {
+        let pt = parse_pair(value)?;
+        Ok(match self.transform {
+            Some(ref t) => t.apply(pt),
+            None => pt,
+        }
+        .to_vec())
+    }
+
+    fn lines(&self, value: &Value) -> Result<Vec<Vec<Vec<f64>>>> {
+        as_array(value)?
+            .iter()
+            .map(|arcs| self.line(arcs))
+            .collect()
+    }
+
+    /// Stitches arcs together into one line. Negative indices (the one's complement) mean the arc
+    /// is reversed.
+    fn line(&self, value: &Value) -> Result<Vec<Vec<f64>>> {
+        let mut points: Vec<Vec<f64>> = Vec::new();
+        for idx in as_array(value)? {
+            let Some(idx) = idx.as_i64() else {
+                bail!("Arc index {idx} isn't an integer");
+            };
+            let (arc_idx, reversed) = if idx < 0 {
+                (!idx as usize, true)
+            } else {
+                (idx as usize, false)
+            };
+            let Some(arc) = self.arcs.get(arc_idx) else {
+                bail!("Arc index {idx} is out of bounds");
+            };
+            let mut arc = arc.clone();
+            if reversed {
+                arc.reverse();
+            }
+            // Each arc starts where the previous one ended
+            if !points.is_empty() && !arc.is_empty() {
+                arc.remove(0);
+            }
+            points.extend(arc);
+        }
+        Ok(points)
+    }
+}
+
+impl Transform {
+    fn apply(&self, pt: [f64; 2]) -> [f64; 2] {
+        [
+            pt[0] * self.scale[0] + self.translate[0],
+            pt[1] * self.scale[1] + self.translate[1],
+        ]
+    }
+}
+
+/// Undoes quantization and delta-encoding
+fn decode_arcs(value: &Value, transform: Option<&Transform>) -> Result<Vec<Vec<Vec<f64>>>> {
+    let mut arcs = Vec::new();
+    for arc in as_array(value)? {
+        let mut points = Vec::new();
+        let mut current = [0.0, 0.0];
+        for pt in as_array(arc)? {
+            let pt = parse_pair(pt)?;
+            let absolute = match transform {
+                Some(t) => {
+                    current = [current[0] + pt[0], current[1] + pt[1]];
+                    t.apply(current)
+                }
+                None => pt,
+            };
+            points.push(absolute.to_vec());
+        }
+        arcs.push(points);
+    }
+    Ok(arcs)
+}
+
+fn parse_pair(value: &Value) -> Result<[f64; 2]> {
+    let array = as_array(value)?;
+    match (
+        array.first().and_then(|x| x.as_f64()),
+        array.get(1).and_then(|x| x.as_f64()),
+    ) {
+        (Some(x), Some(y)) => Ok([x, y]),
+        _ => bail!("Expected a pair of numbers, got {value}"),
+    }
+}
+
+fn as_array(value: &Value) -> Result<&Vec<Value>> {
+    match value.as_array() {
+        Some(array) => Ok(array),
+        None => bail!("Expected an array, got {value}"),
+    }
+}
@@ -0,0 +1,464 @@
+//! Decodes Mapbox Vector Tiles back into WGS84 features, for re-tiling existing archives
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+
+use anyhow::{bail, Result};
+use flate2::read::GzDecoder;
+use geojson::{feature::Id, Feature, Geometry, JsonObject, Value as GeoValue};
+use serde_json::Value;
+
+use super::protobuf::{zigzag, Message};
+
+/// Decodes tiles from one zoom level. A feature might appear in several tiles, so exact duplicates
+/// are skipped. Each feature has its layer name in a `layer` foreign member.
+pub struct TileDecoder {
+    layer: Option<String>,
+    seen: HashSet<u64>,
+    /// Leave coordinates in the tile's own pixels, from 0 to the extent, instead of WGS84
+    pub pixel_coords: bool,
+    pub features: Vec<Feature>,
+}
+
+impl TileDecoder {
+    /// If `layer` is set, only features from that layer are kept
+    pub fn new(layer: Option<String>) -> Self {
+        Self {
+            layer,
+            seen: HashSet::new(),
+            pixel_coords: false,
+            features: Vec::new(),
+        }
+    }
+
+    /// The tile may be gzipped. `y` counts from the top, like XYZ tiles.
+    pub fn decode(&mut self, bytes: &[u8], z: u8, x: u64, y: u64) -> Result<()> {
+        let unzipped;
+        let bytes = if bytes.starts_with(&[0x1f, 0x8b]) {
+            let mut buf = Vec::new();
+            GzDecoder::new(bytes).read_to_end(&mut buf)?;
+            unzipped = buf;
+            &unzipped[..]
+        } else {
+            bytes
+        };
+
+        for field in Message::new(bytes) {
+            if let (3, layer) = field? {
+                self.decode_layer(layer.as_bytes()?, z, x, y)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_layer(&mut self, bytes: &[u8], z: u8, x: u64, y: u64) -> Result<()> {
+        let mut name = String::new();
+        let mut raw_features = Vec::new();
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        let mut extent = 4096;
+        for field in Message::new(bytes) {
+            match field? {
+                (1, value) => name = value.as_str()?.to_string(),
+                (2, value) => raw_features.push(value.as_bytes()?),
+                (3, value) => keys.push(value.as_str()?.to_string()),
+                (4, value) => values.push(read_value(value.as_bytes()?)?),
+                (5, value) => extent = value.as_u64()?,
+                _ => {}
+            }
+        }
+        if self.layer.as_ref().is_some_and(|layer| *layer != name) {
+            return Ok(());
+        }
+
+        let tile = TileCoords {
+            z,
+            x,
+            y,
+            extent,
+            pixel_coords: self.pixel_coords,
+        };
+        for bytes in raw_features {
+            let mut id = None;
+            let mut tags = Vec::new();
+            let mut geometry_type = 0;
+            let mut commands = Vec::new();
+            for field in Message::new(bytes) {
+                match field? {
+                    (1, value) => id = Some(value.as_u64()?),
+                    (2, value) => value.read_packed_varints(&mut tags)?,
+                    (3, value) => geometry_type = value.as_u64()?,
+                    (4, value) => value.read_packed_varints(&mut commands)?,
+                    _ => {}
+                }
+            }
+
+            let mut properties = JsonObject::new();
+            for pair in tags.chunks(2) {
+                let (Some(key), Some(value)) = (
+                    keys.get(pair[0] as usize),
+                    pair.get(1).and_then(|idx| values.get(*idx as usize)),
+                ) else {
+                    bail!("Vector tile feature has a bad tag index");
+                };
+                properties.insert(key.clone(), value.clone());
+            }
+
+            let rings = decode_commands(&commands)?;
+            // Features appearing whole in several tiles land on the same global pixels
+            let mut hasher = DefaultHasher::new();
+            name.hash(&mut hasher);
+            for ring in &rings {
+                tile.to_global(ring).hash(&mut hasher);
+            }
+            serde_json::to_string(&properties)?.hash(&mut hasher);
+            if !self.seen.insert(hasher.finish()) {
+                continue;
+            }
+
+            let Some(geometry) = to_geometry(geometry_type, &rings, &tile) else {
+                continue;
+            };
+            let mut foreign_members = JsonObject::new();
+            foreign_members.insert("layer".to_string(), name.clone().into());
+            self.features.push(Feature {
+                bbox: None,
+                geometry: Some(Geometry::new(geometry)),
+                id: id.map(|id| Id::Number(id.into())),
+                properties: Some(properties),
+                foreign_members: Some(foreign_members),
+            });
+        }
+        Ok(())
+    }
+}
+
+struct TileCoords {
+    z: u8,
+    x: u64,
+    y: u64,
+    extent: u64,
+    pixel_coords: bool,
+}
+
+impl TileCoords {
+    /// Pixel coordinates across the whole world at this zoom
+    fn to_global(&self, points: &[(i64, i64)]) -> Vec<(i64, i64)> {
+        let (dx, dy) = ((self.x * self.extent) as i64, (self.y * self.extent) as i64);
+        points.iter().map(|(x, y)| (x + dx, y + dy)).collect()
+    }
+
+    /// To WGS84, or tile pixels if `pixel_coords` is set
+    fn to_coords(&self, points: &[(i64, i64)]) -> Vec<Vec<f64>> {
+        if self.pixel_coords {
+            return points
+                .iter()
+                .map(|(x, y)| vec![*x as f64, *y as f64])
+                .collect();
+        }
+        let size = (self.extent << self.z) as f64;
+        self.to_global(points)
+            .into_iter()
+            .map(|(x, y)| {
+                let lon = x as f64 / size * 360.0 - 180.0;
+                let lat = (std::f64::consts::PI * (1.0 - 2.0 * y as f64 / size))
+                    .sinh()
+                    .atan()
+                    .to_degrees();
+                vec![lon, lat]
+            })
+            .collect()
+    }
+}
+
+/// Returns each MoveTo-started sequence of points in tile coordinates. Closed rings don't repeat
+/// their first point.
+fn decode_commands(commands: &[u64]) -> Result<Vec<Vec<(i64, i64)>>> {
+    let mut parts: Vec<Vec<(i64, i64)>> = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    let mut i = 0;
+    while i < commands.len() {
+        let id = commands[i] & 0x7;
+        let count = (commands[i] >> 3) as usize;
+        i += 1;
+        match id {
+            // MoveTo or LineTo
+            1 | 2 => {
+                for _ in 0..count {
+                    let (Some(dx), Some(dy)) = (commands.get(i), commands.get(i + 1)) else {
+                        bail!("Vector tile geometry is truncated");
+                    };
+                    x += zigzag(*dx);
+                    y += zigzag(*dy);
+                    i += 2;
+                    if id == 1 || parts.is_empty() {
+                        parts.push(Vec::new());
+                    }
+                    parts.last_mut().unwrap().push((x, y));
+                }
+            }
+            // ClosePath
+            7 => {}
+            _ => bail!("Unknown vector tile geometry command {id}"),
+        }
+    }
+    Ok(parts)
+}
+
+fn to_geometry(
+    geometry_type: u64,
+    parts: &[Vec<(i64, i64)>],
+    tile: &TileCoords,
+) -> Option<GeoValue> {
+    match geometry_type {
+        // Point
+        1 => {
+            let mut points: Vec<_> = parts.iter().flat_map(|part| tile.to_coords(part)).collect();
+            match points.len() {
+                0 => None,
+                1 => Some(GeoValue::Point(points.pop().unwrap())),
+                _ => Some(GeoValue::MultiPoint(points)),
+            }
+        }
+        // LineString
+        2 => {
+            let mut lines: Vec<_> = parts
+                .iter()
+                .filter(|part| part.len() > 1)
+                .map(|part| tile.to_coords(part))
+                .collect();
+            match lines.len() {
+                0 => None,
+                1 => Some(GeoValue::LineString(lines.pop().unwrap())),
+                _ => Some(GeoValue::MultiLineString(lines)),
+            }
+        }
+        // Polygon. Exterior rings have positive area in tile coordinates, and start a new polygon.
+        3 => {
+            let mut polygons: Vec<Vec<Vec<Vec<f64>>>> = Vec::new();
+            for ring in parts.iter().filter(|ring| ring.len() > 2) {
+                let mut closed = tile.to_coords(ring);
+                closed.push(closed[0].clone());
+                if signed_area(ring) > 0 || polygons.is_empty() {
+                    polygons.push(vec![closed]);
+                } else {
+                    polygons.last_mut().unwrap().push(closed);
+                }
+            }
+            match polygons.len() {
+                0 => None,
+                1 => Some(GeoValue::Polygon(polygons.pop().unwrap())),
+                _ => Some(GeoValue::MultiPolygon(polygons)),
+            }
+        }
+        _ => None,
+    }
+}
+
+fn signed_area(ring: &[(i64, i64)]) -> i64 {
+    let mut sum = 0;
+    for (i, (x1, y1)) in ring.iter().enumerate() {
+        let (x2, y2) = ring[(i + 1) % ring.len()];
+        sum += x1 * y2 - x2 * y1;
+    }
+    sum
+}
+
+fn read_value(bytes: &[u8]) -> Result<Value> {
+    let mut value = Value::Null;
+    for field in Message::new(bytes) {
+        value = match field? {
+            (1, x) => x.as_str()?.into(),
+            (2, x) | (3, x) => x.as_f64()?.into(),
+            (4, x) => (x.as_u64()? as i64).into(),
+            (5, x) => x.as_u64()?.into(),
+            (6, x) => zigzag(x.as_u64()?).into(),
+            (7, x) => (x.as_u64()? != 0).into(),
+            _ => continue,
+        };
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use mvt::{GeomEncoder, GeomType, Tile};
+    use pointy::Transform;
+
+    use super::*;
+
+    /// A tile with one of each geometry type in a `roads` layer, and a point in an `other` layer
+    fn encode_tile() -> Vec<u8> {
+        let mut tile = Tile::new(4096);
+        let mut layer = tile.create_layer("roads");
+
+        let line = GeomEncoder::new(GeomType::Linestring, Transform::default())
+            .point(0.0, 0.0)
+            .unwrap()
+            .point(100.0, 200.0)
+            .unwrap()
+            .point(300.0, 50.0)
+            .unwrap()
+            .encode()
+            .unwrap();
+        let mut feature = layer.into_feature(line);
+        feature.set_id(7);
+        feature.add_tag_string("name", "Main St");
+        feature.add_tag_double("count", 2.5);
+        feature.add_tag_sint("offset", -3);
+        feature.add_tag_uint("lanes", 2);
+        feature.add_tag_bool("oneway", true);
+        layer = feature.into_layer();
+
+        let mut lines = GeomEncoder::new(GeomType::Linestring, Transform::default());
+        for part in [[(10.0, 10.0), (20.0, 10.0)], [(30.0, 30.0), (30.0, 40.0)]] {
+            for (x, y) in part {
+                lines.add_point(x, y).unwrap();
+            }
+            lines.complete_geom().unwrap();
+        }
+        layer = layer.into_feature(lines.encode().unwrap()).into_layer();
+
+        let polygon = GeomEncoder::new(GeomType::Polygon, Transform::default())
+            .point(0.0, 0.0)
+            .unwrap()
+            .point(10.0, 0.0)
+            .unwrap()
+            .point(10.0, 10.0)
+            .unwrap()
+            .point(0.0, 10.0)
+            .unwrap()
+            .complete()
+            .unwrap()
+            .encode()
+            .unwrap();
+        layer = layer.into_feature(polygon).into_layer();
+        tile.add_layer(layer).unwrap();
+
+        let other = tile.create_layer("other");
+        let point = GeomEncoder::new(GeomType::Point, Transform::default())
+            .point(2048.0, 2048.0)
+            .unwrap()
+            .encode()
+            .unwrap();
+        tile.add_layer(other.into_feature(point).into_layer())
+            .unwrap();
+        tile.to_bytes().unwrap()
+    }
+
+    fn decode(decoder: &mut TileDecoder, bytes: &[u8]) -> Vec<Feature> {
+        decoder.decode(bytes, 0, 0, 0).unwrap();
+        std::mem::take(&mut decoder.features)
+    }
+
+    fn pixels(layer: Option<&str>, bytes: &[u8]) -> Vec<Feature> {
+        let mut decoder = TileDecoder::new(layer.map(|x| x.to_string()));
+        decoder.pixel_coords = true;
+        decode(&mut decoder, bytes)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let features = pixels(None, &encode_tile());
+        assert_eq!(features.len(), 4);
+
+        let line = &features[0];
+        assert_eq!(
+            line.geometry.as_ref().unwrap().value,
+            GeoValue::LineString(vec![vec![0.0, 0.0], vec![100.0, 200.0], vec![300.0, 50.0]])
+        );
+        assert_eq!(line.id, Some(Id::Number(7.into())));
+        let properties = line.properties.as_ref().unwrap();
+        assert_eq!(properties["name"], "Main St");
+        assert_eq!(properties["count"], 2.5);
+        assert_eq!(properties["offset"], -3);
+        assert_eq!(properties["lanes"], 2);
+        assert_eq!(properties["oneway"], true);
+        assert_eq!(line.foreign_members.as_ref().unwrap()["layer"], "roads");
+
+        assert_eq!(
+            features[1].geometry.as_ref().unwrap().value,
+            GeoValue::MultiLineString(vec![
+                vec![vec![10.0, 10.0], vec![20.0, 10.0]],
+                vec![vec![30.0, 30.0], vec![30.0, 40.0]],
+            ])
+        );
+        assert_eq!(
+            features[2].geometry.as_ref().unwrap().value,
+            GeoValue::Polygon(vec![vec![
+                vec![0.0, 0.0],
+                vec![10.0, 0.0],
+                vec![10.0, 10.0],
+                vec![0.0, 10.0],
+                vec![0.0, 0.0],
+            ]])
+        );
+        assert_eq!(
+            features[3].geometry.as_ref().unwrap().value,
+            GeoValue::Point(vec![2048.0, 2048.0])
+        );
+        assert_eq!(
+            features[3].foreign_members.as_ref().unwrap()["layer"],
+            "other"
+        );
+    }
+
+    #[test]
+    fn test_wgs84() {
+        let features = decode(
+            &mut TileDecoder::new(Some("other".to_string())),
+            &encode_tile(),
+        );
+        assert_eq!(features.len(), 1);
+        let GeoValue::Point(ref pt) = features[0].geometry.as_ref().unwrap().value else {
+            panic!("Expected a Point");
+        };
+        // The middle of the only tile at zoom 0
+        assert!(pt[0].abs() < 1e-9 && pt[1].abs() < 1e-9);
+
+        let features = decode(&mut TileDecoder::new(None), &encode_tile());
+        let GeoValue::LineString(ref line) = features[0].geometry.as_ref().unwrap().value else {
+            panic!("Expected a LineString");
+        };
+        // The top-left corner
+        assert_eq!(line[0][0], -180.0);
+        assert!((line[0][1] - 85.0511).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_layer_and_duplicates() {
+        let bytes = encode_tile();
+        assert!(pixels(Some("missing"), &bytes).is_empty());
+
+        let mut decoder = TileDecoder::new(Some("roads".to_string()));
+        assert_eq!(decode(&mut decoder, &bytes).len(), 3);
+        // The same features again are skipped
+        assert!(decode(&mut decoder, &bytes).is_empty());
+    }
+
+    #[test]
+    fn test_gzipped() {
+        let bytes = encode_tile();
+        let mut gzipped = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzipped.write_all(&bytes).unwrap();
+        let gzipped = gzipped.finish().unwrap();
+        assert_eq!(pixels(None, &gzipped), pixels(None, &bytes));
+    }
+
+    #[test]
+    fn test_bad_commands() {
+        assert_eq!(
+            decode_commands(&[(1 << 3) | 1, 2]).unwrap_err().to_string(),
+            "Vector tile geometry is truncated"
+        );
+        assert_eq!(
+            decode_commands(&[(1 << 3) | 3]).unwrap_err().to_string(),
+            "Unknown vector tile geometry command 3"
+        );
+    }
+}
@@ -1,132 +1,1785 @@
-use std::collections::HashMap;
-use std::io::{Cursor, Read};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{Cursor, Read, Seek, Write};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use geo::algorithm::bounding_rect::BoundingRect;
+use geo::algorithm::euclidean_length::EuclideanLength;
 use geo::algorithm::map_coords::MapCoordsInPlace;
 use geo_types::Geometry;
 use geojson::FeatureReader;
-use indicatif::{HumanBytes, HumanCount, MultiProgress, ProgressBar, ProgressStyle};
 use mvt::{GeomEncoder, GeomType, MapGrid, Tile, TileId};
-use pmtiles2::{util::tile_id as get_tile_id, Compression, PMTiles, TileType};
+use pmtiles2::util::{compress_all, decompress_all, tile_id as get_tile_id};
+use pmtiles2::{Compression, PMTiles, TileType};
 use pointy::Transform;
 use rayon::prelude::*;
-use rstar::{primitives::CachedEnvelope, RTree, RTreeObject, AABB};
+use rstar::primitives::Rectangle;
+use rstar::{Envelope, RTreeObject, AABB};
 use serde_json::Value;
 
+use self::checkpoint::Checkpoint;
+use self::clock::Instant;
+use self::compress::TileCompressor;
+use self::error::invalid_options;
+use self::human::{HumanBytes, HumanCount};
 use self::math::BBox;
+use self::output::{PMTilesSink, PMTilesWriter, TileSink};
+use self::spill::{Spill, SpillWriter};
+use self::stats::TileRecorder;
 
+#[cfg(feature = "async")]
+pub use self::asynchronous::geojson_to_pmtiles_async;
+pub use self::builder::OptionsBuilder;
+pub use self::compress::CompressionPreset;
+pub use self::density::DensityRaster;
+pub use self::direction::DirectionRule;
+pub use self::error::ConversionError;
+pub use self::estimate::{estimate, Estimate, ZoomEstimate};
+pub use self::filter::Filter;
+pub use self::grid::GridAggregation;
+pub use self::mask::read_mask;
+pub use self::pipeline::{TileIter, TilePipeline};
+#[cfg(feature = "progress")]
+pub use self::progress::IndicatifProgress;
+pub use self::progress::{Progress, ProgressObserver, TileReport};
+pub use self::properties::{Properties, PropertyValue};
+pub use self::push::TilerBuilder;
+pub use self::sample::Sampler;
+pub use self::spill::FeatureRef;
+pub use self::stats::{ConversionStats, ZoomStats};
+pub use self::warning::Warning;
+pub use rstar::{primitives::CachedEnvelope, RTree};
+
+#[cfg(feature = "async")]
+mod asynchronous;
+mod builder;
+mod checkpoint;
+mod clock;
+mod compress;
+mod density;
+mod direction;
+mod dissolve;
+mod error;
+mod estimate;
+mod filter;
+mod grid;
+pub mod human;
+pub mod input;
+mod mask;
 mod math;
+pub mod output;
+mod pipeline;
+mod progress;
+mod properties;
+mod push;
+mod sample;
+#[cfg(feature = "serde")]
+mod serialize;
+mod sorted;
+mod spill;
+mod stats;
+mod validate;
+mod warning;
 
+/// How to make tiles. Start from `Options::default()` or `Options::builder()`, since fields get
+/// added over time.
+#[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Options {
+    /// The name of the one layer made from a single input, unless `layers` is set
     pub layer_name: String,
+    /// Splits a single input into these layers, like `edges`, `origins`, and `destinations`, with
+    /// each feature going in every layer whose `LayerOptions::filter` matches it. Each layer falls
+    /// back to these options for anything it doesn't set. If empty, everything goes in one layer
+    /// called `layer_name`. Functions taking their own layers ignore this.
+    pub layers: Vec<LayerOptions>,
     pub description: Option<String>,
     /// Descending
     pub sort_by_key: Option<String>,
     pub zoom_levels: Vec<u32>,
+    /// Go up to a zoom guessed from how closely spaced the features are, instead of the last of
+    /// `zoom_levels`
+    pub guess_max_zoom: bool,
+    /// Start from the zoom where all the features fit in one tile, instead of the first of
+    /// `zoom_levels`
+    pub guess_min_zoom: bool,
     pub limit_size_bytes: Option<usize>,
+    /// Merge all LineStrings sharing a value for this key into one MultiLineString before tiling
+    pub dissolve_by_key: Option<String>,
+    /// Replace raw features at low zooms with a density grid layer
+    pub grid_aggregation: Option<GridAggregation>,
+    /// Only keep features this matches. Features left out don't count towards the bounds or the
+    /// layer's fields.
+    pub filter: Option<Filter>,
+    /// Only keep about this fraction of the features, from 0 to 1, picked at random by `seed`.
+    /// Features are picked by their position in their layer, counting ones `filter` leaves out, so
+    /// changing the filter doesn't change which of the remaining features are kept.
+    pub sample_rate: Option<f64>,
+    /// Makes every random choice, like `sample_rate`, so runs with the same input and seed make the
+    /// same archive. `Sampler::new(options.seed)` makes the same choices in callbacks.
+    pub seed: u64,
+    /// Which properties end up in the tiles and the metadata. Sort and dissolve keys work either
+    /// way.
+    pub attributes: Attributes,
+    /// Only keep features intersecting this WGS84 boundary, clipping LineStrings crossing the edge
+    pub mask: Option<geo_types::MultiPolygon<f64>>,
+    /// Make LineStrings point in a consistent direction
+    pub direction: Option<DirectionRule>,
+    /// Only generate tiles covering this WGS84 area, even if the features extend further
+    pub bbox: Option<geo_types::Rect<f64>>,
+    /// Draw PNG heatmap tiles of line density, instead of vector tiles
+    pub density: Option<DensityRaster>,
+    /// Leave the tiles, directories, and metadata uncompressed, so archives built from slightly
+    /// different inputs can be diffed. Tiles are always written in a fixed order.
+    pub canonical: bool,
+    /// The header of the archive
+    pub archive: ArchiveOptions,
+    /// Trades tile size for speed, however tiles are compressed
+    pub compression_preset: CompressionPreset,
+    /// Overrides the preset with an exact level. Higher is smaller and slower. GZip goes up to 9,
+    /// Brotli to 11, and zstd to 22.
+    pub compression_level: Option<i32>,
+    /// How many units across each vector tile is, from 512 to 8192. 4096 is usual. Lower extents
+    /// snap coordinates more coarsely, making smaller tiles that still look fine at low zooms.
+    pub extent: u32,
+    /// Check every vector tile follows the MVT 2.1 spec, failing on the first one that doesn't,
+    /// instead of writing tiles that some parsers choke on
+    pub strict: bool,
+    /// Where viewers should open the archive, in WGS84. By default, this is the middle of the tile
+    /// with the most features at the highest zoom.
+    pub center: Option<geo_types::Point<f64>>,
+    /// The zoom viewers should open the archive at. By default, this is the zoom where all the
+    /// features fit in about one tile.
+    pub center_zoom: Option<u8>,
+    /// Told how the run is going, from any thread. Nothing is reported without one.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub progress: Option<Box<dyn ProgressObserver>>,
+    /// Save each tile to this file as soon as it's made, so running again after an interruption
+    /// only makes the missing tiles. Resuming only checks the features' count and bounds and the
+    /// archive's header and metadata match, so delete the file after changing other options. It's
+    /// left behind once the run is done.
+    pub checkpoint: Option<std::path::PathBuf>,
+    /// How many threads to make tiles on. By default, rayon's global pool is used, with a thread
+    /// per core. Reading the input doesn't use these.
+    pub threads: Option<usize>,
+    /// Roughly how much memory loaded features can take up, in bytes. Past this, the rest are kept
+    /// in a temporary file and read back for each tile they're in, which is much slower. Finished
+    /// tiles are always kept on disk by `PMTilesWriter`. Can't be used with `dissolve_by_key`. With
+    /// `two_pass`, this is how much sorting can take up instead.
+    pub max_memory: Option<usize>,
+    /// Make tiles in two passes, like Planetiler, instead of indexing every feature in memory. The
+    /// first pass writes each feature to a temporary file once, with a small record for each tile
+    /// it's in, and sorts the records by tile in chunks. The second pass merges the chunks, making
+    /// each tile from its features in order. Zooms can't be guessed, and this can't be used with
+    /// `dissolve_by_key`, `grid_aggregation`, `density`, or `checkpoint`. Only functions sending
+    /// tiles out as they're made use this, like `layers_to_sink` and `geojson_to_pmtiles_writer`.
+    pub two_pass: bool,
+    /// Leave out features that can't be tiled, like ones with no geometry or an empty one, instead
+    /// of failing. Features that can't be read at all still fail.
+    pub skip_invalid: bool,
+    /// Called with each feature `skip_invalid` leaves out
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_skip: Option<Box<dyn Fn(SkippedFeature) + Send + Sync>>,
+    /// Called with each `Warning` as it happens, like skipped features or coordinates past the
+    /// edge of the map, so they can be shown somewhere other than the log
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_warning: Option<Box<dyn Fn(Warning) + Send + Sync>>,
+    /// Setting this to true from anywhere stops the run soon after, between features or tiles,
+    /// with `ConversionError::Cancelled`. Nothing is finished, and everything loaded is freed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called on each feature as it's loaded, after `filter`, with its WGS84 geometry and its
+    /// properties, which it can change. Features without properties get an empty map. It can
+    /// also drop the feature, and empty geometries are dropped too. Loading happens on one
+    /// thread, but this has to be `Sync`, so keep any state behind a `Mutex` or atomics.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub transform: Option<Box<FeatureTransform>>,
+    /// Called on each layer of each vector tile, once its features are sorted and before any are
+    /// encoded. It can reorder, change, remove, or add features, like to drop or merge them in
+    /// its own way. Size limits then leave out features from the end. It's called from many
+    /// threads at once.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub process_tile: Option<Box<TileHook>>,
+    /// Store these options in the metadata under `lines2pmtiles_options`, to show how the archive
+    /// was made. Callbacks like `progress` and `transform` are left out.
+    #[cfg(feature = "serde")]
+    pub record_options: bool,
+}
+
+/// See `Options::process_tile`. It gets the layer name, the tile's `[z, x, y]`, and the features.
+pub type TileHook = dyn Fn(&str, [u32; 3], &mut Vec<FeatureRef<'_>>) + Send + Sync;
+
+/// See `Options::transform`
+pub type FeatureTransform =
+    dyn Fn(&mut Geometry<f64>, &mut geojson::JsonObject) -> FeatureAction + Send + Sync;
+
+/// What `Options::transform` decided to do with a feature
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeatureAction {
+    Keep,
+    Drop,
+}
+
+impl Options {
+    /// Catches settings that can't work, before any features are read, naming the field to fix.
+    /// Every conversion calls this first, and so does `OptionsBuilder::build`.
+    pub fn validate(&self) -> Result<(), ConversionError> {
+        let no_layer_zooms = self.layers.is_empty()
+            || self
+                .layers
+                .iter()
+                .any(|layer| layer.zoom_levels.as_ref().is_none_or(Vec::is_empty));
+        if self.zoom_levels.is_empty()
+            && no_layer_zooms
+            && !self.guess_min_zoom
+            && !self.guess_max_zoom
+        {
+            return Err(invalid_options(
+                "`zoom_levels` is empty, so there are no tiles to make. Set some zooms, or turn on \
+                 `guess_max_zoom`.",
+            )
+            .into());
+        }
+        if let Some(zoom) = self.zoom_levels.iter().find(|zoom| **zoom > MAX_ZOOM) {
+            return Err(invalid_options(format!(
+                "`zoom_levels` has zoom {zoom}, past the highest zoom, {MAX_ZOOM}"
+            ))
+            .into());
+        }
+        if let Some(zoom) = self.center_zoom.filter(|zoom| *zoom as u32 > MAX_ZOOM) {
+            return Err(invalid_options(format!(
+                "`center_zoom` is {zoom}, past the highest zoom, {MAX_ZOOM}"
+            ))
+            .into());
+        }
+        if let Some(center) = self.center {
+            if !(-180.0..=180.0).contains(&center.x()) || !(-90.0..=90.0).contains(&center.y()) {
+                return Err(invalid_options(format!(
+                    "`center` is {},{}, which isn't a WGS84 longitude and latitude",
+                    center.x(),
+                    center.y()
+                ))
+                .into());
+            }
+        }
+        if self.limit_size_bytes == Some(0) {
+            return Err(invalid_options(
+                "`limit_size_bytes` is 0, so every tile would be empty. Unset it for no limit.",
+            )
+            .into());
+        }
+        if !self.extent.is_power_of_two() || !(512..=8192).contains(&self.extent) {
+            return Err(invalid_options(format!(
+                "`extent` is {}, but should be 512, 1024, 2048, 4096, or 8192",
+                self.extent
+            ))
+            .into());
+        }
+        if let Some(rate) = self.sample_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(invalid_options(format!(
+                    "`sample_rate` is {rate}, but should be from 0 to 1"
+                ))
+                .into());
+            }
+        }
+        if matches!(self.grid_aggregation, Some(ref grid) if grid.cells_per_tile == 0) {
+            return Err(invalid_options(
+                "`grid_aggregation.cells_per_tile` is 0, but tiles need at least one cell",
+            )
+            .into());
+        }
+        if self.grid_aggregation.is_some() && self.density.is_some() {
+            return Err(invalid_options(
+                "`grid_aggregation` only makes vector tiles, so it can't be used with `density`",
+            )
+            .into());
+        }
+        if self.max_memory.is_some() && self.dissolve_by_key.is_some() {
+            return Err(invalid_options(
+                "`dissolve_by_key` needs every feature in memory, so it can't be used with \
+                 `max_memory`",
+            )
+            .into());
+        }
+        if self.layers.is_empty() && self.layer_name.is_empty() {
+            return Err(invalid_options("`layer_name` is empty").into());
+        }
+        let mut layer_names = HashSet::new();
+        for layer in &self.layers {
+            if layer.name.is_empty() {
+                return Err(invalid_options("A layer in `layers` has an empty `name`").into());
+            }
+            if !layer_names.insert(&layer.name) {
+                return Err(invalid_options(format!(
+                    "`layers` has more than one layer called {}",
+                    layer.name
+                ))
+                .into());
+            }
+            if let Some(zoom) = layer.zoom_levels.iter().flatten().find(|z| **z > MAX_ZOOM) {
+                return Err(invalid_options(format!(
+                    "The `zoom_levels` of layer {} has zoom {zoom}, past the highest zoom, \
+                     {MAX_ZOOM}",
+                    layer.name
+                ))
+                .into());
+            }
+            if layer.limit_size_bytes == Some(0) {
+                return Err(invalid_options(format!(
+                    "The `limit_size_bytes` of layer {} is 0, so it would always be empty",
+                    layer.name
+                ))
+                .into());
+            }
+        }
+        if self.two_pass {
+            let needs_every_feature = [
+                (self.guess_min_zoom, "guess_min_zoom"),
+                (self.guess_max_zoom, "guess_max_zoom"),
+                (self.dissolve_by_key.is_some(), "dissolve_by_key"),
+                (self.grid_aggregation.is_some(), "grid_aggregation"),
+                (self.density.is_some(), "density"),
+                (self.checkpoint.is_some(), "checkpoint"),
+            ];
+            if let Some((_, field)) = needs_every_feature.iter().find(|(set, _)| *set) {
+                return Err(invalid_options(format!(
+                    "`two_pass` only sees each tile's own features, so it can't be used with \
+                     `{field}`"
+                ))
+                .into());
+            }
+        }
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        if self.checkpoint.is_some()
+            || self.max_memory.is_some()
+            || self.threads.is_some()
+            || self.two_pass
+        {
+            return Err(invalid_options(
+                "The browser has no files or threads for `checkpoint`, `max_memory`, `threads`, or \
+                 `two_pass`",
+            )
+            .into());
+        }
+        if self.threads == Some(0) {
+            return Err(invalid_options("`threads` is 0, but there must be at least one").into());
+        }
+        Ok(())
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match self.cancel {
+            Some(ref cancel) if cancel.load(Ordering::Relaxed) => {
+                Err(ConversionError::Cancelled.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn warn(&self, warning: Warning) {
+        if let Some(ref on_warning) = self.on_warning {
+            on_warning(warning);
+        }
+    }
+
+    fn report(&self, progress: Progress) {
+        if let Some(ref observer) = self.progress {
+            observer.progress(progress);
+        }
+    }
+}
+
+/// Which properties to put in the tiles, for `Options::attributes`. With the `serde` feature, this
+/// is `"all"`, `{ "only": [...] }`, or `{ "except": [...] }`.
+#[derive(Clone, Debug, Default)]
+pub enum Attributes {
+    #[default]
+    All,
+    /// Only these. If empty, features have no properties at all.
+    Only(HashSet<String>),
+    /// Everything but these
+    Except(HashSet<String>),
+}
+
+impl Attributes {
+    pub fn keeps(&self, key: &str) -> bool {
+        match self {
+            Attributes::All => true,
+            Attributes::Only(keys) => keys.contains(key),
+            Attributes::Except(keys) => !keys.contains(key),
+        }
+    }
+}
+
+/// A feature left out by `Options::skip_invalid`
+#[derive(Clone, Debug)]
+pub struct SkippedFeature {
+    pub layer: String,
+    /// Counting from 0, in the order the layer's features were read
+    pub index: usize,
+    pub reason: String,
+}
+
+/// How the PMTiles archive is laid out and what its header declares, apart from the zooms, bounds,
+/// and center worked out from the features
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ArchiveOptions {
+    /// What the tiles are declared as. By default, this is `TileType::Png` with
+    /// `Options::density` and `TileType::Mvt` otherwise. Setting anything else only works with
+    /// `TileType::Unknown`, for readers that sniff the tiles themselves.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_tile_type"))]
+    pub tile_type: Option<TileType>,
+    /// How to compress each vector tile. Most servers and clients expect `Compression::GZip`, like
+    /// tippecanoe makes. `Compression::ZStd` is quick and small, but check the tiles' readers
+    /// support it first. Raster tiles are never compressed again, and `Options::canonical`
+    /// overrides this.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::CompressionDef"))]
+    pub tile_compression: Compression,
+    /// How to compress the directories and metadata. They're only read once per archive, so
+    /// `Compression::GZip` keeps archives with millions of tiles from carrying megabytes of
+    /// directories. `Options::canonical` overrides this.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::CompressionDef"))]
+    pub internal_compression: Compression,
+    /// Write the tile data in order of tile ID and mark the archive as clustered, when it's
+    /// streamed out. See `PMTilesWriter::with_clustered`.
+    pub clustered: bool,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self {
+            tile_type: None,
+            tile_compression: Compression::GZip,
+            internal_compression: Compression::GZip,
+            clustered: true,
+        }
+    }
 }
 
+/// Settings for one layer of a multi-layer archive. Anything unset falls back to `Options`.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayerOptions {
+    pub name: String,
+    /// Which of the archive's zoom levels to include this layer in
+    pub zoom_levels: Option<Vec<u32>>,
+    /// Descending
+    pub sort_by_key: Option<String>,
+    /// Caps this layer's share of each tile. `Options::limit_size_bytes` still caps the whole tile.
+    pub limit_size_bytes: Option<usize>,
+    /// Only keep this layer's features this matches, as well as `Options::filter`. With
+    /// `Options::layers`, this picks which of the input's features go in the layer, so features
+    /// can end up in several layers or none.
+    pub filter: Option<Filter>,
+}
+
+impl LayerOptions {
+    /// A layer that uses all the defaults from `Options`
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            zoom_levels: None,
+            sort_by_key: None,
+            limit_size_bytes: None,
+            filter: None,
+        }
+    }
+}
+
+/// Reads GeoJSON from `geojson_input` and writes a PMTiles archive to `output`, starting from where
+/// it is now. Tiles are spooled to a temporary file as they're generated, so the archive never has
+/// to fit in memory. Returns the header and metadata written, without any tiles.
+pub fn geojson_to_pmtiles_writer<R: Read, W: Write + Seek + Send>(
+    geojson_input: R,
+    options: Options,
+    output: &mut W,
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    let features = FeatureReader::from_reader(geojson_input)
+        .features()
+        .map(|f| f.map_err(anyhow::Error::from));
+    layers_to_pmtiles_writer(input_layers(features, &options)?, options, output)
+}
+
+/// Like `geojson_to_pmtiles_writer`, but keeps every tile in memory and returns the whole archive.
+/// Only use this for small inputs.
 pub fn geojson_to_pmtiles<R: Read>(
     geojson_input: R,
     options: Options,
-) -> Result<PMTiles<Cursor<&'static [u8]>>> {
-    let (r_tree, feature_count, bbox, fields) =
-        load_features(FeatureReader::from_reader(geojson_input))?;
-
-    println!(
-        "bbox of {} features: {:?}",
-        HumanCount(feature_count as u64),
-        bbox
-    );
-
-    let mut pmtiles = PMTiles::new(TileType::Mvt, Compression::None);
-    pmtiles.min_longitude = bbox.min_lon;
-    pmtiles.min_latitude = bbox.min_lat;
-    pmtiles.max_longitude = bbox.max_lon;
-    pmtiles.max_latitude = bbox.max_lat;
-    pmtiles.min_zoom = options.zoom_levels[0] as u8;
-    pmtiles.max_zoom = *options.zoom_levels.last().unwrap() as u8;
-    let mut metadata = serde_json::json!(
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    geojson_features_to_pmtiles(
+        FeatureReader::from_reader(geojson_input)
+            .features()
+            .map(|f| f.map_err(anyhow::Error::from)),
+        options,
+    )
+}
+
+/// Like `geojson_to_pmtiles`, but takes features from any source, like the readers in `input`
+pub fn geojson_features_to_pmtiles(
+    features: impl Iterator<Item = Result<geojson::Feature>>,
+    options: Options,
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    layers_to_pmtiles(input_layers(features, &options)?, options)
+}
+
+/// The layers made from a single input. With `Options::layers`, the features are read up front,
+/// and each one is copied to every layer it matches.
+fn input_layers<'a>(
+    features: impl Iterator<Item = Result<geojson::Feature>> + 'a,
+    options: &Options,
+) -> Result<Vec<(LayerOptions, InputFeatures<'a>)>> {
+    if options.layers.is_empty() {
+        let layer = LayerOptions::new(options.layer_name.clone());
+        return Ok(vec![(layer, Box::new(features))]);
+    }
+    let mut split: Vec<Vec<Result<geojson::Feature>>> =
+        options.layers.iter().map(|_| Vec::new()).collect();
+    for (index, f) in features.enumerate() {
+        let f = f.map_err(|source| ConversionError::Parse {
+            layer: options
+                .layers
+                .iter()
+                .map(|layer| layer.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            index,
+            source,
+        })?;
+        options.check_cancelled()?;
+        for (layer, features) in options.layers.iter().zip(&mut split) {
+            if layer
+                .filter
+                .as_ref()
+                .is_none_or(|filter| filter.matches(&f))
+            {
+                features.push(Ok(f.clone()));
+            }
+        }
+    }
+    Ok(options
+        .layers
+        .iter()
+        .cloned()
+        .zip(split)
+        .map(|(layer, features)| (layer, Box::new(features.into_iter()) as InputFeatures))
+        .collect())
+}
+
+type InputFeatures<'a> = Box<dyn Iterator<Item = Result<geojson::Feature>> + 'a>;
+
+/// Like `geojson_features_to_pmtiles`, but takes WGS84 geometries and their properties that are
+/// already in memory, like a `Vec<(LineString<f64>, serde_json::Map<String, Value>)>`, instead of
+/// GeoJSON
+pub fn features_to_pmtiles<G: Into<Geometry<f64>>>(
+    features: impl IntoIterator<Item = (G, geojson::JsonObject)>,
+    options: Options,
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    geojson_features_to_pmtiles(
+        features.into_iter().map(|(geometry, properties)| {
+            Ok(geojson::Feature {
+                bbox: None,
+                geometry: Some(geojson::Geometry::from(&geometry.into())),
+                id: None,
+                properties: Some(properties),
+                foreign_members: None,
+            })
+        }),
+        options,
+    )
+}
+
+/// Like `features_to_pmtiles`, but the properties can be anything implementing `Properties`, like
+/// a `Vec<(&str, PropertyValue)>` for a database row. They're only copied once, while loading.
+pub fn properties_to_pmtiles<G: Into<Geometry<f64>>, P: Properties>(
+    features: impl IntoIterator<Item = (G, P)>,
+    options: Options,
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    features_to_pmtiles(
+        features
+            .into_iter()
+            .map(|(geometry, properties)| (geometry, properties::to_json_object(&properties))),
+        options,
+    )
+}
+
+/// Like `geojson_features_to_pmtiles`, but each input becomes its own layer, with its own settings.
+/// `Options::layer_name` is ignored, and all other options apply to every layer. The archive covers
+/// every layer's zoom levels.
+pub fn layers_to_pmtiles<I: Iterator<Item = Result<geojson::Feature>>>(
+    inputs: Vec<(LayerOptions, I)>,
+    options: Options,
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    Ok(layers_to_pmtiles_with_stats(inputs, options)?.0)
+}
+
+type ArchiveWithStats = (PMTiles<Cursor<&'static [u8]>>, ConversionStats);
+
+/// Like `layers_to_pmtiles`, but also says what happened. Nothing is written yet, so
+/// `ConversionStats::writing` is zero.
+pub fn layers_to_pmtiles_with_stats<I: Iterator<Item = Result<geojson::Feature>>>(
+    inputs: Vec<(LayerOptions, I)>,
+    options: Options,
+) -> Result<ArchiveWithStats, ConversionError> {
+    let loaded = load_inputs(inputs, &options)?;
+    tile_loaded(borrow_layers(&loaded), options)
+}
+
+fn tile_loaded(
+    layers: Vec<(LayerOptions, &LoadedLayer)>,
+    options: Options,
+) -> Result<ArchiveWithStats, ConversionError> {
+    let (tiler, mut pmtiles) = Tiler::new(layers, options)?;
+    tiler.send_tiles(&tiler.zoom_levels, &tiler.bbox, &mut pmtiles)?;
+    Ok((pmtiles, tiler.stats(Duration::ZERO)))
+}
+
+/// Like `layers_to_pmtiles`, but sends each tile to `sink` as soon as it's generated, then
+/// finishes it. Returns an archive with the header and metadata, but no tiles.
+pub fn layers_to_sink<I: Iterator<Item = Result<geojson::Feature>>>(
+    inputs: Vec<(LayerOptions, I)>,
+    options: Options,
+    sink: &mut (impl TileSink + Send),
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    Ok(layers_to_sink_with_stats(inputs, options, sink)?.0)
+}
+
+/// Like `layers_to_sink`, but also says what happened
+pub fn layers_to_sink_with_stats<I: Iterator<Item = Result<geojson::Feature>>>(
+    inputs: Vec<(LayerOptions, I)>,
+    options: Options,
+    sink: &mut (impl TileSink + Send),
+) -> Result<ArchiveWithStats, ConversionError> {
+    if options.two_pass {
+        return Ok(sorted::layers_to_sink_sorted(inputs, options, sink)?);
+    }
+    let loaded = load_inputs(inputs, &options)?;
+    sink_loaded(borrow_layers(&loaded), options, sink)
+}
+
+fn sink_loaded(
+    layers: Vec<(LayerOptions, &LoadedLayer)>,
+    options: Options,
+    sink: &mut (impl TileSink + Send),
+) -> Result<ArchiveWithStats, ConversionError> {
+    let pipeline = TilePipeline::plan(layers, options)?;
+    let stats = pipeline.write(sink)?;
+    Ok((pipeline.into_archive(), stats))
+}
+
+/// Reads and indexes the features for one layer once, to make any number of archives from with
+/// `loaded_layers_to_pmtiles` and `loaded_layers_to_sink`, without reading them again. Only the
+/// options deciding which features are kept and what's in them matter here: `filter`,
+/// `attributes`, `mask`, `direction`, `dissolve_by_key`, `sort_by_key`, `max_memory`,
+/// `skip_invalid`, `on_skip`, `on_warning`, and `cancel`. The layer's own `sort_by_key` wins.
+pub fn load_features(
+    features: impl Iterator<Item = Result<geojson::Feature>>,
+    layer: &LayerOptions,
+    options: &Options,
+) -> Result<LoadedLayer, ConversionError> {
+    options.validate()?;
+    Ok(load_layer(features, layer, options, &mut 0)?)
+}
+
+/// Like `layers_to_pmtiles`, but with layers from `load_features` or `LoadedLayer::from_features`.
+/// The `LayerOptions` here decide the name, zooms, and size limit of each layer, and can differ
+/// from the ones used to load it. Options that only matter while loading are ignored.
+pub fn loaded_layers_to_pmtiles(
+    layers: Vec<(LayerOptions, &LoadedLayer)>,
+    options: Options,
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    Ok(tile_loaded(layers, options)?.0)
+}
+
+/// Like `layers_to_sink`, but with layers from `load_features`, like `loaded_layers_to_pmtiles`
+pub fn loaded_layers_to_sink(
+    layers: Vec<(LayerOptions, &LoadedLayer)>,
+    options: Options,
+    sink: &mut (impl TileSink + Send),
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    Ok(sink_loaded(layers, options, sink)?.0)
+}
+
+/// Makes one vector tile from a layer, sorting and limiting its features exactly like a whole run
+/// would, for making tiles on demand. `tile` is `[z, x, y]`. Returns the uncompressed MVT, or nothing
+/// if the tile is empty or the layer isn't shown at this zoom. Only vector tiles are made, so
+/// `grid_aggregation` and `density` are ignored, like the options that only matter while loading.
+pub fn render_tile(
+    tile: [u32; 3],
+    features: &LoadedLayer,
+    layer: &LayerOptions,
+    options: &Options,
+) -> Result<Option<Vec<u8>>, ConversionError> {
+    let [z, x, y] = tile;
+    let tile_id = TileId::new(x, y, z)
+        .map_err(|_| ConversionError::InvalidOptions(format!("There's no tile {z}/{x}/{y}")))?;
+    let zoom_levels = layer
+        .zoom_levels
+        .clone()
+        .unwrap_or_else(|| options.zoom_levels.clone());
+    if !zoom_levels.contains(&z) {
+        return Ok(None);
+    }
+    let layer = Layer {
+        name: layer.name.clone(),
+        features,
+        zoom_levels,
+        sort_by_key: layer
+            .sort_by_key
+            .clone()
+            .or_else(|| options.sort_by_key.clone()),
+        limit_size_bytes: layer.limit_size_bytes,
+    };
+    let in_tile = features.features_intersecting(&tile_envelope(tile_id))?;
+    let encode = || -> Result<Option<Vec<u8>>> {
+        let Some((_, tile)) = make_tile(tile_id, vec![(&layer, in_tile)], options)? else {
+            return Ok(None);
+        };
+        let bytes = tile.to_bytes()?;
+        if options.strict {
+            validate::validate_tile(&bytes)
+                .with_context(|| format!("Tile {tile_id} breaks the MVT spec"))?;
+        }
+        Ok(Some(bytes))
+    };
+    encode().map_err(|source| ConversionError::Encoding {
+        tile: format!("{z}/{x}/{y}"),
+        source,
+    })
+}
+
+/// Like `layers_to_pmtiles`, but writes the archive to `output` as tiles are generated, instead of
+/// holding every tile in memory. Tiles are spooled to a temporary file until the end. Returns an
+/// archive with the same header and metadata, but no tiles, for describing the output.
+pub fn layers_to_pmtiles_writer<I: Iterator<Item = Result<geojson::Feature>>>(
+    inputs: Vec<(LayerOptions, I)>,
+    options: Options,
+    output: &mut (impl Write + Send),
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    let clustered = options.archive.clustered;
+    layers_to_sink(
+        inputs,
+        options,
+        &mut PMTilesSink::new(output)?.with_clustered(clustered),
+    )
+}
+
+/// Like `layers_to_pmtiles_writer`, but splits the archive by zoom level, writing the tiles for
+/// each range of zooms to its own output, like z0-9 and z10-14. The features are only loaded once.
+/// Returns the header and metadata of each output, in the same order.
+pub fn layers_to_pmtiles_shards<I: Iterator<Item = Result<geojson::Feature>>, W: Write + Send>(
+    inputs: Vec<(LayerOptions, I)>,
+    options: Options,
+    shards: &mut [(RangeInclusive<u32>, W)],
+) -> Result<Vec<PMTiles<Cursor<&'static [u8]>>>, ConversionError> {
+    let loaded = load_inputs(inputs, &options)?;
+    let (tiler, archive) = Tiler::new(borrow_layers(&loaded), options)?;
+    let mut results = Vec::new();
+    for (zooms, output) in shards {
+        let zoom_levels: Vec<u32> = tiler
+            .zoom_levels
+            .iter()
+            .cloned()
+            .filter(|z| zooms.contains(z))
+            .collect();
+        let (Some(min_zoom), Some(max_zoom)) = (zoom_levels.first(), zoom_levels.last()) else {
+            return Err(ConversionError::InvalidOptions(format!(
+                "No zoom levels to tile in the range {zooms:?}"
+            )));
+        };
+
+        let mut shard = PMTiles::new(archive.tile_type, archive.tile_compression);
+        shard.internal_compression = archive.internal_compression;
+        shard.min_longitude = archive.min_longitude;
+        shard.min_latitude = archive.min_latitude;
+        shard.max_longitude = archive.max_longitude;
+        shard.max_latitude = archive.max_latitude;
+        shard.min_zoom = *min_zoom as u8;
+        shard.max_zoom = *max_zoom as u8;
+        shard.center_longitude = archive.center_longitude;
+        shard.center_latitude = archive.center_latitude;
+        shard.center_zoom = archive.center_zoom.clamp(shard.min_zoom, shard.max_zoom);
+        shard.meta_data = archive
+            .meta_data
+            .clone()
+            .map(|json| clamp_vector_layers(json, *min_zoom, *max_zoom));
+
+        let mut sink = PMTilesSink::new(output)?.with_clustered(tiler.options.archive.clustered);
+        tiler.send_tiles(&zoom_levels, &tiler.bbox, &mut sink)?;
+        tiler.options.report(Progress::Writing);
+        sink.finish(&shard)?;
+        results.push(shard);
+    }
+    Ok(results)
+}
+
+/// Updates an `existing` archive, only regenerating tiles touching `changed_area` and copying the
+/// rest as they are. `inputs` must have every current feature in the changed area, not just the
+/// changed ones, and removed features should be included in the area. The metadata comes from
+/// `inputs`. Writes the result to `output` like `layers_to_pmtiles_writer`.
+pub fn update_pmtiles<R: Read + Seek, I: Iterator<Item = Result<geojson::Feature>>>(
+    mut existing: PMTiles<R>,
+    inputs: Vec<(LayerOptions, I)>,
+    options: Options,
+    changed_area: geo_types::Rect<f64>,
+    output: &mut impl Write,
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    let loaded = load_inputs(inputs, &options)?;
+    let (tiler, mut archive) = Tiler::new(borrow_layers(&loaded), options)?;
+    let changed = BBox::from(&changed_area);
+    let changed_tiles: HashMap<u32, (u32, u32, u32, u32)> = tiler
+        .zoom_levels
+        .iter()
+        .map(|z| (*z, changed.to_tiles(*z)))
+        .collect();
+
+    let mut writer = PMTilesWriter::new()?.with_clustered(tiler.options.archive.clustered);
+    copy_existing_tiles(&mut existing, &archive, &mut writer, |z, x, y| {
+        let Some((x1, y1, x2, y2)) = changed_tiles.get(&(z as u32)) else {
+            return true;
+        };
+        !((*x1 as u64..=*x2 as u64).contains(&x) && (*y1 as u64..=*y2 as u64).contains(&y))
+    })?;
+
+    let writer = Mutex::new(writer);
+    tiler.for_each_tile(&tiler.zoom_levels, &changed, |tile_id, data| {
+        writer.lock().unwrap().add_tile(tile_id, &data)
+    })?;
+
+    cover_existing(&mut archive, &existing);
+    tiler.options.report(Progress::Writing);
+    writer.into_inner().unwrap().finish(&archive, output)?;
+    Ok(archive)
+}
+
+/// Regenerates every tile in the zoom levels set by `options` and `inputs`, copying the tiles at
+/// all other zoom levels from `existing` as they are. The zoom levels of each layer in the
+/// metadata are widened to cover both. Writes the result to `output` like
+/// `layers_to_pmtiles_writer`.
+pub fn regenerate_zooms<R: Read + Seek, I: Iterator<Item = Result<geojson::Feature>>>(
+    mut existing: PMTiles<R>,
+    inputs: Vec<(LayerOptions, I)>,
+    options: Options,
+    output: &mut impl Write,
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    let loaded = load_inputs(inputs, &options)?;
+    let (tiler, mut archive) = Tiler::new(borrow_layers(&loaded), options)?;
+
+    let mut writer = PMTilesWriter::new()?.with_clustered(tiler.options.archive.clustered);
+    copy_existing_tiles(&mut existing, &archive, &mut writer, |z, _, _| {
+        !tiler.zoom_levels.contains(&(z as u32))
+    })?;
+
+    let writer = Mutex::new(writer);
+    tiler.for_each_tile(&tiler.zoom_levels, &tiler.bbox, |tile_id, data| {
+        writer.lock().unwrap().add_tile(tile_id, &data)
+    })?;
+
+    cover_existing(&mut archive, &existing);
+    if let (Some(metadata), Some(old)) = (archive.meta_data.as_mut(), existing.meta_data.as_ref()) {
+        merge_vector_layers(metadata, old);
+    }
+    tiler.options.report(Progress::Writing);
+    writer.into_inner().unwrap().finish(&archive, output)?;
+    Ok(archive)
+}
+
+/// Combines PMTiles archives with the same tile type into one, streamed to `output`. Where several
+/// archives have the same tile, the last one wins. Tiles are recompressed to match the first
+/// archive, and its header is widened to cover all of them. Returns the header and metadata.
+pub fn merge_pmtiles<R: Read + Seek>(
+    mut inputs: Vec<PMTiles<R>>,
+    output: &mut impl Write,
+) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+    let Some(first) = inputs.first() else {
+        return Err(ConversionError::InvalidOptions(
+            "There are no archives to merge".to_string(),
+        ));
+    };
+    let mut archive = PMTiles::new(first.tile_type, first.tile_compression);
+    archive.internal_compression = first.internal_compression;
+    archive.min_zoom = first.min_zoom;
+    archive.max_zoom = first.max_zoom;
+    archive.center_zoom = first.center_zoom;
+    archive.min_longitude = first.min_longitude;
+    archive.min_latitude = first.min_latitude;
+    archive.max_longitude = first.max_longitude;
+    archive.max_latitude = first.max_latitude;
+    archive.center_longitude = first.center_longitude;
+    archive.center_latitude = first.center_latitude;
+    archive.meta_data = first.meta_data.clone();
+    for existing in &inputs[1..] {
+        cover_existing(&mut archive, existing);
+        match (archive.meta_data.as_mut(), existing.meta_data.as_ref()) {
+            (Some(metadata), Some(old)) => merge_vector_layers(metadata, old),
+            (None, Some(old)) => archive.meta_data = Some(old.clone()),
+            _ => {}
+        }
+    }
+
+    let mut writer = PMTilesWriter::new()?;
+    let mut copied = HashSet::new();
+    for existing in inputs.iter_mut().rev() {
+        copy_existing_tiles(existing, &archive, &mut writer, |z, x, y| {
+            !copied.contains(&get_tile_id(z, x, y))
+        })?;
+        copied.extend(existing.tile_ids().into_iter().cloned());
+    }
+    writer.finish(&archive, output)?;
+    Ok(archive)
+}
+
+/// Copies every tile from `existing` that `keep(z, x, y)` is true for, recompressing it to match
+/// `archive` if needed
+fn copy_existing_tiles<R: Read + Seek>(
+    existing: &mut PMTiles<R>,
+    archive: &PMTiles<Cursor<&'static [u8]>>,
+    writer: &mut PMTilesWriter,
+    keep: impl Fn(u8, u64, u64) -> bool,
+) -> Result<()> {
+    if existing.tile_type != archive.tile_type {
+        return Err(invalid_options(format!(
+            "The existing archive has {:?} tiles, but the new tiles are {:?}",
+            existing.tile_type, archive.tile_type
+        )));
+    }
+    let mut kept = 0;
+    let mut tile_ids: Vec<u64> = existing.tile_ids().into_iter().cloned().collect();
+    tile_ids.sort();
+    tile_ids.retain(|tile_id| pmtiles2::util::zxy(*tile_id).is_ok_and(|(z, x, y)| keep(z, x, y)));
+    // Reading has to happen in order, but recompressing can happen on every thread. Batches
+    // keep memory bounded.
+    for batch in tile_ids.chunks(1024) {
+        let mut tiles = Vec::new();
+        for tile_id in batch {
+            if let Some(data) = existing.get_tile_by_id(*tile_id)? {
+                tiles.push((*tile_id, data));
+            }
+        }
+        if existing.tile_compression != archive.tile_compression {
+            tiles
+                .par_iter_mut()
+                .try_for_each(|(_, data)| -> Result<()> {
+                    *data = compress_all(
+                        archive.tile_compression,
+                        &decompress_all(existing.tile_compression, data)?,
+                    )?;
+                    Ok(())
+                })?;
+        }
+        for (tile_id, data) in tiles {
+            writer.add_tile(tile_id, &data)?;
+            kept += 1;
+        }
+    }
+    log::info!("Keeping {} existing tiles", HumanCount(kept));
+    Ok(())
+}
+
+/// Widens the zooms and bounds in the header to cover everything still in the existing archive
+fn cover_existing<R>(archive: &mut PMTiles<Cursor<&'static [u8]>>, existing: &PMTiles<R>) {
+    archive.min_zoom = archive.min_zoom.min(existing.min_zoom);
+    archive.max_zoom = archive.max_zoom.max(existing.max_zoom);
+    archive.min_longitude = archive.min_longitude.min(existing.min_longitude);
+    archive.min_latitude = archive.min_latitude.min(existing.min_latitude);
+    archive.max_longitude = archive.max_longitude.max(existing.max_longitude);
+    archive.max_latitude = archive.max_latitude.max(existing.max_latitude);
+}
+
+/// Widens the zooms of each layer in `metadata` to cover the same layer in `old`, and keeps layers
+/// only in `old`
+fn merge_vector_layers(metadata: &mut Value, old: &Value) {
+    let Some(Value::Array(old_layers)) = old.get("vector_layers") else {
+        return;
+    };
+    let Some(Value::Array(vector_layers)) = metadata.get_mut("vector_layers") else {
+        return;
+    };
+    for old_layer in old_layers {
+        match vector_layers
+            .iter_mut()
+            .find(|layer| layer["id"] == old_layer["id"])
         {
-            "vector_layers": [
+            Some(layer) => {
+                if let (Some(new), Some(old)) =
+                    (layer["minzoom"].as_u64(), old_layer["minzoom"].as_u64())
+                {
+                    layer["minzoom"] = new.min(old).into();
+                }
+                if let (Some(new), Some(old)) =
+                    (layer["maxzoom"].as_u64(), old_layer["maxzoom"].as_u64())
                 {
-                    "id": options.layer_name,
-                    "minzoom": pmtiles.min_zoom,
-                    "maxzoom": pmtiles.max_zoom,
-                    "fields": fields,
+                    layer["maxzoom"] = new.max(old).into();
                 }
-            ]
+            }
+            None => vector_layers.push(old_layer.clone()),
         }
-    );
-    if let Some(description) = options.description.clone() {
-        metadata
-            .as_object_mut()
-            .unwrap()
-            .insert("description".to_string(), description.into());
     }
-    pmtiles.meta_data = Some(metadata);
+}
+
+/// Limits each layer's zooms in the metadata to one shard, removing layers outside it
+fn clamp_vector_layers(mut metadata: Value, min_zoom: u32, max_zoom: u32) -> Value {
+    if let Some(Value::Array(vector_layers)) = metadata.get_mut("vector_layers") {
+        vector_layers.retain_mut(|layer| {
+            let layer_min = layer["minzoom"].as_u64().unwrap_or(0).max(min_zoom as u64);
+            let layer_max = layer["maxzoom"].as_u64().unwrap_or(0).min(max_zoom as u64);
+            layer["minzoom"] = layer_min.into();
+            layer["maxzoom"] = layer_max.into();
+            layer_min <= layer_max
+        });
+    }
+    metadata
+}
+
+/// Features loaded for every layer, ready to generate tiles from
+struct Tiler<'a> {
+    layers: Vec<Layer<'a>>,
+    bbox: BBox,
+    /// Sorted, across all layers
+    zoom_levels: Vec<u32>,
+    /// See `DensityRaster::scale`
+    density_scale: f64,
+    /// What the tiles are actually compressed with
+    compressor: TileCompressor,
+    /// Tells whether a checkpoint is from the same run
+    fingerprint: u32,
+    /// Set by `Options::threads`
+    pool: Option<rayon::ThreadPool>,
+    options: Options,
+    /// Everything known once the features are loaded
+    stats: ConversionStats,
+    recorder: TileRecorder,
+}
+
+impl<'a> Tiler<'a> {
+    /// Also returns an archive with no tiles yet, but with the header and metadata filled out
+    fn new(
+        inputs: Vec<(LayerOptions, &'a LoadedLayer)>,
+        options: Options,
+    ) -> Result<(Self, PMTiles<Cursor<&'static [u8]>>)> {
+        options.validate()?;
+        check_layer_count(inputs.len(), &options)?;
+
+        let start = Instant::now();
+        let mut stats = ConversionStats::default();
+        let mut layers = Vec::new();
+        let mut feature_count = 0;
+        let mut bbox = BBox::empty();
+        for (layer_options, features) in inputs {
+            feature_count += features.num_features;
+            bbox.union(&features.bbox);
+            stats.features_read += features.stats.features_read;
+            stats.features_skipped += features.stats.features_skipped;
+            stats.features_dropped += features.stats.features_dropped;
+            stats.loading += features.stats.loading;
+            // Layers without their own zooms get the defaults, once they can be guessed
+            let layer_zoom_levels = layer_options.zoom_levels.unwrap_or_default();
+            layers.push(Layer {
+                name: layer_options.name,
+                features,
+                zoom_levels: layer_zoom_levels,
+                sort_by_key: layer_options
+                    .sort_by_key
+                    .or_else(|| options.sort_by_key.clone()),
+                limit_size_bytes: layer_options.limit_size_bytes,
+            });
+        }
+
+        log::info!(
+            "bbox of {} features: {:?}",
+            HumanCount(feature_count as u64),
+            bbox
+        );
+        if let Some(ref rect) = options.bbox {
+            bbox.intersect(rect);
+            if bbox.is_empty() {
+                return Err(invalid_options(format!(
+                    "None of the features are inside the bbox {},{},{},{}",
+                    rect.min().x,
+                    rect.min().y,
+                    rect.max().x,
+                    rect.max().y
+                )));
+            }
+        }
+
+        let default_zoom_levels = if options.guess_min_zoom || options.guess_max_zoom {
+            let zoom_levels = guess_zooms(&layers, &bbox, &options)?;
+            log::info!(
+                "Guessed zooms {} to {}",
+                zoom_levels.first().unwrap(),
+                zoom_levels.last().unwrap()
+            );
+            zoom_levels
+        } else {
+            options.zoom_levels.clone()
+        };
+        let mut zoom_levels = BTreeSet::new();
+        for layer in &mut layers {
+            if layer.zoom_levels.is_empty() {
+                layer.zoom_levels = default_zoom_levels.clone();
+            }
+            if layer.zoom_levels.is_empty() {
+                return Err(invalid_options(format!(
+                    "Layer {} has no zoom levels",
+                    layer.name
+                )));
+            }
+            zoom_levels.extend(layer.zoom_levels.iter().cloned());
+        }
+        let zoom_levels: Vec<u32> = zoom_levels.into_iter().collect();
+
+        let tile_type = if options.density.is_some() {
+            TileType::Png
+        } else {
+            TileType::Mvt
+        };
+        let tile_type = match options.archive.tile_type {
+            None => tile_type,
+            Some(declared) if declared == tile_type || declared == TileType::Unknown => declared,
+            Some(declared) => {
+                return Err(invalid_options(format!(
+                    "The tiles are {tile_type:?}, so they can't be declared as {declared:?}"
+                )))
+            }
+        };
+        let compressor = if options.density.is_some() || options.canonical {
+            TileCompressor::none()
+        } else {
+            TileCompressor::new(
+                options.archive.tile_compression,
+                options.compression_preset,
+                options.compression_level,
+            )?
+        };
+        let mut pmtiles = PMTiles::new(tile_type, compressor.compression);
+        pmtiles.internal_compression = if options.canonical {
+            Compression::None
+        } else {
+            options.archive.internal_compression
+        };
+        pmtiles.min_longitude = bbox.min_lon;
+        pmtiles.min_latitude = bbox.min_lat;
+        pmtiles.max_longitude = bbox.max_lon;
+        pmtiles.max_latitude = bbox.max_lat;
+        pmtiles.min_zoom = zoom_levels[0] as u8;
+        pmtiles.max_zoom = *zoom_levels.last().unwrap() as u8;
+        let center = options
+            .center
+            .or_else(|| densest_tile_center(&layers, &bbox, pmtiles.max_zoom as u32))
+            .unwrap_or_else(|| {
+                geo_types::Point::new(
+                    (bbox.min_lon + bbox.max_lon) / 2.0,
+                    (bbox.min_lat + bbox.max_lat) / 2.0,
+                )
+            });
+        pmtiles.center_longitude = center.x();
+        pmtiles.center_latitude = center.y();
+        pmtiles.center_zoom = options
+            .center_zoom
+            .unwrap_or_else(|| zoom_to_fit(&bbox))
+            .clamp(pmtiles.min_zoom, pmtiles.max_zoom);
+        let mut vector_layers = Vec::new();
+        if let Some(ref grid) = options.grid_aggregation {
+            vector_layers.push(serde_json::json!({
+                "id": grid.layer_name,
+                "minzoom": pmtiles.min_zoom,
+                "maxzoom": grid.max_zoom.min(pmtiles.max_zoom as u32),
+                "fields": {
+                    grid.value_key(&options): "",
+                    "num_features": "",
+                },
+            }));
+        }
+        let raw_min_zoom = match options.grid_aggregation {
+            Some(ref grid) => (grid.max_zoom + 1).max(pmtiles.min_zoom as u32),
+            None => pmtiles.min_zoom as u32,
+        };
+        for layer in &layers {
+            let min_zoom = layer.zoom_levels.iter().min().unwrap().max(&raw_min_zoom);
+            let max_zoom = layer.zoom_levels.iter().max().unwrap();
+            if min_zoom <= max_zoom {
+                vector_layers.push(serde_json::json!({
+                    "id": layer.name,
+                    "minzoom": min_zoom,
+                    "maxzoom": max_zoom,
+                    "fields": layer.features.fields,
+                }));
+            }
+        }
+        let mut metadata = if options.density.is_some() {
+            serde_json::json!({})
+        } else {
+            serde_json::json!({ "vector_layers": vector_layers })
+        };
+        if let Some(description) = options.description.clone() {
+            metadata
+                .as_object_mut()
+                .unwrap()
+                .insert("description".to_string(), description.into());
+        }
+        #[cfg(feature = "serde")]
+        if options.record_options {
+            metadata.as_object_mut().unwrap().insert(
+                "lines2pmtiles_options".to_string(),
+                serde_json::to_value(&options)?,
+            );
+        }
+        pmtiles.meta_data = Some(metadata);
+
+        let density_scale = options
+            .density
+            .as_ref()
+            .map(|raster| raster.scale(&layers))
+            .transpose()?
+            .unwrap_or(0.0);
+        let pool = match options.threads {
+            Some(threads) => Some(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()?,
+            ),
+            None => None,
+        };
+        let fingerprint = crc32fast::hash(
+            format!(
+                "{feature_count} {:?} {:?} {zoom_levels:?} {:?} {}",
+                pmtiles.tile_type,
+                pmtiles.tile_compression,
+                [bbox.min_lon, bbox.min_lat, bbox.max_lon, bbox.max_lat],
+                pmtiles.meta_data.clone().unwrap_or_default(),
+            )
+            .as_bytes(),
+        );
+
+        Ok((
+            Self {
+                layers,
+                bbox,
+                zoom_levels,
+                density_scale,
+                compressor,
+                fingerprint,
+                pool,
+                options,
+                stats: ConversionStats {
+                    loading: stats.loading + start.elapsed(),
+                    ..stats
+                },
+                recorder: TileRecorder::default(),
+            },
+            pmtiles,
+        ))
+    }
+
+    fn stats(&self, writing: Duration) -> ConversionStats {
+        let mut stats = self.stats.clone();
+        self.recorder.finish(&mut stats);
+        stats.writing = writing;
+        stats
+    }
+
+    /// Runs `f` on the pool from `Options::threads`, if there is one, so parallel iterators in it
+    /// use that pool
+    fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        match self.pool {
+            Some(ref pool) => pool.install(f),
+            None => f(),
+        }
+    }
+
+    /// Adds the tiles in `zoom_levels` covering `bbox` to `sink`, without finishing it
+    fn send_tiles(
+        &self,
+        zoom_levels: &[u32],
+        bbox: &BBox,
+        sink: &mut (impl TileSink + Send),
+    ) -> Result<()> {
+        let sink = Mutex::new(sink);
+        self.for_each_tile(zoom_levels, bbox, |tile_id, data| {
+            sink.lock().unwrap().add_tile(tile_id, data)
+        })
+    }
+
+    /// Calls `add_tile` with the encoded bytes of each non-empty tile in `zoom_levels` covering
+    /// `bbox` as soon as it's done, from many threads and in no particular order. Tiles are
+    /// compressed on the same thread they're made on, so only writing them out is serialized.
+    fn for_each_tile(
+        &self,
+        zoom_levels: &[u32],
+        bbox: &BBox,
+        add_tile: impl Fn(u64, Vec<u8>) -> Result<()> + Sync,
+    ) -> Result<()> {
+        let mut tiles = tiles_covering(zoom_levels, bbox)?;
+        let tiles_total = tiles.len();
+        let checkpoint = match self.options.checkpoint {
+            Some(ref path) => {
+                let wanted = tiles
+                    .iter()
+                    .map(|tile_id| pmtiles_tile_id(*tile_id))
+                    .collect();
+                let (checkpoint, done) =
+                    Checkpoint::open(path, self.fingerprint, &wanted, &add_tile)?;
+                if !done.is_empty() {
+                    log::info!(
+                        "Resuming from {}, with {} of {} tiles already done",
+                        path.display(),
+                        HumanCount(done.len() as u64),
+                        HumanCount(tiles_total as u64)
+                    );
+                    tiles.retain(|tile_id| !done.contains(&pmtiles_tile_id(*tile_id)));
+                }
+                Some(checkpoint)
+            }
+            None => None,
+        };
+        let tiles_done = AtomicUsize::new(tiles_total - tiles.len());
+        let bytes = AtomicU64::new(0);
+        self.options.report(Progress::Tiling {
+            tiles_done: tiles_done.load(Ordering::Relaxed),
+            tiles_total,
+            bytes: 0,
+        });
+        let start = Instant::now();
+        // Tiles come a zoom at a time, so only one zoom's buckets are held at once
+        for tiles in tiles.chunk_by(|a, b| a.z() == b.z()) {
+            let zoom = tiles[0].z();
+            let buckets = self.bucket_features(zoom, bbox.to_tiles(zoom));
+            self.install(|| {
+                tiles.par_iter().try_for_each(|tile_id| -> Result<()> {
+                    let tile_id = *tile_id;
+                    self.options.check_cancelled()?;
+                    let features = self.features_in_bucket(tile_id, &buckets)?;
+                    let tile = self.encode_tile_features(tile_id, features)?;
+                    let tile_bytes = tile.as_ref().map_or(0, |(_, data)| data.len() as u64);
+                    if let Some(ref checkpoint) = checkpoint {
+                        checkpoint.record(
+                            pmtiles_tile_id(tile_id),
+                            tile.as_ref().map(|(_, data)| data.as_slice()),
+                        )?;
+                    }
+                    if let Some((pmtiles_tile_id, data)) = tile {
+                        self.recorder.add_tile(tile_id.z(), data.len());
+                        add_tile(pmtiles_tile_id, data)?;
+                    }
+                    self.options.report(Progress::Tiling {
+                        tiles_done: tiles_done.fetch_add(1, Ordering::Relaxed) + 1,
+                        tiles_total,
+                        bytes: bytes.fetch_add(tile_bytes, Ordering::Relaxed) + tile_bytes,
+                    });
+                    Ok(())
+                })
+            })?;
+        }
+        self.recorder.add_tiling(start.elapsed());
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Which tiles at `zoom` each in-memory feature touches, worked out from its envelope, so
+    /// making each tile doesn't need to search the R-tree. Only tiles in the range from
+    /// `BBox::to_tiles` get buckets. Each bucket has the index of the feature's layer, in the order
+    /// the R-tree stores them, which isn't always the order searching it finds them in, so ties in
+    /// `sort_by_key` can come out differently than from `features_in_tile`. Features touching more
+    /// than `MAX_BUCKETS_PER_FEATURE` tiles aren't bucketed, so tiles they touch search the R-tree
+    /// instead.
+    fn bucket_features(&self, zoom: u32, (x1, y1, x2, y2): (u32, u32, u32, u32)) -> Buckets<'a> {
+        let num_tiles = 1 << zoom;
+        let mut buckets: HashMap<_, Vec<_>> = HashMap::new();
+        let mut wide = Vec::new();
+        for (index, layer) in self.layers.iter().enumerate() {
+            if !layer.zoom_levels.contains(&zoom) {
+                continue;
+            }
+            let features: &'a LoadedLayer = layer.features;
+            for feature in &features.tree {
+                let envelope = feature.envelope();
+                // Tile y counts from the top
+                let [min_x, min_y] = math::web_mercator_to_tile(
+                    [envelope.lower()[0], envelope.upper()[1]],
+                    num_tiles,
+                );
+                let [max_x, max_y] = math::web_mercator_to_tile(
+                    [envelope.upper()[0], envelope.lower()[1]],
+                    num_tiles,
+                );
+                let xs = math::tiles_touching(min_x, max_x, num_tiles);
+                let ys = math::tiles_touching(min_y, max_y, num_tiles);
+                let xs = *xs.start().max(&x1)..=*xs.end().min(&x2);
+                let ys = *ys.start().max(&y1)..=*ys.end().min(&y2);
+                if xs.clone().count() * ys.clone().count() > MAX_BUCKETS_PER_FEATURE {
+                    wide.push(Rectangle::from_aabb(envelope));
+                    continue;
+                }
+                for x in xs {
+                    for y in ys.clone() {
+                        buckets.entry((x, y)).or_default().push((index, &**feature));
+                    }
+                }
+            }
+        }
+        Buckets {
+            tiles: buckets,
+            wide: RTree::bulk_load(wide),
+        }
+    }
+
+    /// Like `features_in_tile`, but with the in-memory features from `bucket_features`. Spilled
+    /// features are still searched for.
+    fn features_in_bucket(
+        &self,
+        tile_id: TileId,
+        buckets: &Buckets<'a>,
+    ) -> Result<Vec<(&Layer<'a>, Vec<FeatureRef<'a>>)>> {
+        let envelope = tile_envelope(tile_id);
+        if buckets
+            .wide
+            .locate_in_envelope_intersecting(&envelope)
+            .next()
+            .is_some()
+        {
+            return self.features_in_tile(tile_id);
+        }
+        let bucket = buckets
+            .tiles
+            .get(&(tile_id.x(), tile_id.y()))
+            .map_or(&[][..], Vec::as_slice);
+        self.layers
+            .iter()
+            .enumerate()
+            .filter(|(_, layer)| layer.zoom_levels.contains(&tile_id.z()))
+            .map(|(index, layer)| {
+                let mut features: Vec<_> = bucket
+                    .iter()
+                    .filter(|(layer_index, _)| *layer_index == index)
+                    .map(|(_, feature)| FeatureRef::Borrowed(*feature))
+                    .collect();
+                if let Some(ref spill) = layer.features.spill {
+                    features.extend(
+                        spill
+                            .locate_in_envelope_intersecting(&envelope)?
+                            .into_iter()
+                            .map(FeatureRef::Owned),
+                    );
+                }
+                Ok((layer, features))
+            })
+            .collect()
+    }
+
+    /// The features from each layer shown at this zoom that touch the tile
+    fn features_in_tile(&self, tile_id: TileId) -> Result<Vec<(&Layer<'a>, Vec<FeatureRef<'a>>)>> {
+        let envelope = tile_envelope(tile_id);
+        self.layers
+            .iter()
+            .filter(|layer| layer.zoom_levels.contains(&tile_id.z()))
+            .map(|layer| Ok((layer, layer.features.features_intersecting(&envelope)?)))
+            .collect()
+    }
 
-    let map_grid = MapGrid::default();
+    /// Makes one tile, returning its PMTiles ID and compressed bytes, or nothing if it's empty
+    fn encode_tile(&self, tile_id: TileId) -> Result<Option<(u64, Vec<u8>)>> {
+        let features = self.features_in_tile(tile_id)?;
+        self.encode_tile_features(tile_id, features)
+    }
 
-    let mut tiles_to_calculate = Vec::new();
+    /// `encode_features`, saying which tile failed
+    fn encode_tile_features(
+        &self,
+        tile_id: TileId,
+        features: Vec<(&Layer<'a>, Vec<FeatureRef<'a>>)>,
+    ) -> Result<Option<(u64, Vec<u8>)>> {
+        self.encode_features(tile_id, features).map_err(|source| {
+            ConversionError::Encoding {
+                tile: format!("{}/{}/{}", tile_id.z(), tile_id.x(), tile_id.y()),
+                source,
+            }
+            .into()
+        })
+    }
 
-    for z in &options.zoom_levels {
+    fn encode_features(
+        &self,
+        tile_id: TileId,
+        features: Vec<(&Layer<'a>, Vec<FeatureRef<'a>>)>,
+    ) -> Result<Option<(u64, Vec<u8>)>> {
+        let Self {
+            options,
+            density_scale,
+            compressor,
+            ..
+        } = self;
+        if let Some(ref raster) = options.density {
+            let png = density::make_density_tile(tile_id, features, raster, *density_scale)?;
+            return Ok(png.map(|png| (pmtiles_tile_id(tile_id), png)));
+        }
+        let tile = match options.grid_aggregation {
+            Some(ref grid) if tile_id.z() <= grid.max_zoom => {
+                // There's only one layer
+                let Some((_, features)) = features.into_iter().next() else {
+                    return Ok(None);
+                };
+                grid::make_grid_tile(tile_id, features, grid, options)?
+            }
+            // TODO And figure out clipping
+            _ => make_tile(tile_id, features, options)?,
+        };
+        let Some((tile_id, tile)) = tile else {
+            return Ok(None);
+        };
+        let bytes = tile.to_bytes()?;
+        if options.strict {
+            validate::validate_tile(&bytes)
+                .with_context(|| format!("Tile {tile_id} breaks the MVT spec"))?;
+        }
+        let compressed = compressor.compress(&bytes)?;
+        log::debug!(
+            "Tile {tile_id} is {}, compressed from {}",
+            HumanBytes(compressed.len() as u64),
+            HumanBytes(bytes.len() as u64)
+        );
+        Ok(Some((pmtiles_tile_id(tile_id), compressed)))
+    }
+}
+
+/// Features touching more tiles than this are found with the R-tree, so bucketing a zoom takes at
+/// most this many entries per feature
+const MAX_BUCKETS_PER_FEATURE: usize = 16;
+
+/// The in-memory features touching each tile at one zoom, from `Tiler::bucket_features`
+struct Buckets<'a> {
+    /// By tile x and y, with the index of each feature's layer
+    tiles: HashMap<(u32, u32), Vec<(usize, &'a TreeFeature)>>,
+    /// The envelopes of features touching too many tiles to bucket
+    wide: RTree<Rectangle<[f64; 2]>>,
+}
+
+/// The tile's bounds in web mercator
+fn tile_envelope(tile_id: TileId) -> AABB<[f64; 2]> {
+    let tbounds = MapGrid::default().tile_bbox(tile_id);
+    AABB::from_corners(
+        [tbounds.x_min(), tbounds.y_min()],
+        [tbounds.x_max(), tbounds.y_max()],
+    )
+}
+
+fn pmtiles_tile_id(tile_id: TileId) -> u64 {
+    get_tile_id(tile_id.z() as u8, tile_id.x() as u64, tile_id.y() as u64)
+}
+
+/// Every tile in `zoom_levels` covering `bbox`, whether or not it has any features
+fn tiles_covering(zoom_levels: &[u32], bbox: &BBox) -> Result<Vec<TileId>> {
+    let mut tiles = Vec::new();
+    for z in zoom_levels {
         let z = *z;
         let (x1, y1, x2, y2) = bbox.to_tiles(z);
         for x in x1..=x2 {
             for y in y1..=y2 {
-                tiles_to_calculate.push(TileId::new(x, y, z)?);
+                tiles.push(TileId::new(x, y, z)?);
             }
         }
     }
+    Ok(tiles)
+}
 
-    let multi_progress = MultiProgress::new();
-    let tiles: Vec<(TileId, Tile)> = tiles_to_calculate
-        .into_par_iter()
-        .flat_map(|tile_id| {
-            let tbounds = map_grid.tile_bbox(tile_id);
-            let features = r_tree.locate_in_envelope_intersecting(&AABB::from_corners(
-                [tbounds.x_min(), tbounds.y_min()],
-                [tbounds.x_max(), tbounds.y_max()],
-            ));
-            // TODO And figure out clipping
-            // TODO Plumb the result
-            make_tile(
-                tile_id,
-                features.collect(),
-                &options,
-                multi_progress.clone(),
-            )
-            .unwrap()
-        })
-        .collect();
+/// A loaded layer, with the settings for one run
+struct Layer<'a> {
+    name: String,
+    features: &'a LoadedLayer,
+    zoom_levels: Vec<u32>,
+    sort_by_key: Option<String>,
+    limit_size_bytes: Option<usize>,
+}
 
-    // Assemble the final thing
-    for (tile_id, tile) in tiles {
-        pmtiles.add_tile(
-            get_tile_id(tile_id.z() as u8, tile_id.x() as u64, tile_id.y() as u64),
-            tile.to_bytes()?,
-        );
-    }
-    Ok(pmtiles)
+/// One layer's features, indexed and ready to tile, from `load_features`
+pub struct LoadedLayer {
+    tree: RTree<CachedEnvelope<TreeFeature>>,
+    /// Features past `Options::max_memory`
+    spill: Option<Spill>,
+    fields: HashMap<String, String>,
+    /// In WGS84
+    bbox: BBox,
+    num_features: usize,
+    /// Only the feature counts and loading time are filled in
+    stats: ConversionStats,
 }
 
-struct TreeFeature {
+/// A feature ready to be tiled, in web mercator
+#[derive(Clone)]
+pub struct TreeFeature {
     geometry: geo_types::Geometry<f64>,
     properties: Option<geojson::JsonObject>,
 }
 
-impl From<geojson::Feature> for TreeFeature {
-    fn from(feature: geojson::Feature) -> Self {
-        let properties = feature.properties;
-        // Geometry must exist
-        let mut geometry: geo_types::Geometry<f64> = feature.geometry.unwrap().try_into().unwrap();
+impl TryFrom<geojson::Feature> for TreeFeature {
+    type Error = anyhow::Error;
+
+    fn try_from(feature: geojson::Feature) -> Result<Self> {
+        let (geometry, properties) = read_geometry(feature)?;
+        Ok(TreeFeature::project(geometry, properties))
+    }
+}
+
+/// The WGS84 geometry and properties of a feature that can be tiled
+fn read_geometry(
+    feature: geojson::Feature,
+) -> Result<(Geometry<f64>, Option<geojson::JsonObject>)> {
+    let Some(geometry) = feature.geometry else {
+        bail!("It has no geometry");
+    };
+    let geometry: Geometry<f64> = geometry.try_into()?;
+    // The R-tree needs an envelope
+    if geometry.bounding_rect().is_none() {
+        bail!("Its geometry is empty");
+    }
+    Ok((geometry, feature.properties))
+}
+
+/// For `Warning::UnsupportedGeometry`
+fn geometry_type_name(geometry: &Geometry<f64>) -> &'static str {
+    match geometry {
+        Geometry::Point(_) => "Point",
+        Geometry::Line(_) => "Line",
+        Geometry::LineString(_) => "LineString",
+        Geometry::Polygon(_) => "Polygon",
+        Geometry::MultiPoint(_) => "MultiPoint",
+        Geometry::MultiLineString(_) => "MultiLineString",
+        Geometry::MultiPolygon(_) => "MultiPolygon",
+        Geometry::GeometryCollection(_) => "GeometryCollection",
+        Geometry::Rect(_) => "Rect",
+        Geometry::Triangle(_) => "Triangle",
+    }
+}
+
+impl TreeFeature {
+    fn project(mut geometry: Geometry<f64>, properties: Option<geojson::JsonObject>) -> Self {
         geometry.map_coords_in_place(|p| math::wgs84_to_web_mercator([p.x, p.y]).into());
-        return Self {
+        Self {
+            geometry,
             properties,
+        }
+    }
+
+    /// For a geometry already in web mercator, in meters like EPSG:3857. Empty geometries can't be
+    /// indexed, so they give `None`.
+    pub fn new(
+        geometry: geo_types::Geometry<f64>,
+        properties: Option<geojson::JsonObject>,
+    ) -> Option<Self> {
+        geometry.bounding_rect()?;
+        Some(Self {
             geometry,
-        };
+            properties,
+        })
+    }
+
+    /// In web mercator
+    pub fn geometry(&self) -> &geo_types::Geometry<f64> {
+        &self.geometry
+    }
+
+    pub fn properties(&self) -> Option<&geojson::JsonObject> {
+        self.properties.as_ref()
+    }
+}
+
+impl LoadedLayer {
+    /// A layer from features that are already in web mercator, skipping everything
+    /// `load_features` does, like filtering and masking. They're indexed all at once.
+    pub fn from_features(features: impl IntoIterator<Item = TreeFeature>) -> Self {
+        let features: Vec<_> = features.into_iter().map(CachedEnvelope::new).collect();
+        Self::from_tree(RTree::bulk_load(features))
+    }
+
+    /// A layer from an index that's already built, like `from_features`
+    pub fn from_tree(tree: RTree<CachedEnvelope<TreeFeature>>) -> Self {
+        let mut bbox = BBox::empty();
+        let mut fields = HashMap::new();
+        if tree.size() > 0 {
+            let envelope = tree.root().envelope();
+            let [min_lon, min_lat] = math::web_mercator_to_wgs84(envelope.lower());
+            let [max_lon, max_lat] = math::web_mercator_to_wgs84(envelope.upper());
+            bbox = BBox {
+                min_lon,
+                min_lat,
+                max_lon,
+                max_lat,
+            };
+            for feature in &tree {
+                for key in feature.properties.iter().flat_map(|props| props.keys()) {
+                    if !fields.contains_key(key) {
+                        fields.insert(key.clone(), String::new());
+                    }
+                }
+            }
+        }
+        Self {
+            num_features: tree.size(),
+            tree,
+            spill: None,
+            fields,
+            bbox,
+            stats: ConversionStats::default(),
+        }
+    }
+
+    /// The features kept in memory, indexed by their web mercator envelopes. With
+    /// `Options::max_memory`, some features can be on disk instead.
+    pub fn tree(&self) -> &RTree<CachedEnvelope<TreeFeature>> {
+        &self.tree
+    }
+
+    /// Every feature, including ones on disk
+    pub fn num_features(&self) -> usize {
+        self.num_features
+    }
+
+    /// The property keys found, for the metadata
+    pub fn fields(&self) -> impl Iterator<Item = &str> {
+        self.fields.keys().map(String::as_str)
+    }
+
+    fn features_intersecting(&self, envelope: &AABB<[f64; 2]>) -> Result<Vec<FeatureRef<'_>>> {
+        let mut features: Vec<_> = self
+            .tree
+            .locate_in_envelope_intersecting(envelope)
+            .map(|feature| FeatureRef::Borrowed(&**feature))
+            .collect();
+        if let Some(ref spill) = self.spill {
+            features.extend(
+                spill
+                    .locate_in_envelope_intersecting(envelope)?
+                    .into_iter()
+                    .map(FeatureRef::Owned),
+            );
+        }
+        Ok(features)
+    }
+
+    /// Calls `f` on every feature, reading back any that were spilled
+    fn for_each_feature(&self, mut f: impl FnMut(&TreeFeature)) -> Result<()> {
+        for feature in &self.tree {
+            f(feature);
+        }
+        if let Some(ref spill) = self.spill {
+            spill.for_each(f)?;
+        }
+        Ok(())
+    }
+
+    fn envelopes(&self) -> impl Iterator<Item = AABB<[f64; 2]>> + '_ {
+        self.tree
+            .iter()
+            .map(|feature| feature.envelope())
+            .chain(self.spill.iter().flat_map(|spill| spill.envelopes()))
     }
 }
 
@@ -134,6 +1787,7 @@ impl RTreeObject for TreeFeature {
     type Envelope = AABB<[f64; 2]>;
 
     fn envelope(&self) -> Self::Envelope {
+        // Features without a bounding box are never made
         let bbox = self.geometry.bounding_rect().unwrap();
         AABB::from_corners([bbox.min().x, bbox.min().y], [bbox.max().x, bbox.max().y])
     }
@@ -148,195 +1802,922 @@ impl TreeFeature {
     }
 }
 
-fn load_features<R: Read>(
-    reader: FeatureReader<R>,
-) -> Result<(
-    RTree<CachedEnvelope<TreeFeature>>,
-    usize,
-    BBox,
-    HashMap<String, String>,
-)> {
+fn check_layer_count(num_layers: usize, options: &Options) -> Result<()> {
+    if num_layers == 0 {
+        return Err(invalid_options("There are no layers to tile"));
+    }
+    if options.grid_aggregation.is_some() && num_layers > 1 {
+        return Err(invalid_options(
+            "Grid aggregation only works with one input layer",
+        ));
+    }
+    Ok(())
+}
+
+/// Loads every input, sharing `Options::max_memory` between them
+fn load_inputs<I: Iterator<Item = Result<geojson::Feature>>>(
+    inputs: Vec<(LayerOptions, I)>,
+    options: &Options,
+) -> Result<Vec<(LayerOptions, LoadedLayer)>> {
+    options.validate()?;
+    check_layer_count(inputs.len(), options)?;
+    options.report(Progress::Loading);
+    let mut memory_used = 0;
+    inputs
+        .into_iter()
+        .map(|(layer_options, features)| {
+            let layer = load_layer(features, &layer_options, options, &mut memory_used)?;
+            Ok((layer_options, layer))
+        })
+        .collect()
+}
+
+fn borrow_layers(loaded: &[(LayerOptions, LoadedLayer)]) -> Vec<(LayerOptions, &LoadedLayer)> {
+    loaded
+        .iter()
+        .map(|(layer_options, layer)| (layer_options.clone(), layer))
+        .collect()
+}
+
+/// `memory_used` is the `approx_size` of the features kept in memory so far, across layers
+fn load_layer(
+    features: impl Iterator<Item = Result<geojson::Feature>>,
+    layer: &LayerOptions,
+    options: &Options,
+    memory_used: &mut usize,
+) -> Result<LoadedLayer> {
+    let mut loader = LayerLoader::new(layer.clone(), options);
+    for f in features {
+        loader.push(f, options, memory_used)?;
+    }
+    loader.finish(options)
+}
+
+/// Loads one layer's features as they arrive, for `load_layer` and `TilerBuilder`
+struct LayerLoader {
+    layer: LayerOptions,
+    start: Instant,
+    stats: ConversionStats,
     // Note we calculate a bbox from WGS84 features instead of using the rtree's envelope. The
     // rtree is in web mercator space, making it harder to calculate the tiles covered
-    let mut bbox = BBox::empty();
-    let mut tree_features = Vec::new();
-    let mut fields = HashMap::new();
-    for f in reader.features() {
-        let f = f?;
-        bbox.add(&f);
+    bbox: BBox,
+    tree_features: Vec<TreeFeature>,
+    spill: Option<SpillWriter>,
+    fields: HashMap<String, String>,
+    projected_mask: Option<geo_types::MultiPolygon<f64>>,
+    /// For `Options::sample_rate`
+    sampler: Sampler,
+    /// How many features had each kind of `Warning`, to sum up once the layer is loaded
+    unsupported: usize,
+    clamped: usize,
+}
+
+impl LayerLoader {
+    fn new(layer: LayerOptions, options: &Options) -> Self {
+        Self {
+            start: Instant::now(),
+            stats: ConversionStats::default(),
+            bbox: BBox::empty(),
+            tree_features: Vec::new(),
+            spill: None,
+            fields: HashMap::new(),
+            projected_mask: options.mask.as_ref().map(mask::project_mask),
+            sampler: Sampler::new(options.seed).for_stream(&layer.name),
+            unsupported: 0,
+            clamped: 0,
+            layer,
+        }
+    }
+
+    fn sort_by_key<'a>(&'a self, options: &'a Options) -> Option<&'a str> {
+        self.layer
+            .sort_by_key
+            .as_deref()
+            .or(options.sort_by_key.as_deref())
+    }
+
+    /// Filters, transforms, and projects a feature, counting it in the stats, the bounds, and the
+    /// fields, without keeping it. Gives nothing if it's left out.
+    fn prepare(
+        &mut self,
+        f: Result<geojson::Feature>,
+        options: &Options,
+    ) -> Result<Option<TreeFeature>> {
+        let layer_name = self.layer.name.as_str();
+        // Reading stops at the first error, so this is the position in the layer
+        let index = self.stats.features_read;
+        let f = f.map_err(|source| ConversionError::Parse {
+            layer: layer_name.to_string(),
+            index,
+            source,
+        })?;
+        options.check_cancelled()?;
+        self.stats.features_read += 1;
+        if [&options.filter, &self.layer.filter]
+            .into_iter()
+            .flatten()
+            .any(|filter| !filter.matches(&f))
+        {
+            self.stats.features_dropped += 1;
+            return Ok(None);
+        }
+        if let Some(rate) = options.sample_rate {
+            if !self.sampler.keeps(index as u64, rate) {
+                self.stats.features_dropped += 1;
+                return Ok(None);
+            }
+        }
+        let mut feature_bbox = BBox::empty();
+        feature_bbox.add(&f);
+        let (mut geometry, mut properties) = match read_geometry(f) {
+            Ok(f) => f,
+            Err(err) if options.skip_invalid => {
+                log::debug!("Skipping feature {index} in layer {layer_name}: {err}");
+                self.stats.features_skipped += 1;
+                let skipped = SkippedFeature {
+                    layer: layer_name.to_string(),
+                    index,
+                    reason: err.to_string(),
+                };
+                if let Some(ref on_skip) = options.on_skip {
+                    on_skip(skipped.clone());
+                }
+                options.warn(Warning::Skipped(skipped));
+                return Ok(None);
+            }
+            Err(err) => {
+                return Err(ConversionError::InvalidGeometry {
+                    layer: layer_name.to_string(),
+                    index,
+                    reason: err.to_string(),
+                }
+                .into())
+            }
+        };
+        if let Some(ref transform) = options.transform {
+            let props = properties.get_or_insert_with(Default::default);
+            if transform(&mut geometry, props) == FeatureAction::Drop
+                || geometry.bounding_rect().is_none()
+            {
+                self.stats.features_dropped += 1;
+                return Ok(None);
+            }
+            feature_bbox = BBox::empty();
+            feature_bbox.add_geometry(&geometry);
+        }
+        if options.density.is_none()
+            && !matches!(
+                geometry,
+                Geometry::Point(_) | Geometry::LineString(_) | Geometry::MultiLineString(_)
+            )
+        {
+            self.unsupported += 1;
+            options.warn(Warning::UnsupportedGeometry {
+                layer: layer_name.to_string(),
+                index,
+                geometry_type: geometry_type_name(&geometry),
+            });
+        }
+        if feature_bbox.min_lat < -math::MAX_LATITUDE
+            || feature_bbox.max_lat > math::MAX_LATITUDE
+            || feature_bbox.min_lon < -180.0
+            || feature_bbox.max_lon > 180.0
+        {
+            self.clamped += 1;
+            options.warn(Warning::ClampedCoordinates {
+                layer: layer_name.to_string(),
+                index,
+            });
+        }
+        self.bbox.union(&feature_bbox);
+        let mut f = TreeFeature::project(geometry, properties);
 
         if let Some(ref props) = f.properties {
             for (key, _value) in props {
+                if !options.attributes.keeps(key) {
+                    continue;
+                }
                 // TODO Give a real description based on the JSON value type?
-                fields.entry(key.to_string()).or_insert_with(String::new);
+                self.fields.entry(key.to_string()).or_default();
+            }
+        }
+
+        if let Some(ref projected) = self.projected_mask {
+            let Some(clipped) = mask::clip(f, projected) else {
+                self.stats.features_dropped += 1;
+                return Ok(None);
+            };
+            f = clipped;
+        }
+        if let Some(ref rule) = options.direction {
+            rule.apply(&mut f);
+        }
+        if !matches!(options.attributes, Attributes::All) {
+            let sort_by_key = self.sort_by_key(options);
+            // Don't hold onto properties that won't be used
+            if let Some(ref mut props) = f.properties {
+                props.retain(|key, _| {
+                    options.attributes.keeps(key)
+                        || Some(key.as_str()) == sort_by_key
+                        || Some(key) == options.sort_by_key.as_ref()
+                        || Some(key) == options.dissolve_by_key.as_ref()
+                });
             }
         }
+        Ok(Some(f))
+    }
 
-        tree_features.push(CachedEnvelope::new(f.into()));
+    /// `memory_used` is like for `load_layer`
+    fn push(
+        &mut self,
+        f: Result<geojson::Feature>,
+        options: &Options,
+        memory_used: &mut usize,
+    ) -> Result<()> {
+        let Some(f) = self.prepare(f, options)? else {
+            return Ok(());
+        };
+        if let Some(ref mut spill) = self.spill {
+            return spill.push(f);
+        }
+        if let Some(max_memory) = options.max_memory {
+            *memory_used += spill::approx_size(&f);
+            if *memory_used > max_memory {
+                log::info!(
+                    "Reached the memory limit of {} after {} features, so keeping the rest on disk",
+                    HumanBytes(max_memory as u64),
+                    HumanCount(self.tree_features.len() as u64)
+                );
+                let mut writer = SpillWriter::new()?;
+                writer.push(f)?;
+                self.spill = Some(writer);
+                return Ok(());
+            }
+        }
+        self.tree_features.push(f);
+        Ok(())
+    }
+
+    fn finish(self, options: &Options) -> Result<LoadedLayer> {
+        let sort_by_key = self.sort_by_key(options).map(str::to_string);
+        let sort_by_key = sort_by_key.as_deref();
+        let Self {
+            layer,
+            start,
+            mut stats,
+            mut bbox,
+            mut tree_features,
+            spill,
+            mut fields,
+            unsupported,
+            clamped,
+            ..
+        } = self;
+        let layer_name = layer.name.as_str();
+        if stats.features_skipped > 0 {
+            log::warn!(
+                "Skipped {} invalid features in layer {layer_name}",
+                HumanCount(stats.features_skipped as u64)
+            );
+        }
+        if unsupported > 0 {
+            log::warn!(
+                "{} features in layer {layer_name} can't be drawn in vector tiles",
+                HumanCount(unsupported as u64)
+            );
+        }
+        if clamped > 0 {
+            log::warn!(
+                "{} features in layer {layer_name} go past the edge of web mercator",
+                HumanCount(clamped as u64)
+            );
+        }
+        if stats.features_dropped > 0 {
+            options.warn(Warning::DroppedFeatures {
+                layer: layer_name.to_string(),
+                count: stats.features_dropped,
+            });
+        }
+
+        if let Some(ref mask) = options.mask {
+            if let Some(mask_bbox) = mask.bounding_rect() {
+                bbox.intersect(&mask_bbox);
+            }
+        }
+
+        if let Some(ref key) = options.dissolve_by_key {
+            tree_features = dissolve::dissolve(tree_features, key, sort_by_key);
+            // Only the dissolve key and the aggregated sort key survive
+            fields.retain(|k, _| k == key || Some(k.as_str()) == sort_by_key);
+        }
+
+        let tree_features: Vec<_> = tree_features.into_iter().map(CachedEnvelope::new).collect();
+        let num_features = tree_features.len() + spill.as_ref().map_or(0, |spill| spill.len());
+        let tree = RTree::bulk_load(tree_features);
+        let spill = spill.map(|spill| spill.finish()).transpose()?;
+        if let Some(key) = sort_by_key {
+            if num_features > 0 && !fields.contains_key(key) {
+                let warning = Warning::MissingSortKey {
+                    layer: layer_name.to_string(),
+                    key: key.to_string(),
+                };
+                log::warn!("{warning}");
+                options.warn(warning);
+            }
+        }
+        stats.loading = start.elapsed();
+        Ok(LoadedLayer {
+            tree,
+            spill,
+            fields,
+            bbox,
+            num_features,
+            stats,
+        })
     }
-    let num_features = tree_features.len();
-    let tree = RTree::bulk_load(tree_features);
-    Ok((tree, num_features, bbox, fields))
 }
 
-fn make_tile(
-    current_tile_id: TileId,
-    mut features: Vec<&CachedEnvelope<TreeFeature>>,
-    options: &Options,
-    multi_progress: MultiProgress,
-) -> Result<Option<(TileId, Tile)>> {
-    // Start this early to capture the time taken to sort
-    let progress = multi_progress.add(progress_bar_for_count(features.len()));
+/// The middle of the tile at `zoom` with the most features inside `bbox`, counting each feature
+/// once, by the middle of its envelope. Ties go to the tile furthest north, then west. At low zooms
+/// the tile can be much bigger than `bbox`, so this is the middle of the part inside `bbox`.
+fn densest_tile_center(layers: &[Layer], bbox: &BBox, zoom: u32) -> Option<geo_types::Point> {
+    let mut counts = HashMap::new();
+    for layer in layers {
+        for envelope in layer.features.envelopes() {
+            count_feature_center(&mut counts, &envelope, bbox, zoom);
+        }
+    }
+    densest_center(counts, bbox, zoom)
+}
 
-    // We have to do this to each result from RTree, because order is of course not maintained
-    // between internal buckets
-    if let Some(ref key) = options.sort_by_key {
-        features.sort_by_key(|f| f.get_sort_key(key).unwrap_or(0));
-        features.reverse();
+/// Counts a feature towards the tile its envelope's middle is in, for `densest_tile_center`
+fn count_feature_center(
+    counts: &mut HashMap<(u32, u32), usize>,
+    envelope: &AABB<[f64; 2]>,
+    bbox: &BBox,
+    zoom: u32,
+) {
+    let [lon, lat] = math::web_mercator_to_wgs84(envelope.center());
+    if lon < bbox.min_lon || lon > bbox.max_lon || lat < bbox.min_lat || lat > bbox.max_lat {
+        return;
     }
+    *counts
+        .entry(math::lon_lat_to_tile(lon, lat, zoom))
+        .or_insert(0) += 1;
+}
 
-    let web_mercator_transform = MapGrid::default();
-    let transform = web_mercator_transform.tile_transform(current_tile_id);
-    let mut tile = Tile::new(4096);
+/// The middle of the tile with the highest count, like `densest_tile_center`
+fn densest_center(
+    counts: HashMap<(u32, u32), usize>,
+    bbox: &BBox,
+    zoom: u32,
+) -> Option<geo_types::Point> {
+    let ((x, y), _) = counts
+        .into_iter()
+        .max_by_key(|((x, y), count)| (*count, std::cmp::Reverse((*y, *x))))?;
+    // Tile y counts from the top
+    let [west, north] = math::tile_to_lon_lat(x as f64, y as f64, zoom);
+    let [east, south] = math::tile_to_lon_lat(x as f64 + 1.0, y as f64 + 1.0, zoom);
+    Some(geo_types::Point::new(
+        (west.max(bbox.min_lon) + east.min(bbox.max_lon)) / 2.0,
+        (south.max(bbox.min_lat) + north.min(bbox.max_lat)) / 2.0,
+    ))
+}
 
-    let mut layer = tile.create_layer(&options.layer_name);
+/// The highest zoom where `bbox` is no bigger than one tile
+fn zoom_to_fit(bbox: &BBox) -> u8 {
+    let [x1, y1] = math::wgs84_to_web_mercator([bbox.min_lon, bbox.min_lat]);
+    let [x2, y2] = math::wgs84_to_web_mercator([bbox.max_lon, bbox.max_lat]);
+    let size = (x2 - x1).max(y2 - y1);
+    if size <= 0.0 {
+        return u8::MAX;
+    }
+    (math::WORLD_SIZE / size)
+        .log2()
+        .floor()
+        .clamp(0.0, u8::MAX as f64) as u8
+}
 
-    let mut bytes_so_far = 0;
-    let mut skipped = false;
-    for feature in features {
-        progress.inc(1);
+/// PMTiles can address deeper zooms, but nothing renders them
+pub const MAX_ZOOM: u32 = 30;
 
-        let geom_type = match feature.geometry {
-            Geometry::Point(_) => GeomType::Point,
-            Geometry::LineString(_) => GeomType::Linestring,
-            _ => continue,
-        };
-        let mut b = GeomEncoder::new(geom_type, Transform::default());
-        let mut any = false;
-
-        // TODO Refactor
-        match feature.geometry {
-            Geometry::Point(pt) => {
-                let pt = geo_types::Coord::from(pt);
-                // Transform to 0-1 tile coords (not sure why this doesnt work with passing the
-                // transform through)
-                let transformed_pt = transform * (pt.x, pt.y);
-
-                // If any part of the LineString is within this tile, keep the whole thing. No
-                // clipping yet.
-                if transformed_pt.x >= 0.0
-                    && transformed_pt.x <= 1.0
-                    && transformed_pt.y >= 0.0
-                    && transformed_pt.y <= 1.0
-                {
-                    any = true;
-                }
+/// Zooms past a guess aren't likely to show anything new
+const MAX_GUESSED_ZOOM: u32 = 18;
 
-                // Same as extent
-                b = b.point(transformed_pt.x * 4096.0, transformed_pt.y * 4096.0)?;
-            }
-            Geometry::LineString(ref line_string) => {
-                for pt in line_string {
-                    // Transform to 0-1 tile coords (not sure why this doesnt work with passing the
-                    // transform through)
-                    let transformed_pt = transform * (pt.x, pt.y);
-
-                    // If any part of the LineString is within this tile, keep the whole thing. No
-                    // clipping yet.
-                    if transformed_pt.x >= 0.0
-                        && transformed_pt.x <= 1.0
-                        && transformed_pt.y >= 0.0
-                        && transformed_pt.y <= 1.0
-                    {
-                        any = true;
+/// Fills in `Options::guess_min_zoom` and `Options::guess_max_zoom`. The max zoom is where the
+/// typical gap between vertices, or between points, is at least a pixel of a 256 pixel tile, since
+/// zooming in further only spreads out detail that's already visible.
+fn guess_zooms(layers: &[Layer], bbox: &BBox, options: &Options) -> Result<Vec<u32>> {
+    let mut min_zoom = if options.guess_min_zoom {
+        (zoom_to_fit(bbox) as u32).min(MAX_GUESSED_ZOOM)
+    } else {
+        options.zoom_levels.first().cloned().unwrap_or(0)
+    };
+    let max_zoom = if options.guess_max_zoom {
+        let mut segment_lengths = Vec::new();
+        let mut num_points = 0;
+        for layer in layers {
+            layer.features.for_each_feature(|feature| {
+                let mut line_strings = Vec::new();
+                match feature.geometry {
+                    Geometry::Point(_) => num_points += 1,
+                    Geometry::MultiPoint(ref points) => num_points += points.0.len(),
+                    Geometry::LineString(ref line_string) => line_strings.push(line_string),
+                    Geometry::MultiLineString(ref multi_line_string) => {
+                        line_strings.extend(&multi_line_string.0)
                     }
-
-                    // Same as extent
-                    b = b.point(transformed_pt.x * 4096.0, transformed_pt.y * 4096.0)?;
+                    Geometry::Polygon(ref polygon) => {
+                        line_strings.push(polygon.exterior());
+                        line_strings.extend(polygon.interiors());
+                    }
+                    Geometry::MultiPolygon(ref multi_polygon) => {
+                        for polygon in multi_polygon {
+                            line_strings.push(polygon.exterior());
+                            line_strings.extend(polygon.interiors());
+                        }
+                    }
+                    _ => {}
                 }
+                segment_lengths.extend(
+                    line_strings
+                        .into_iter()
+                        .flat_map(|line_string| line_string.lines())
+                        .map(|line| line.euclidean_length())
+                        .filter(|length| *length > 0.0),
+                );
+            })?;
+        }
+
+        let mut spacing = f64::INFINITY;
+        if !segment_lengths.is_empty() {
+            let middle = segment_lengths.len() / 2;
+            let (_, median, _) =
+                segment_lengths.select_nth_unstable_by(middle, |a, b| a.total_cmp(b));
+            spacing = *median;
+        }
+        if num_points > 1 {
+            // As if the points were evenly spread over the bbox
+            let [x1, y1] = math::wgs84_to_web_mercator([bbox.min_lon, bbox.min_lat]);
+            let [x2, y2] = math::wgs84_to_web_mercator([bbox.max_lon, bbox.max_lat]);
+            let area = (x2 - x1) * (y2 - y1);
+            if area > 0.0 {
+                spacing = spacing.min((area / num_points as f64).sqrt());
             }
-            _ => continue,
         }
+        if spacing.is_finite() {
+            (math::WORLD_SIZE / (256.0 * spacing))
+                .log2()
+                .ceil()
+                .clamp(min_zoom as f64, MAX_GUESSED_ZOOM as f64) as u32
+        } else {
+            // A single point, or nothing at all
+            min_zoom.max(MAX_GUESSED_ZOOM)
+        }
+    } else {
+        options
+            .zoom_levels
+            .last()
+            .cloned()
+            .unwrap_or(MAX_GUESSED_ZOOM)
+    };
+    min_zoom = min_zoom.min(max_zoom);
+    Ok((min_zoom..=max_zoom).collect())
+}
 
-        if !any {
-            // This wasn't a LineString. Totally skip.
-            // TODO Fix upstream, because b.encode() didn't fail and wound up generating something
-            // that breaks the protobuf parsing in the frontend
-            continue;
+fn make_tile(
+    current_tile_id: TileId,
+    layers: Vec<(&Layer, Vec<FeatureRef<'_>>)>,
+    options: &Options,
+) -> Result<Option<(TileId, Tile)>> {
+    let web_mercator_transform = MapGrid::default();
+    let transform = web_mercator_transform.tile_transform(current_tile_id);
+    let mut tile = Tile::new(options.extent);
+
+    // The size limit in Options applies to the whole tile, shared by all layers
+    let mut bytes_so_far = 0;
+    let mut tile_full = false;
+    let mut skipped = false;
+    let mut total_features = 0;
+    for (layer_options, mut features) in layers {
+        // We have to do this to each result from RTree, because order is of course not maintained
+        // between internal buckets
+        if let Some(ref key) = layer_options.sort_by_key {
+            features.sort_by_key(|f| f.get_sort_key(key).unwrap_or(0));
+            features.reverse();
+        }
+        if let Some(ref process_tile) = options.process_tile {
+            let zxy = [
+                current_tile_id.z(),
+                current_tile_id.x(),
+                current_tile_id.y(),
+            ];
+            process_tile(&layer_options.name, zxy, &mut features);
         }
 
-        let encoded = b.encode()?;
-        bytes_so_far += encoded.len();
-        // TODO Note we don't use the layer size, because it's expensive to constantly protobuf
-        // encode it. This could overcount (ignoring properties) but also undercount (the encoded
-        // geometry is further compacted by protobuf?)
-        if let Some(limit) = options.limit_size_bytes {
-            if bytes_so_far > limit {
-                skipped = true;
-                progress.finish();
+        let mut layer = tile.create_layer(&layer_options.name);
+        let mut layer_bytes = 0;
+        for feature in features {
+            if tile_full {
                 break;
             }
-        }
 
-        let id = layer.num_features() as u64;
-        // The ownership swaps between layer and write_feature due to how feature properties are
-        // encoded
-        let mut write_feature = layer.into_feature(encoded);
-        write_feature.set_id(id);
+            let geom_type = match feature.geometry {
+                Geometry::Point(_) => GeomType::Point,
+                Geometry::LineString(_) | Geometry::MultiLineString(_) => GeomType::Linestring,
+                _ => continue,
+            };
+            let mut b = GeomEncoder::new(geom_type, Transform::default());
 
-        if let Some(ref props) = feature.properties {
-            for (key, value) in props {
-                match value {
-                    Value::Null => {}
-                    Value::Bool(x) => write_feature.add_tag_bool(key, *x),
-                    Value::Number(x) => {
-                        // TODO Other variations, and maybe use float?
-                        if let Some(x) = x.as_f64() {
-                            write_feature.add_tag_double(key, x);
-                        }
+            let any = match feature.geometry {
+                Geometry::Point(pt) => {
+                    add_points(&mut b, std::iter::once(pt.0), &transform, options.extent)?
+                }
+                Geometry::LineString(ref line_string) => add_points(
+                    &mut b,
+                    line_string.coords().cloned(),
+                    &transform,
+                    options.extent,
+                )?,
+                Geometry::MultiLineString(ref multi_line_string) => {
+                    let mut any = false;
+                    for line_string in multi_line_string {
+                        any |= add_points(
+                            &mut b,
+                            line_string.coords().cloned(),
+                            &transform,
+                            options.extent,
+                        )?;
+                        b.complete_geom()?;
                     }
-                    Value::String(x) => write_feature.add_tag_string(key, x),
-                    // Encode other cases as strings, like tippecanoe. Note this is probably bad in
-                    // the input; unless the possible cases for arrays and objects are small, it'll
-                    // take lots to encode these
-                    Value::Array(x) => {
-                        write_feature.add_tag_string(key, &serde_json::to_string(&x)?)
+                    any
+                }
+                _ => continue,
+            };
+
+            if !any {
+                // This wasn't a LineString. Totally skip.
+                // TODO Fix upstream, because b.encode() didn't fail and wound up generating
+                // something that breaks the protobuf parsing in the frontend
+                continue;
+            }
+
+            let encoded = b.encode()?;
+            bytes_so_far += encoded.len();
+            layer_bytes += encoded.len();
+            // TODO Note we don't use the layer size, because it's expensive to constantly
+            // protobuf encode it. This could overcount (ignoring properties) but also undercount
+            // (the encoded geometry is further compacted by protobuf?)
+            if let Some(limit) = options.limit_size_bytes {
+                if bytes_so_far > limit {
+                    log::trace!(
+                        "Tile {current_tile_id} reached the size limit after {} features",
+                        total_features + layer.num_features()
+                    );
+                    tile_full = true;
+                    skipped = true;
+                    break;
+                }
+            }
+            if let Some(limit) = layer_options.limit_size_bytes {
+                if layer_bytes > limit {
+                    log::trace!(
+                        "Layer {} of tile {current_tile_id} reached its size limit after {} features",
+                        layer_options.name,
+                        layer.num_features()
+                    );
+                    skipped = true;
+                    break;
+                }
+            }
+
+            let id = layer.num_features() as u64;
+            // The ownership swaps between layer and write_feature due to how feature properties
+            // are encoded
+            let mut write_feature = layer.into_feature(encoded);
+            write_feature.set_id(id);
+
+            if let Some(ref props) = feature.properties {
+                for (key, value) in props {
+                    if !options.attributes.keeps(key) {
+                        continue;
                     }
-                    Value::Object(x) => {
-                        write_feature.add_tag_string(key, &serde_json::to_string(&x)?)
+                    match value {
+                        Value::Null => {}
+                        Value::Bool(x) => write_feature.add_tag_bool(key, *x),
+                        Value::Number(x) => {
+                            // TODO Other variations, and maybe use float?
+                            if let Some(x) = x.as_f64() {
+                                write_feature.add_tag_double(key, x);
+                            }
+                        }
+                        Value::String(x) => write_feature.add_tag_string(key, x),
+                        // Encode other cases as strings, like tippecanoe. Note this is probably
+                        // bad in the input; unless the possible cases for arrays and objects are
+                        // small, it'll take lots to encode these
+                        Value::Array(x) => {
+                            write_feature.add_tag_string(key, &serde_json::to_string(&x)?)
+                        }
+                        Value::Object(x) => {
+                            write_feature.add_tag_string(key, &serde_json::to_string(&x)?)
+                        }
                     }
                 }
             }
+
+            layer = write_feature.into_layer();
         }
 
-        layer = write_feature.into_layer();
+        // Leave out empty layers
+        if layer.num_features() > 0 {
+            total_features += layer.num_features();
+            tile.add_layer(layer)?;
+        }
     }
 
-    let num_features = layer.num_features();
-    if num_features == 0 {
+    if total_features == 0 {
         // Nothing fit in this tile, just skip it!
         return Ok(None);
     }
 
-    tile.add_layer(layer)?;
-    progress.set_style(ProgressStyle::with_template("[{elapsed_precise}] {msg}").unwrap());
-    progress.finish_with_message(format!(
-        "Added {} features into {}, costing {}{}",
-        HumanCount(num_features as u64),
-        current_tile_id,
-        // TODO Maybe this is slow and we should use to_bytes() once
-        HumanBytes(tile.compute_size() as u64),
-        if skipped {
-            " (skipping some features after hitting size limit)"
-        } else {
-            ""
-        }
-    ));
+    if let Some(ref observer) = options.progress {
+        observer.tile_made(TileReport {
+            z: current_tile_id.z(),
+            x: current_tile_id.x(),
+            y: current_tile_id.y(),
+            num_features: total_features,
+            // TODO Maybe this is slow and we should use to_bytes() once
+            bytes: tile.compute_size(),
+            hit_size_limit: skipped,
+        });
+    }
 
     Ok(Some((current_tile_id, tile)))
 }
 
-fn progress_bar_for_count(count: usize) -> ProgressBar {
-    ProgressBar::new(count as u64).with_style(ProgressStyle::with_template(
-        "[{elapsed_precise}] [{wide_bar:.cyan/blue}] {human_pos}/{human_len} ({per_sec}, {eta})").unwrap())
+/// Adds points to the encoder, returning true if any of them are within this tile
+fn add_points(
+    b: &mut GeomEncoder<f64>,
+    points: impl Iterator<Item = geo_types::Coord>,
+    transform: &Transform<f64>,
+    extent: u32,
+) -> Result<bool> {
+    let mut any = false;
+    for pt in points {
+        // Transform to 0-1 tile coords (not sure why this doesnt work with passing the
+        // transform through)
+        let transformed_pt = *transform * (pt.x, pt.y);
+
+        // If any part of the LineString is within this tile, keep the whole thing. No
+        // clipping yet.
+        if transformed_pt.x >= 0.0
+            && transformed_pt.x <= 1.0
+            && transformed_pt.y >= 0.0
+            && transformed_pt.y <= 1.0
+        {
+            any = true;
+        }
+
+        b.add_point(
+            transformed_pt.x * extent as f64,
+            transformed_pt.y * extent as f64,
+        )?;
+    }
+    Ok(any)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    /// `count` lines around London, the same ones every time. Most are short, but every tenth one
+    /// crosses dozens of tiles at the highest zooms. Each has an `id`, and a `count` to sort by.
+    pub(crate) fn test_lines(count: usize) -> Vec<geojson::Feature> {
+        // A fixed linear congruential generator, returning numbers from 0 to 1
+        let mut state = 1_u64;
+        let mut random = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1_u64 << 53) as f64
+        };
+        (0..count)
+            .map(|idx| {
+                let length = if idx % 10 == 0 { 1.0 } else { 0.01 };
+                let start = vec![-0.3 + random() * 0.6, 51.3 + random() * 0.4];
+                let end = vec![
+                    start[0] + (random() - 0.5) * length,
+                    start[1] + (random() - 0.5) * length,
+                ];
+                geojson::Feature {
+                    geometry: Some(geojson::Geometry::new(geojson::Value::LineString(vec![
+                        start, end,
+                    ]))),
+                    properties: serde_json::json!({ "id": idx, "count": idx % 7 })
+                        .as_object()
+                        .cloned(),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    /// The `id` of each feature from each layer, in order
+    fn ids(features: Vec<(&Layer, Vec<FeatureRef>)>) -> Vec<Vec<u64>> {
+        features
+            .into_iter()
+            .map(|(_, features)| {
+                let mut ids: Vec<u64> = features
+                    .iter()
+                    .map(|feature| feature.properties().unwrap()["id"].as_u64().unwrap())
+                    .collect();
+                ids.sort_unstable();
+                ids
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_buckets_match_rtree() {
+        for bbox in [None, Some(geo_types::Rect::new((-0.3, 51.3), (0.2, 51.6)))] {
+            // The long lines are bucketed at the lower zooms, but touch too many tiles at the higher
+            let options = Options {
+                zoom_levels: (8..=12).collect(),
+                bbox,
+                ..Default::default()
+            };
+            let inputs = vec![(
+                LayerOptions::new("lines"),
+                test_lines(200).into_iter().map(Ok),
+            )];
+            let loaded = load_inputs(inputs, &options).unwrap();
+            let (tiler, _) = Tiler::new(borrow_layers(&loaded), options).unwrap();
+            let (mut bucketed, mut wide) = (0, 0);
+            for zoom in &tiler.zoom_levels {
+                let buckets = tiler.bucket_features(*zoom, tiler.bbox.to_tiles(*zoom));
+                bucketed += buckets.tiles.len();
+                wide += buckets.wide.size();
+                for tile_id in tiles_covering(&[*zoom], &tiler.bbox).unwrap() {
+                    assert_eq!(
+                        ids(tiler.features_in_bucket(tile_id, &buckets).unwrap()),
+                        ids(tiler.features_in_tile(tile_id).unwrap()),
+                        "tile {tile_id}"
+                    );
+                }
+            }
+            assert!(bucketed > 0);
+            assert!(wide > 0);
+        }
+    }
+
+    /// Every tile in the archive, by tile ID
+    pub(crate) fn all_tiles<R: Read + Seek>(archive: &mut PMTiles<R>) -> BTreeMap<u64, Vec<u8>> {
+        let tile_ids: Vec<u64> = archive.tile_ids().into_iter().copied().collect();
+        tile_ids
+            .into_iter()
+            .map(|tile_id| (tile_id, archive.get_tile_by_id(tile_id).unwrap().unwrap()))
+            .collect()
+    }
+
+    /// `test_lines`, plus one more line near the middle
+    fn changed_lines() -> Vec<geojson::Feature> {
+        let mut lines = test_lines(200);
+        lines.push(geojson::Feature {
+            geometry: Some(geojson::Geometry::new(geojson::Value::LineString(vec![
+                vec![-0.04, 51.46],
+                vec![-0.01, 51.49],
+            ]))),
+            properties: serde_json::json!({ "id": 1000, "count": 0 })
+                .as_object()
+                .cloned(),
+            ..Default::default()
+        });
+        lines
+    }
+
+    fn lines_options(zoom_levels: RangeInclusive<u32>) -> Options {
+        Options {
+            zoom_levels: zoom_levels.collect(),
+            sort_by_key: Some("id".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn lines_to_pmtiles(
+        layers: &[&str],
+        lines: Vec<geojson::Feature>,
+        options: Options,
+    ) -> PMTiles<Cursor<&'static [u8]>> {
+        let inputs = layers
+            .iter()
+            .map(|name| (LayerOptions::new(*name), lines.clone().into_iter().map(Ok)))
+            .collect();
+        layers_to_pmtiles(inputs, options).unwrap()
+    }
+
+    #[test]
+    fn test_update_pmtiles() {
+        let mut existing = lines_to_pmtiles(&["lines"], test_lines(200), lines_options(8..=12));
+        let old_tiles = all_tiles(&mut existing);
+        let mut fresh = lines_to_pmtiles(&["lines"], changed_lines(), lines_options(8..=12));
+        let fresh_tiles = all_tiles(&mut fresh);
+
+        let changed_area = geo_types::Rect::new((-0.04, 51.46), (-0.01, 51.49));
+        let mut output = Vec::new();
+        update_pmtiles(
+            existing,
+            vec![(
+                LayerOptions::new("lines"),
+                changed_lines().into_iter().map(Ok),
+            )],
+            lines_options(8..=12),
+            changed_area,
+            &mut output,
+        )
+        .unwrap();
+        let new_tiles = all_tiles(&mut PMTiles::from_reader(Cursor::new(output)).unwrap());
+
+        let changed = BBox::from(&changed_area);
+        let (mut kept, mut regenerated) = (0, 0);
+        for tile_id in old_tiles.keys().chain(fresh_tiles.keys()) {
+            let (z, x, y) = pmtiles2::util::zxy(*tile_id).unwrap();
+            let (x1, y1, x2, y2) = changed.to_tiles(z as u32);
+            if (x1 as u64..=x2 as u64).contains(&x) && (y1 as u64..=y2 as u64).contains(&y) {
+                assert_eq!(
+                    new_tiles.get(tile_id),
+                    fresh_tiles.get(tile_id),
+                    "{z}/{x}/{y}"
+                );
+                assert_ne!(
+                    new_tiles.get(tile_id),
+                    old_tiles.get(tile_id),
+                    "{z}/{x}/{y}"
+                );
+                regenerated += 1;
+            } else {
+                assert_eq!(
+                    new_tiles.get(tile_id),
+                    old_tiles.get(tile_id),
+                    "{z}/{x}/{y}"
+                );
+                kept += 1;
+            }
+        }
+        assert!(kept > 100);
+        assert!(regenerated >= 5);
+    }
+
+    #[test]
+    fn test_regenerate_zooms() {
+        let mut existing =
+            lines_to_pmtiles(&["lines", "old"], test_lines(200), lines_options(6..=10));
+        let old_tiles = all_tiles(&mut existing);
+        let mut fresh = lines_to_pmtiles(&["lines"], changed_lines(), lines_options(10..=12));
+        let fresh_tiles = all_tiles(&mut fresh);
+
+        let mut output = Vec::new();
+        let archive = regenerate_zooms(
+            existing,
+            vec![(
+                LayerOptions::new("lines"),
+                changed_lines().into_iter().map(Ok),
+            )],
+            lines_options(10..=12),
+            &mut output,
+        )
+        .unwrap();
+        let new_tiles = all_tiles(&mut PMTiles::from_reader(Cursor::new(output)).unwrap());
+
+        let (old_zooms, new_zooms): (BTreeMap<_, _>, BTreeMap<_, _>) = new_tiles
+            .into_iter()
+            .partition(|(tile_id, _)| pmtiles2::util::zxy(*tile_id).unwrap().0 < 10);
+        assert!(!old_zooms.is_empty());
+        assert_eq!(
+            old_zooms,
+            old_tiles
+                .into_iter()
+                .filter(|(tile_id, _)| pmtiles2::util::zxy(*tile_id).unwrap().0 < 10)
+                .collect()
+        );
+        assert!(new_zooms == fresh_tiles);
+
+        assert_eq!((archive.min_zoom, archive.max_zoom), (6, 12));
+        let vector_layers: Vec<_> = archive.meta_data.unwrap()["vector_layers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|layer| {
+                (
+                    layer["id"].as_str().unwrap().to_string(),
+                    layer["minzoom"].as_u64().unwrap(),
+                    layer["maxzoom"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vector_layers,
+            [("lines".to_string(), 6, 12), ("old".to_string(), 6, 10)]
+        );
+    }
 }
@@ -1,28 +1,52 @@
-use std::io::BufReader;
+use std::process::ExitCode;
 
 use anyhow::Result;
-use fs_err::File;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 
-fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        panic!("Pass in a .geojson file");
-    }
+mod cli;
+
+/// Converts GeoJSON Points and LineStrings, or any other supported input, into vector tiles
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[command(flatten)]
+    log: cli::logger::LogArgs,
+}
 
-    // TODO use clap
-    let options = lines2pmtiles::Options {
-        layer_name: "layer1".to_string(),
-        description: None,
-        sort_by_key: Some("count".to_string()),
-        zoom_levels: (0..13).collect(),
-        // This is so much less than 500KB, but the final tile size is still big
-        limit_size_bytes: Some(200 * 1024),
-    };
+#[derive(Subcommand)]
+enum Command {
+    Convert(Box<cli::convert::ConvertArgs>),
+    Inspect(cli::inspect::InspectArgs),
+    Merge(cli::merge::MergeArgs),
+    Serve(cli::serve::ServeArgs),
+    /// Prints a shell completion script
+    #[command(hide = true)]
+    Completions(cli::generate::CompletionsArgs),
+    /// Prints a man page
+    #[command(hide = true)]
+    Manpage,
+}
 
-    let reader = BufReader::new(File::open(&args[1])?);
-    let pmtiles = lines2pmtiles::geojson_to_pmtiles(reader, options)?;
-    println!("Writing out.pmtiles");
-    let mut file = File::create("out.pmtiles")?;
-    pmtiles.to_writer(&mut file)?;
-    Ok(())
+fn main() -> Result<ExitCode> {
+    let matches = cli::with_env_vars(Cli::command()).get_matches();
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    cli::logger::init(&cli.log);
+    if let Command::Convert(ref mut args) = cli.command {
+        if let Some(ref path) = args.config {
+            let convert_matches = matches.subcommand_matches("convert").unwrap();
+            **args = cli::config::apply(path, convert_matches)?;
+        }
+    }
+
+    match cli.command {
+        Command::Convert(args) => return cli::convert::run(*args),
+        Command::Inspect(args) => cli::inspect::run(args)?,
+        Command::Merge(args) => cli::merge::run(args)?,
+        Command::Serve(args) => cli::serve::run(args)?,
+        Command::Completions(args) => cli::generate::completions(args, Cli::command()),
+        Command::Manpage => cli::generate::manpage(Cli::command()),
+    }
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,145 @@
+use std::io::Read;
+
+use geo::algorithm::bool_ops::BooleanOps;
+use geo::algorithm::contains::Contains;
+use geo::algorithm::map_coords::MapCoords;
+use geo_types::{Geometry, MultiLineString, MultiPolygon};
+use geojson::FeatureReader;
+
+use crate::{math, ConversionError, TreeFeature};
+
+/// Reads every Polygon and MultiPolygon from a GeoJSON file into one MultiPolygon, to be used as
+/// `Options::mask`.
+pub fn read_mask<R: Read>(geojson_input: R) -> Result<MultiPolygon<f64>, ConversionError> {
+    let mut polygons = Vec::new();
+    for f in FeatureReader::from_reader(geojson_input).features() {
+        let Some(geometry) = f.map_err(anyhow::Error::from)?.geometry else {
+            continue;
+        };
+        match geometry.try_into().map_err(anyhow::Error::from)? {
+            Geometry::Polygon(polygon) => polygons.push(polygon),
+            Geometry::MultiPolygon(multi_polygon) => polygons.extend(multi_polygon),
+            _ => {}
+        }
+    }
+    if polygons.is_empty() {
+        return Err(ConversionError::InvalidOptions(
+            "The mask doesn't contain any polygons".to_string(),
+        ));
+    }
+    Ok(MultiPolygon::new(polygons))
+}
+
+/// Transforms a WGS84 mask into the same web mercator space as `TreeFeature`s
+pub fn project_mask(mask: &MultiPolygon<f64>) -> MultiPolygon<f64> {
+    mask.map_coords(|p| math::wgs84_to_web_mercator([p.x, p.y]).into())
+}
+
+/// Returns the part of the feature inside the mask, or nothing if it's totally outside. The mask
+/// must already be projected.
+pub fn clip(mut feature: TreeFeature, mask: &MultiPolygon<f64>) -> Option<TreeFeature> {
+    let lines = match feature.geometry {
+        Geometry::Point(pt) => {
+            return if mask.contains(&pt) {
+                Some(feature)
+            } else {
+                None
+            };
+        }
+        Geometry::LineString(line_string) => MultiLineString::new(vec![line_string]),
+        Geometry::MultiLineString(multi_line_string) => multi_line_string,
+        // Other types aren't tiled anyway
+        _ => return None,
+    };
+
+    let mut clipped = mask.clip(&lines, false);
+    clipped.0.retain(|line_string| line_string.0.len() > 1);
+    feature.geometry = match clipped.0.len() {
+        0 => return None,
+        1 => Geometry::LineString(clipped.0.pop().unwrap()),
+        _ => Geometry::MultiLineString(clipped),
+    };
+    Some(feature)
+}
+
+#[cfg(test)]
+mod tests {
+    use geo_types::{LineString, Point, Polygon};
+    use serde_json::json;
+
+    use super::*;
+
+    /// From `min_x` to `max_x`, and 0 to 10 up
+    fn rect(min_x: f64, max_x: f64) -> Polygon {
+        Polygon::new(
+            LineString::from(vec![
+                (min_x, 0.0),
+                (max_x, 0.0),
+                (max_x, 10.0),
+                (min_x, 10.0),
+                (min_x, 0.0),
+            ]),
+            Vec::new(),
+        )
+    }
+
+    fn clip_geometry(geometry: impl Into<Geometry>, mask: &MultiPolygon) -> Option<Geometry> {
+        let feature = TreeFeature {
+            geometry: geometry.into(),
+            properties: json!({ "name": "A" }).as_object().cloned(),
+        };
+        let clipped = clip(feature, mask)?;
+        assert_eq!(
+            clipped.properties,
+            json!({ "name": "A" }).as_object().cloned()
+        );
+        Some(clipped.geometry)
+    }
+
+    #[test]
+    fn test_clip() {
+        let mask = MultiPolygon::new(vec![rect(0.0, 10.0)]);
+
+        // Crossing the edge
+        assert_eq!(
+            clip_geometry(LineString::from(vec![(-5.0, 5.0), (5.0, 5.0)]), &mask),
+            Some(LineString::from(vec![(0.0, 5.0), (5.0, 5.0)]).into())
+        );
+        // Entirely inside
+        assert_eq!(
+            clip_geometry(LineString::from(vec![(2.0, 2.0), (8.0, 8.0)]), &mask),
+            Some(LineString::from(vec![(2.0, 2.0), (8.0, 8.0)]).into())
+        );
+        // Entirely outside
+        assert_eq!(
+            clip_geometry(LineString::from(vec![(20.0, 2.0), (30.0, 8.0)]), &mask),
+            None
+        );
+        assert_eq!(
+            clip_geometry(Point::new(5.0, 5.0), &mask),
+            Some(Point::new(5.0, 5.0).into())
+        );
+        assert_eq!(clip_geometry(Point::new(15.0, 5.0), &mask), None);
+        // Only lines and points are kept
+        assert_eq!(clip_geometry(rect(2.0, 8.0), &mask), None);
+    }
+
+    #[test]
+    fn test_clip_split() {
+        // Two polygons, and a line going in and out of both
+        let mask = MultiPolygon::new(vec![rect(0.0, 10.0), rect(20.0, 30.0)]);
+        let Some(Geometry::MultiLineString(mut clipped)) =
+            clip_geometry(LineString::from(vec![(-5.0, 5.0), (35.0, 5.0)]), &mask)
+        else {
+            panic!("The line should be split in two");
+        };
+        clipped.0.sort_by(|a, b| a.0[0].x.total_cmp(&b.0[0].x));
+        assert_eq!(
+            clipped,
+            MultiLineString::new(vec![
+                LineString::from(vec![(0.0, 5.0), (10.0, 5.0)]),
+                LineString::from(vec![(20.0, 5.0), (30.0, 5.0)]),
+            ])
+        );
+    }
+}
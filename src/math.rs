@@ -1,4 +1,5 @@
 use std::f64;
+use std::ops::RangeInclusive;
 
 use geojson::{Feature, Value};
 
@@ -11,13 +12,47 @@ pub fn wgs84_to_web_mercator(c: [f64; 2]) -> [f64; 2] {
     static D2R: f64 = f64::consts::PI / 180.0;
 
     [
-        (A * c[0] * D2R).max(-MAXEXTENT).min(MAXEXTENT) as f64,
+        (A * c[0] * D2R).max(-MAXEXTENT).min(MAXEXTENT),
         (A * (((f64::consts::PI * 0.25f64) + (0.5f64 * c[1] * D2R)).tan()).ln())
             .max(-MAXEXTENT)
-            .min(MAXEXTENT) as f64,
+            .min(MAXEXTENT),
     ]
 }
 
+// Mercator to WGS84
+pub fn web_mercator_to_wgs84(c: [f64; 2]) -> [f64; 2] {
+    static A: f64 = 6378137.0;
+
+    [
+        (c[0] / A).to_degrees(),
+        (2.0 * (c[1] / A).exp().atan() - f64::consts::FRAC_PI_2).to_degrees(),
+    ]
+}
+
+/// The furthest north or south web mercator goes, in degrees
+pub const MAX_LATITUDE: f64 = 85.05112877980659;
+
+/// The width of the whole world in web mercator, which is also its height
+pub const WORLD_SIZE: f64 = 2.0 * 20037508.342789244;
+
+/// Where a web mercator point is, in fractional tiles from the top left, at a zoom with
+/// `num_tiles` across
+pub fn web_mercator_to_tile(pt: [f64; 2], num_tiles: u32) -> [f64; 2] {
+    let scale = num_tiles as f64 / WORLD_SIZE;
+    [
+        (pt[0] + WORLD_SIZE / 2.0) * scale,
+        (WORLD_SIZE / 2.0 - pt[1]) * scale,
+    ]
+}
+
+/// The tiles along one axis touching the span from `min` to `max`, in fractional tiles. A span
+/// ending on an edge touches the tiles on both sides, like envelopes in the R-tree.
+pub fn tiles_touching(min: f64, max: f64, num_tiles: u32) -> RangeInclusive<u32> {
+    let first = ((min.ceil() - 1.0).max(0.0) as u32).min(num_tiles - 1);
+    let last = (max.floor().max(0.0) as u32).min(num_tiles - 1);
+    first..=last
+}
+
 #[derive(Debug)]
 pub struct BBox {
     pub min_lon: f64,
@@ -26,6 +61,17 @@ pub struct BBox {
     pub max_lat: f64,
 }
 
+impl From<&geo_types::Rect<f64>> for BBox {
+    fn from(rect: &geo_types::Rect<f64>) -> Self {
+        Self {
+            min_lon: rect.min().x,
+            min_lat: rect.min().y,
+            max_lon: rect.max().x,
+            max_lat: rect.max().y,
+        }
+    }
+}
+
 impl BBox {
     pub fn empty() -> Self {
         Self {
@@ -36,20 +82,25 @@ impl BBox {
         }
     }
 
-    fn add_point(&mut self, pt: &geojson::Position) {
-        self.min_lon = self.min_lon.min(pt[0]);
-        self.min_lat = self.min_lat.min(pt[1]);
-        self.max_lon = self.max_lon.max(pt[0]);
-        self.max_lat = self.max_lat.max(pt[1]);
+    fn add_point(&mut self, lon: f64, lat: f64) {
+        self.min_lon = self.min_lon.min(lon);
+        self.min_lat = self.min_lat.min(lat);
+        self.max_lon = self.max_lon.max(lon);
+        self.max_lat = self.max_lat.max(lat);
     }
 
     pub fn add(&mut self, f: &Feature) {
         if let Some(ref geometry) = f.geometry {
             match geometry.value {
-                Value::Point(ref pt) => self.add_point(pt),
+                Value::Point(ref pt) => self.add_point(pt[0], pt[1]),
                 Value::LineString(ref line_string) => {
                     for pt in line_string {
-                        self.add_point(pt);
+                        self.add_point(pt[0], pt[1]);
+                    }
+                }
+                Value::MultiLineString(ref multi_line_string) => {
+                    for pt in multi_line_string.iter().flatten() {
+                        self.add_point(pt[0], pt[1]);
                     }
                 }
                 _ => {}
@@ -57,6 +108,44 @@ impl BBox {
         }
     }
 
+    /// Like `add`, for a geometry that isn't GeoJSON
+    pub fn add_geometry(&mut self, geometry: &geo_types::Geometry<f64>) {
+        let points: Box<dyn Iterator<Item = &geo_types::Coord>> = match geometry {
+            geo_types::Geometry::Point(pt) => Box::new(std::iter::once(&pt.0)),
+            geo_types::Geometry::LineString(line_string) => Box::new(line_string.0.iter()),
+            geo_types::Geometry::MultiLineString(multi_line_string) => Box::new(
+                multi_line_string
+                    .iter()
+                    .flat_map(|line_string| line_string.0.iter()),
+            ),
+            _ => return,
+        };
+        for pt in points {
+            self.add_point(pt.x, pt.y);
+        }
+    }
+
+    /// True if nothing has been added, or an intersection left nothing
+    pub fn is_empty(&self) -> bool {
+        self.min_lon > self.max_lon || self.min_lat > self.max_lat
+    }
+
+    /// Grows this to also cover `other`
+    pub fn union(&mut self, other: &BBox) {
+        self.min_lon = self.min_lon.min(other.min_lon);
+        self.min_lat = self.min_lat.min(other.min_lat);
+        self.max_lon = self.max_lon.max(other.max_lon);
+        self.max_lat = self.max_lat.max(other.max_lat);
+    }
+
+    /// Shrinks this to the part also covered by `rect`
+    pub fn intersect(&mut self, rect: &geo_types::Rect<f64>) {
+        self.min_lon = self.min_lon.max(rect.min().x);
+        self.min_lat = self.min_lat.max(rect.min().y);
+        self.max_lon = self.max_lon.min(rect.max().x);
+        self.max_lat = self.max_lat.min(rect.max().y);
+    }
+
     pub fn to_tiles(&self, zoom: u32) -> (u32, u32, u32, u32) {
         let (x1, y1) = lon_lat_to_tile(self.min_lon, self.min_lat, zoom);
         let (x2, y2) = lon_lat_to_tile(self.max_lon, self.max_lat, zoom);
@@ -68,7 +157,7 @@ impl BBox {
 // Thanks to https://github.com/MilesMcBain/slippymath/blob/master/R/slippymath.R
 // Use https://crates.io/crates/tile-grid or something instead?
 // Alternatively https://wiki.openstreetmap.org/wiki/Slippy_map_tilenames#Python
-fn lon_lat_to_tile(lon: f64, lat: f64, zoom: u32) -> (u32, u32) {
+pub fn lon_lat_to_tile(lon: f64, lat: f64, zoom: u32) -> (u32, u32) {
     let lon_radians = lon.to_radians();
     let lat_radians = lat.to_radians();
 
@@ -85,3 +174,14 @@ fn lon_lat_to_tile(lon: f64, lat: f64, zoom: u32) -> (u32, u32) {
         (y * num_tiles).floor() as u32,
     )
 }
+
+/// The inverse of `lon_lat_to_tile`, taking fractional tile coordinates to reach inside tiles
+pub fn tile_to_lon_lat(x: f64, y: f64, zoom: u32) -> [f64; 2] {
+    let num_tiles = 2u32.pow(zoom) as f64;
+    let lon = x / num_tiles * 360.0 - 180.0;
+    let lat = (f64::consts::PI * (1.0 - 2.0 * y / num_tiles))
+        .sinh()
+        .atan()
+        .to_degrees();
+    [lon, lat]
+}
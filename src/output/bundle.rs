@@ -0,0 +1,81 @@
+use std::io::{BufWriter, Cursor, Write};
+
+use anyhow::{bail, Result};
+use fs_err::File;
+use pmtiles2::PMTiles;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use super::directory::for_each_tile_file;
+
+/// Writes the same `{z}/{x}/{y}.pbf` tree and `tiles.json` as `write_directory`, but bundled into
+/// one uncompressed tar file. Timestamps are left at zero, so the same tiles give the same file.
+pub fn write_tar(mut pmtiles: PMTiles<Cursor<&'static [u8]>>, path: &str) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for_each_tile_file(&mut pmtiles, |name, data| {
+        file.write_all(&tar_header(name, data.len() as u64)?)?;
+        file.write_all(data)?;
+        // Contents are padded to whole blocks
+        let padding = (512 - data.len() % 512) % 512;
+        file.write_all(&vec![0; padding])?;
+        Ok(())
+    })?;
+    // The archive ends with two empty blocks
+    file.write_all(&[0; 1024])?;
+    file.flush()?;
+    Ok(())
+}
+
+/// A ustar header for a regular file
+fn tar_header(name: &str, size: u64) -> Result<[u8; 512]> {
+    if name.len() > 100 {
+        bail!("{name} is too long for a tar file");
+    }
+    let mut header = [0; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    // Octal fields are zero-padded and end with a NUL
+    let octal = |header: &mut [u8; 512], offset: usize, len: usize, value: u64| {
+        let field = format!("{value:0width$o}\0", width = len - 1);
+        header[offset..offset + len].copy_from_slice(field.as_bytes());
+    };
+    // The mode, uid, gid, size, and mtime
+    octal(&mut header, 100, 8, 0o644);
+    octal(&mut header, 108, 8, 0);
+    octal(&mut header, 116, 8, 0);
+    octal(&mut header, 124, 12, size);
+    octal(&mut header, 136, 12, 0);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // The checksum is calculated with its own field full of spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|x| *x as u32).sum();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    Ok(header)
+}
+
+/// Like `write_tar`, but a zip file. Tiles are deflated, unless they're already compressed images.
+pub fn write_zip(mut pmtiles: PMTiles<Cursor<&'static [u8]>>, path: &str) -> Result<()> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let method = if pmtiles.tile_type == pmtiles2::TileType::Mvt {
+        CompressionMethod::Deflated
+    } else {
+        CompressionMethod::Stored
+    };
+    for_each_tile_file(&mut pmtiles, |name, data| {
+        let method = if name == "tiles.json" {
+            CompressionMethod::Deflated
+        } else {
+            method
+        };
+        zip.start_file(
+            name,
+            SimpleFileOptions::default().compression_method(method),
+        )?;
+        zip.write_all(data)?;
+        Ok(())
+    })?;
+    zip.finish()?;
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use geojson::FeatureCollection;
+use pmtiles2::util::{decompress_all, zxy};
+use pmtiles2::Compression;
+
+use super::TileSink;
+use crate::input::TileDecoder;
+
+/// Decodes each vector tile as it's generated and writes its features to
+/// `{dir}/{z}/{x}/{y}.geojson`, for checking what survived sorting, size limits, and clipping. Each
+/// feature's layer is in a `layer` foreign member. Existing files in `dir` are overwritten.
+pub struct GeoJsonDebugSink {
+    dir: PathBuf,
+    pixel_coords: bool,
+    compression: Compression,
+}
+
+impl GeoJsonDebugSink {
+    /// With `pixel_coords`, coordinates stay in the tile's own pixels instead of WGS84.
+    /// `compression` must match `ArchiveOptions::tile_compression`.
+    pub fn new(dir: impl Into<PathBuf>, pixel_coords: bool, compression: Compression) -> Self {
+        Self {
+            dir: dir.into(),
+            pixel_coords,
+            compression,
+        }
+    }
+}
+
+impl TileSink for GeoJsonDebugSink {
+    fn add_tile(&mut self, tile_id: u64, data: Vec<u8>) -> Result<()> {
+        let (z, x, y) = zxy(tile_id)?;
+        let mut decoder = TileDecoder::new(None);
+        decoder.pixel_coords = self.pixel_coords;
+        decoder.decode(&decompress_all(self.compression, &data)?, z, x, y)?;
+
+        let column = self.dir.join(z.to_string()).join(x.to_string());
+        fs_err::create_dir_all(&column)?;
+        let collection = FeatureCollection {
+            bbox: None,
+            features: decoder.features,
+            foreign_members: None,
+        };
+        fs_err::write(
+            column.join(format!("{y}.geojson")),
+            serde_json::to_string_pretty(&collection)?,
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use pmtiles2::util::{decompress_all, zxy};
+use pmtiles2::PMTiles;
+
+use super::tile_extension;
+use super::tilejson::tilejson;
+
+/// Writes every tile uncompressed to `{dir}/{z}/{x}/{y}.pbf` (or `.png` and so on for raster
+/// tiles), plus `{dir}/tiles.json` describing
+/// them with a relative tile URL. A previous output in `dir` is replaced, but to avoid deleting
+/// anything else, a non-empty directory without a `tiles.json` is an error.
+pub fn write_directory(mut pmtiles: PMTiles<Cursor<&'static [u8]>>, dir: &str) -> Result<()> {
+    let dir = Path::new(dir);
+    if dir.exists() {
+        if !dir.join("tiles.json").exists() && fs_err::read_dir(dir)?.next().is_some() {
+            bail!(
+                "{} already exists and doesn't look like tiles from a previous run",
+                dir.display()
+            );
+        }
+        fs_err::remove_dir_all(dir)?;
+    }
+    fs_err::create_dir_all(dir)?;
+
+    for_each_tile_file(&mut pmtiles, |name, data| {
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs_err::create_dir_all(parent)?;
+        }
+        fs_err::write(path, data)?;
+        Ok(())
+    })
+}
+
+/// Calls `write` with the relative path and uncompressed contents of each file in the tile tree,
+/// like `12/2030/1318.pbf`, in order, and then `tiles.json`
+pub(crate) fn for_each_tile_file(
+    pmtiles: &mut PMTiles<Cursor<&'static [u8]>>,
+    mut write: impl FnMut(&str, &[u8]) -> Result<()>,
+) -> Result<()> {
+    let extension = tile_extension(pmtiles.tile_type);
+    let mut tile_ids: Vec<u64> = pmtiles.tile_ids().into_iter().cloned().collect();
+    tile_ids.sort();
+    for tile_id in tile_ids {
+        let Some(data) = pmtiles.get_tile_by_id(tile_id)? else {
+            continue;
+        };
+        let (z, x, y) = zxy(tile_id)?;
+        write(
+            &format!("{z}/{x}/{y}.{extension}"),
+            &decompress_all(pmtiles.tile_compression, &data)?,
+        )?;
+    }
+
+    let json = tilejson(pmtiles, &format!("{{z}}/{{x}}/{{y}}.{extension}"));
+    write(
+        "tiles.json",
+        serde_json::to_string_pretty(&json)?.as_bytes(),
+    )
+}
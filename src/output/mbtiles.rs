@@ -0,0 +1,88 @@
+use std::io::Cursor;
+
+use anyhow::Result;
+use pmtiles2::util::{compress_all, decompress_all, zxy};
+use pmtiles2::{Compression, PMTiles, TileType};
+use rusqlite::{params, Connection};
+
+use super::tile_extension;
+
+/// Copies every tile and the metadata into a new MBTiles file, replacing any existing file. Tiles
+/// are gzipped, as most MBTiles servers expect for vector tiles.
+pub fn write_mbtiles(mut pmtiles: PMTiles<Cursor<&'static [u8]>>, path: &str) -> Result<()> {
+    if std::path::Path::new(path).exists() {
+        fs_err::remove_file(path)?;
+    }
+    let mut conn = Connection::open(path)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "CREATE TABLE metadata (name TEXT, value TEXT);
+         CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+         CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);",
+    )?;
+
+    let mut metadata = vec![
+        ("format", tile_extension(pmtiles.tile_type).to_string()),
+        (
+            "bounds",
+            format!(
+                "{},{},{},{}",
+                pmtiles.min_longitude,
+                pmtiles.min_latitude,
+                pmtiles.max_longitude,
+                pmtiles.max_latitude
+            ),
+        ),
+        (
+            "center",
+            format!(
+                "{},{},{}",
+                pmtiles.center_longitude, pmtiles.center_latitude, pmtiles.center_zoom
+            ),
+        ),
+        ("minzoom", pmtiles.min_zoom.to_string()),
+        ("maxzoom", pmtiles.max_zoom.to_string()),
+    ];
+    if let Some(ref json) = pmtiles.meta_data {
+        let name = json["vector_layers"][0]["id"].as_str().unwrap_or("tiles");
+        metadata.push(("name", name.to_string()));
+        if let Some(description) = json["description"].as_str() {
+            metadata.push(("description", description.to_string()));
+        }
+        if pmtiles.tile_type == TileType::Mvt {
+            let json = serde_json::json!({ "vector_layers": json["vector_layers"] });
+            metadata.push(("json", serde_json::to_string(&json)?));
+        }
+    }
+    for (name, value) in metadata {
+        tx.execute("INSERT INTO metadata VALUES (?1, ?2)", params![name, value])?;
+    }
+
+    let mut tile_ids: Vec<u64> = pmtiles.tile_ids().into_iter().cloned().collect();
+    tile_ids.sort();
+    {
+        let mut insert = tx.prepare("INSERT INTO tiles VALUES (?1, ?2, ?3, ?4)")?;
+        for tile_id in tile_ids {
+            let Some(mut data) = pmtiles.get_tile_by_id(tile_id)? else {
+                continue;
+            };
+            let compression = if pmtiles.tile_type == TileType::Mvt {
+                Compression::GZip
+            } else {
+                Compression::None
+            };
+            if pmtiles.tile_compression != compression {
+                data = compress_all(
+                    compression,
+                    &decompress_all(pmtiles.tile_compression, &data)?,
+                )?;
+            }
+            let (z, x, y) = zxy(tile_id)?;
+            // MBTiles rows count from the bottom
+            let tms_y = (1 << z) - 1 - y;
+            insert.execute(params![z, x as i64, tms_y as i64, data])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
@@ -0,0 +1,271 @@
+//! Writers for the output formats. Tiles are always generated the same way, as a `PMTiles`
+//! archive, and then copied into the chosen format. PMTiles can also be streamed out with
+//! `PMTilesWriter`, and tiles can be sent anywhere else with a `TileSink`.
+
+use std::io::{BufWriter, Cursor, Write};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use fs_err::File;
+use pmtiles2::{PMTiles, TileType};
+use tempfile::TempPath;
+
+use crate::ArchiveOptions;
+
+mod bundle;
+mod debug;
+mod directory;
+#[cfg(feature = "sqlite")]
+mod mbtiles;
+mod pmtiles;
+#[cfg(feature = "s3")]
+mod s3;
+mod sink;
+mod stream;
+mod tilejson;
+
+pub use self::bundle::{write_tar, write_zip};
+pub use self::debug::GeoJsonDebugSink;
+pub use self::directory::write_directory;
+#[cfg(feature = "sqlite")]
+pub use self::mbtiles::write_mbtiles;
+pub use self::pmtiles::PMTilesWriter;
+#[cfg(feature = "s3")]
+pub use self::s3::MultipartUpload;
+pub use self::sink::{PMTilesSink, TileCallback, TileSink};
+pub use self::stream::TileStreamSink;
+pub use self::tilejson::{tilejson, write_tilejson};
+
+/// The usual file extension for tiles of this type
+pub fn tile_extension(tile_type: TileType) -> &'static str {
+    match tile_type {
+        TileType::Png => "png",
+        TileType::Jpeg => "jpg",
+        TileType::WebP => "webp",
+        _ => "pbf",
+    }
+}
+
+/// `s3://` and `gs://` URLs are uploaded to an object store
+pub fn is_object_store_url(path: &str) -> bool {
+    path.starts_with("s3://") || path.starts_with("gs://")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    PMTiles,
+    MBTiles,
+    /// A `z/x/y.pbf` tree of files, or `z/x/y.png` for raster tiles
+    Directory,
+    /// The same tree of files, bundled into one file
+    Tar,
+    Zip,
+}
+
+impl OutputFormat {
+    /// A path without an extension, or ending with `/`, is a directory. Anything else unrecognized,
+    /// including `-` for stdout, is written as PMTiles.
+    pub fn from_path(path: &str) -> Self {
+        if path == "-" {
+            OutputFormat::PMTiles
+        } else if path.to_lowercase().ends_with(".mbtiles") {
+            OutputFormat::MBTiles
+        } else if path.to_lowercase().ends_with(".tar") {
+            OutputFormat::Tar
+        } else if path.to_lowercase().ends_with(".zip") {
+            OutputFormat::Zip
+        } else if path.ends_with('/') || Path::new(path).extension().is_none() {
+            OutputFormat::Directory
+        } else {
+            OutputFormat::PMTiles
+        }
+    }
+}
+
+/// Where to send tiles for the output at `path`, following `OutputFormat::from_path`. PMTiles are
+/// streamed out as tiles are generated, like `layers_to_pmtiles_writer`, clustered following
+/// `archive`. Other formats are collected in memory, then written with `write_output` at the end.
+/// PMTiles files and uploads are started right away, so problems show up before any tiles are
+/// generated. PMTiles files are written to `<path>.tmp` and only renamed once finished, so a
+/// failed run doesn't leave a partial archive behind.
+pub fn sink_for_path(path: &str, archive: &ArchiveOptions) -> Result<Box<dyn TileSink + Send>> {
+    let format = OutputFormat::from_path(path);
+    if is_object_store_url(path) {
+        if format != OutputFormat::PMTiles {
+            bail!("Only PMTiles can be uploaded to {path}");
+        }
+        #[cfg(feature = "s3")]
+        return Ok(Box::new(s3::UploadSink::new(path, archive.clustered)?));
+        #[cfg(not(feature = "s3"))]
+        bail!("Uploading to {path} needs the s3 feature");
+    }
+    match format {
+        OutputFormat::PMTiles if path == "-" => Ok(Box::new(
+            PMTilesSink::new(BufWriter::new(std::io::stdout()))?.with_clustered(archive.clustered),
+        )),
+        OutputFormat::PMTiles => {
+            let temp_path = TempPath::try_from_path(format!("{path}.tmp"))?;
+            let file = File::create(&temp_path)?;
+            Ok(Box::new(FileSink {
+                sink: Some(
+                    PMTilesSink::new(BufWriter::new(file))?.with_clustered(archive.clustered),
+                ),
+                temp_path: Some(temp_path),
+                path: path.to_string(),
+            }))
+        }
+        _ => Ok(Box::new(OutputSink {
+            path: path.to_string(),
+            format,
+            pmtiles: PMTiles::new(TileType::Unknown, pmtiles2::Compression::None),
+        })),
+    }
+}
+
+/// Streams PMTiles to a temporary file, deleted unless `finish` succeeds
+struct FileSink {
+    sink: Option<PMTilesSink<BufWriter<File>>>,
+    temp_path: Option<TempPath>,
+    path: String,
+}
+
+impl TileSink for FileSink {
+    fn add_tile(&mut self, tile_id: u64, data: Vec<u8>) -> Result<()> {
+        let Some(ref mut sink) = self.sink else {
+            bail!("Can't add tile {tile_id} after the archive is finished");
+        };
+        sink.add_tile(tile_id, data)
+    }
+
+    fn finish(&mut self, archive: &PMTiles<Cursor<&'static [u8]>>) -> Result<()> {
+        let (Some(mut sink), Some(temp_path)) = (self.sink.take(), self.temp_path.take()) else {
+            bail!("The archive is already finished");
+        };
+        sink.finish(archive)?;
+        // Close the file before moving it
+        drop(sink);
+        temp_path.persist(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Collects tiles for `write_output`
+struct OutputSink {
+    path: String,
+    format: OutputFormat,
+    pmtiles: PMTiles<Cursor<&'static [u8]>>,
+}
+
+impl TileSink for OutputSink {
+    fn add_tile(&mut self, tile_id: u64, data: Vec<u8>) -> Result<()> {
+        self.pmtiles.add_tile(tile_id, data);
+        Ok(())
+    }
+
+    fn finish(&mut self, archive: &PMTiles<Cursor<&'static [u8]>>) -> Result<()> {
+        let empty = PMTiles::new(TileType::Unknown, pmtiles2::Compression::None);
+        let mut pmtiles = std::mem::replace(&mut self.pmtiles, empty);
+        pmtiles.tile_type = archive.tile_type;
+        pmtiles.tile_compression = archive.tile_compression;
+        pmtiles.internal_compression = archive.internal_compression;
+        pmtiles.min_zoom = archive.min_zoom;
+        pmtiles.max_zoom = archive.max_zoom;
+        pmtiles.center_zoom = archive.center_zoom;
+        pmtiles.min_longitude = archive.min_longitude;
+        pmtiles.min_latitude = archive.min_latitude;
+        pmtiles.max_longitude = archive.max_longitude;
+        pmtiles.max_latitude = archive.max_latitude;
+        pmtiles.center_longitude = archive.center_longitude;
+        pmtiles.center_latitude = archive.center_latitude;
+        pmtiles.meta_data = archive.meta_data.clone();
+        write_output(pmtiles, &self.path, self.format)
+    }
+}
+
+/// Writes the archive to `path` in the given format, replacing any existing output. PMTiles can be
+/// written to stdout with a path of `-`, or uploaded to an `s3://` or `gs://` URL.
+pub fn write_output(
+    pmtiles: PMTiles<Cursor<&'static [u8]>>,
+    path: &str,
+    format: OutputFormat,
+) -> Result<()> {
+    if path == "-" && format != OutputFormat::PMTiles {
+        bail!("Only PMTiles can be written to stdout");
+    }
+    if is_object_store_url(path) {
+        if format != OutputFormat::PMTiles {
+            bail!("Only PMTiles can be uploaded to {path}");
+        }
+        #[cfg(feature = "s3")]
+        {
+            let mut buffer = Cursor::new(Vec::new());
+            pmtiles.to_writer(&mut buffer)?;
+            let mut upload = MultipartUpload::new(path)?;
+            upload.write_all(buffer.get_ref())?;
+            return upload.finish();
+        }
+        #[cfg(not(feature = "s3"))]
+        bail!("Uploading to {path} needs the s3 feature");
+    }
+    match format {
+        OutputFormat::PMTiles if path == "-" => write_stdout(pmtiles)?,
+        OutputFormat::PMTiles => {
+            let mut file = File::create(path)?;
+            pmtiles.to_writer(&mut file)?;
+        }
+        #[cfg(feature = "sqlite")]
+        OutputFormat::MBTiles => write_mbtiles(pmtiles, path)?,
+        #[cfg(not(feature = "sqlite"))]
+        OutputFormat::MBTiles => bail!("Writing MBTiles needs the sqlite feature"),
+        OutputFormat::Directory => write_directory(pmtiles, path)?,
+        OutputFormat::Tar => write_tar(pmtiles, path)?,
+        OutputFormat::Zip => write_zip(pmtiles, path)?,
+    }
+    Ok(())
+}
+
+/// Writing PMTiles needs to seek back to fill in the header, so the whole archive is assembled in
+/// memory first
+fn write_stdout(pmtiles: PMTiles<Cursor<&'static [u8]>>) -> Result<()> {
+    let mut buffer = Cursor::new(Vec::new());
+    pmtiles.to_writer(&mut buffer)?;
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(buffer.get_ref())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pmtiles2::Compression;
+
+    use super::*;
+
+    #[test]
+    fn test_pmtiles_file_only_written_when_finished() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.pmtiles");
+        let path = path.to_str().unwrap();
+        let temp_path = format!("{path}.tmp");
+        let archive = PMTiles::new(TileType::Mvt, Compression::None);
+
+        // A failed run leaves nothing behind
+        let mut sink = sink_for_path(path, &ArchiveOptions::default()).unwrap();
+        sink.add_tile(0, b"tile".to_vec()).unwrap();
+        assert!(Path::new(&temp_path).exists());
+        drop(sink);
+        assert!(!Path::new(&temp_path).exists());
+        assert!(!Path::new(path).exists());
+
+        let mut sink = sink_for_path(path, &ArchiveOptions::default()).unwrap();
+        sink.add_tile(0, b"tile".to_vec()).unwrap();
+        assert!(!Path::new(path).exists());
+        sink.finish(&archive).unwrap();
+        assert!(!Path::new(&temp_path).exists());
+        let mut written = PMTiles::from_reader(File::open(path).unwrap()).unwrap();
+        assert_eq!(
+            written.get_tile_by_id(0).unwrap().as_deref(),
+            Some(&b"tile"[..])
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+
+use anyhow::{bail, Result};
+use pmtiles2::util::{compress_all, write_directories};
+use pmtiles2::{Entry, Header, PMTiles};
+
+const HEADER_BYTES: u64 = 127;
+
+/// Writes a PMTiles archive without holding every tile in memory. Tiles are appended to a temporary
+/// spool file as they arrive, in any order. At the end, the directories are built and everything is
+/// copied to the output in one pass, so the output doesn't need to be seekable. The tile data is
+/// copied in order of tile ID, marking the archive as clustered, so readers making range requests
+/// find neighboring tiles close together.
+///
+/// Tiles with identical contents, like empty-ish tiles over the sea, are only stored once.
+pub struct PMTilesWriter {
+    spool: BufWriter<std::fs::File>,
+    entries: Vec<Entry>,
+    tile_data_length: u64,
+    clustered: bool,
+    /// The hash of each tile's contents, and where tiles with that hash are in the spool
+    contents: HashMap<u64, Vec<(u64, u32)>>,
+    num_contents: u64,
+}
+
+impl PMTilesWriter {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            spool: BufWriter::new(tempfile::tempfile()?),
+            entries: Vec::new(),
+            tile_data_length: 0,
+            clustered: true,
+            contents: HashMap::new(),
+            num_contents: 0,
+        })
+    }
+
+    /// Turning clustering off copies the tile data in the order tiles arrived in instead, skipping
+    /// some seeking through the spool, but the same tiles won't always give the same archive
+    pub fn with_clustered(mut self, clustered: bool) -> Self {
+        self.clustered = clustered;
+        self
+    }
+
+    pub fn add_tile(&mut self, tile_id: u64, data: &[u8]) -> Result<()> {
+        let Ok(length) = u32::try_from(data.len()) else {
+            bail!("Tile {tile_id} is too big for PMTiles");
+        };
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let hash = hasher.finish();
+        for (offset, spooled_length) in self.contents.get(&hash).cloned().unwrap_or_default() {
+            // Hashes can collide, so check the contents really match
+            if spooled_length == length && self.read_spooled(offset, length)? == data {
+                self.entries.push(Entry {
+                    tile_id,
+                    offset,
+                    length,
+                    run_length: 1,
+                });
+                return Ok(());
+            }
+        }
+
+        let offset = self.tile_data_length;
+        self.spool.write_all(data)?;
+        self.entries.push(Entry {
+            tile_id,
+            offset,
+            length,
+            run_length: 1,
+        });
+        self.contents
+            .entry(hash)
+            .or_default()
+            .push((offset, length));
+        self.num_contents += 1;
+        self.tile_data_length += data.len() as u64;
+        Ok(())
+    }
+
+    fn read_spooled(&mut self, offset: u64, length: u32) -> Result<Vec<u8>> {
+        // Seeking flushes the buffer first
+        self.spool.seek(SeekFrom::Start(offset))?;
+        let mut data = vec![0; length as usize];
+        self.spool.get_mut().read_exact(&mut data)?;
+        self.spool.seek(SeekFrom::End(0))?;
+        Ok(data)
+    }
+
+    /// The header fields and metadata come from `archive`. Any tiles in it are ignored.
+    pub fn finish(
+        self,
+        archive: &PMTiles<Cursor<&'static [u8]>>,
+        output: &mut impl Write,
+    ) -> Result<()> {
+        let mut entries = self.entries;
+        entries.sort_by_key(|entry| entry.tile_id);
+        let mut spool = self.spool.into_inner()?;
+        // Where each distinct tile is in the spool, in order of the first tile ID using it
+        let mut spooled = Vec::new();
+        if self.clustered {
+            let mut new_offsets = HashMap::new();
+            let mut offset = 0;
+            for entry in &mut entries {
+                let new_offset = *new_offsets.entry(entry.offset).or_insert_with(|| {
+                    spooled.push((entry.offset, entry.length));
+                    let new_offset = offset;
+                    offset += entry.length as u64;
+                    new_offset
+                });
+                entry.offset = new_offset;
+            }
+        }
+        let num_addressed_tiles = entries.len() as u64;
+        // Runs of consecutive tiles with the same contents only need one entry
+        let mut merged: Vec<Entry> = Vec::new();
+        for entry in entries {
+            if let Some(last) = merged.last_mut() {
+                if last.tile_id + last.run_length as u64 == entry.tile_id
+                    && last.offset == entry.offset
+                {
+                    last.run_length += 1;
+                    continue;
+                }
+            }
+            merged.push(entry);
+        }
+        let entries = merged;
+
+        let compression = archive.internal_compression;
+        let mut root_directory = Cursor::new(Vec::new());
+        let leaf_directories = write_directories(&mut root_directory, &entries, compression, None)?;
+        let root_directory = root_directory.into_inner();
+        let metadata = compress_all(
+            compression,
+            &serde_json::to_vec(&archive.meta_data.clone().unwrap_or_default())?,
+        )?;
+
+        let mut header = Header {
+            root_directory_offset: HEADER_BYTES,
+            root_directory_length: root_directory.len() as u64,
+            json_metadata_offset: HEADER_BYTES + root_directory.len() as u64,
+            json_metadata_length: metadata.len() as u64,
+            leaf_directories_offset: HEADER_BYTES + (root_directory.len() + metadata.len()) as u64,
+            leaf_directories_length: leaf_directories.len() as u64,
+            tile_data_offset: HEADER_BYTES
+                + (root_directory.len() + metadata.len() + leaf_directories.len()) as u64,
+            tile_data_length: self.tile_data_length,
+            num_addressed_tiles,
+            num_tile_entries: entries.len() as u64,
+            num_tile_content: self.num_contents,
+            // Otherwise, tiles are spooled in the order they finish
+            clustered: self.clustered,
+            internal_compression: compression,
+            tile_compression: archive.tile_compression,
+            tile_type: archive.tile_type,
+            min_zoom: archive.min_zoom,
+            max_zoom: archive.max_zoom,
+            center_zoom: archive.center_zoom,
+            ..Default::default()
+        };
+        header.min_pos.longitude = archive.min_longitude;
+        header.min_pos.latitude = archive.min_latitude;
+        header.max_pos.longitude = archive.max_longitude;
+        header.max_pos.latitude = archive.max_latitude;
+        header.center_pos.longitude = archive.center_longitude;
+        header.center_pos.latitude = archive.center_latitude;
+
+        header.to_writer(output)?;
+        output.write_all(&root_directory)?;
+        output.write_all(&metadata)?;
+        output.write_all(&leaf_directories)?;
+        if self.clustered {
+            let mut spool = BufReader::new(spool);
+            for (offset, length) in spooled {
+                spool.seek(SeekFrom::Start(offset))?;
+                std::io::copy(&mut (&mut spool).take(length as u64), output)?;
+            }
+        } else {
+            spool.seek(SeekFrom::Start(0))?;
+            std::io::copy(&mut spool, output)?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pmtiles2::{Compression, TileType};
+
+    use super::*;
+
+    /// Writes tiles, given in the order they arrive, returning the archive's bytes
+    fn write(tiles: &[(u64, &[u8])], clustered: bool) -> Vec<u8> {
+        let mut writer = PMTilesWriter::new().unwrap().with_clustered(clustered);
+        for (tile_id, data) in tiles {
+            writer.add_tile(*tile_id, data).unwrap();
+        }
+        let archive = PMTiles::new(TileType::Mvt, Compression::None);
+        let mut output = Vec::new();
+        writer.finish(&archive, &mut output).unwrap();
+        output
+    }
+
+    fn tile_data(bytes: &[u8]) -> &[u8] {
+        let header = Header::from_reader(&mut Cursor::new(bytes)).unwrap();
+        let start = header.tile_data_offset as usize;
+        &bytes[start..start + header.tile_data_length as usize]
+    }
+
+    fn read_tile(bytes: &[u8], tile_id: u64) -> Option<Vec<u8>> {
+        let mut archive = PMTiles::from_reader(Cursor::new(bytes)).unwrap();
+        archive.get_tile_by_id(tile_id).unwrap()
+    }
+
+    #[test]
+    fn test_dedup() {
+        let tiles: [(u64, &[u8]); 6] = [
+            (4, b"sea"),
+            (0, b"sea"),
+            (3, b"land"),
+            (1, b"sea"),
+            (5, b"coast"),
+            (2, b"sea"),
+        ];
+        for clustered in [true, false] {
+            let bytes = write(&tiles, clustered);
+            let header = Header::from_reader(&mut Cursor::new(&bytes)).unwrap();
+            assert_eq!(header.num_addressed_tiles, 6);
+            assert_eq!(header.num_tile_content, 3);
+            // 0 to 2 share one run
+            assert_eq!(header.num_tile_entries, 4);
+            assert_eq!(header.tile_data_length, 12);
+            assert_eq!(header.clustered, clustered);
+            for (tile_id, data) in tiles {
+                assert_eq!(read_tile(&bytes, tile_id).as_deref(), Some(data));
+            }
+            assert_eq!(read_tile(&bytes, 6), None);
+        }
+    }
+
+    #[test]
+    fn test_clustered_order() {
+        let tiles: [(u64, &[u8]); 4] = [(3, b"d"), (1, b"b"), (2, b"c"), (0, b"a")];
+        assert_eq!(tile_data(&write(&tiles, true)), b"abcd");
+        assert_eq!(tile_data(&write(&tiles, false)), b"dbca");
+
+        // Shared contents go where the first tile using them is
+        let tiles: [(u64, &[u8]); 4] = [(3, b"x"), (2, b"y"), (1, b"x"), (0, b"z")];
+        let bytes = write(&tiles, true);
+        assert_eq!(tile_data(&bytes), b"zxy");
+        for (tile_id, data) in tiles {
+            assert_eq!(read_tile(&bytes, tile_id).as_deref(), Some(data));
+        }
+    }
+
+    #[test]
+    fn test_same_tiles_same_archive() {
+        let tiles: [(u64, &[u8]); 3] = [(2, b"c"), (0, b"a"), (1, b"a")];
+        let mut reversed = tiles;
+        reversed.reverse();
+        assert_eq!(write(&tiles, true), write(&reversed, true));
+    }
+}
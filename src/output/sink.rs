@@ -0,0 +1,112 @@
+use std::io::{Cursor, Write};
+
+use anyhow::{bail, Result};
+use pmtiles2::PMTiles;
+
+use super::PMTilesWriter;
+
+/// Somewhere to send tiles as they're generated, like `layers_to_sink` does. Tiles arrive in no
+/// particular order, compressed the way the archive header says. Calls are never concurrent, but
+/// may come from different threads, so sinks need to be `Send`.
+pub trait TileSink {
+    fn add_tile(&mut self, tile_id: u64, data: Vec<u8>) -> Result<()>;
+
+    /// Called once after the last tile, with the header and metadata of the archive. Any tiles in
+    /// `archive` should be ignored.
+    fn finish(&mut self, _archive: &PMTiles<Cursor<&'static [u8]>>) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: TileSink + ?Sized> TileSink for Box<S> {
+    fn add_tile(&mut self, tile_id: u64, data: Vec<u8>) -> Result<()> {
+        (**self).add_tile(tile_id, data)
+    }
+
+    fn finish(&mut self, archive: &PMTiles<Cursor<&'static [u8]>>) -> Result<()> {
+        (**self).finish(archive)
+    }
+}
+
+/// Sends every tile to all of the sinks, like `sink_for_path` for a few outputs, so tiles are only
+/// generated once
+impl<S: TileSink> TileSink for Vec<S> {
+    fn add_tile(&mut self, tile_id: u64, data: Vec<u8>) -> Result<()> {
+        let Some((last, rest)) = self.split_last_mut() else {
+            return Ok(());
+        };
+        for sink in rest {
+            sink.add_tile(tile_id, data.clone())?;
+        }
+        last.add_tile(tile_id, data)
+    }
+
+    fn finish(&mut self, archive: &PMTiles<Cursor<&'static [u8]>>) -> Result<()> {
+        for sink in self {
+            sink.finish(archive)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps every tile in memory
+impl TileSink for PMTiles<Cursor<&'static [u8]>> {
+    fn add_tile(&mut self, tile_id: u64, data: Vec<u8>) -> Result<()> {
+        PMTiles::add_tile(self, tile_id, data);
+        Ok(())
+    }
+}
+
+/// Streams a PMTiles archive to any output using `PMTilesWriter`. The output doesn't need to be
+/// seekable, and nothing is written to it until `finish`.
+pub struct PMTilesSink<W: Write> {
+    writer: Option<PMTilesWriter>,
+    output: W,
+}
+
+impl<W: Write> PMTilesSink<W> {
+    pub fn new(output: W) -> Result<Self> {
+        Ok(Self {
+            writer: Some(PMTilesWriter::new()?),
+            output,
+        })
+    }
+
+    /// See `PMTilesWriter::with_clustered`
+    pub fn with_clustered(mut self, clustered: bool) -> Self {
+        self.writer = self.writer.map(|writer| writer.with_clustered(clustered));
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+impl<W: Write> TileSink for PMTilesSink<W> {
+    fn add_tile(&mut self, tile_id: u64, data: Vec<u8>) -> Result<()> {
+        let Some(ref mut writer) = self.writer else {
+            bail!("Can't add tile {tile_id} after the archive is finished");
+        };
+        writer.add_tile(tile_id, &data)
+    }
+
+    fn finish(&mut self, archive: &PMTiles<Cursor<&'static [u8]>>) -> Result<()> {
+        let Some(writer) = self.writer.take() else {
+            bail!("The archive is already finished");
+        };
+        writer.finish(archive, &mut self.output)?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// Hands each tile to a function, like `TileCallback(|tile_id, data| ...)`, for routing tiles
+/// anywhere else
+pub struct TileCallback<F: FnMut(u64, Vec<u8>) -> Result<()>>(pub F);
+
+impl<F: FnMut(u64, Vec<u8>) -> Result<()>> TileSink for TileCallback<F> {
+    fn add_tile(&mut self, tile_id: u64, data: Vec<u8>) -> Result<()> {
+        (self.0)(tile_id, data)
+    }
+}
@@ -0,0 +1,35 @@
+use std::io::Write;
+
+use anyhow::Result;
+use pmtiles2::util::zxy;
+
+use super::TileSink;
+
+/// Writes each tile to `output` as soon as it's generated, for piping into other tools. Every tile
+/// is a record of `z` as one byte, then `x`, `y`, and the length of the tile data as big-endian
+/// `u32`s, then the tile data itself. Tiles arrive in no particular order, and the stream just ends
+/// after the last one. The output is flushed after every tile.
+pub struct TileStreamSink<W: Write> {
+    output: W,
+}
+
+impl<W: Write> TileStreamSink<W> {
+    pub fn new(output: W) -> Self {
+        Self { output }
+    }
+}
+
+impl<W: Write> TileSink for TileStreamSink<W> {
+    fn add_tile(&mut self, tile_id: u64, data: Vec<u8>) -> Result<()> {
+        let (z, x, y) = zxy(tile_id)?;
+        let mut record = Vec::with_capacity(13 + data.len());
+        record.push(z);
+        record.extend((x as u32).to_be_bytes());
+        record.extend((y as u32).to_be_bytes());
+        record.extend(u32::try_from(data.len())?.to_be_bytes());
+        record.extend(data);
+        self.output.write_all(&record)?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use pmtiles2::{PMTiles, TileType};
+use serde_json::Value;
+
+use super::{is_object_store_url, OutputFormat};
+
+/// Describes the archive as TileJSON 3.0, so MapLibre or a tile server can be pointed at
+/// `tile_url`, like `https://example.com/tiles/{z}/{x}/{y}.pbf`
+pub fn tilejson<R>(pmtiles: &PMTiles<R>, tile_url: &str) -> Value {
+    let metadata = pmtiles.meta_data.as_ref();
+    let mut json = serde_json::json!({
+        "tilejson": "3.0.0",
+        "tiles": [tile_url],
+        "minzoom": pmtiles.min_zoom,
+        "maxzoom": pmtiles.max_zoom,
+        "bounds": [
+            pmtiles.min_longitude,
+            pmtiles.min_latitude,
+            pmtiles.max_longitude,
+            pmtiles.max_latitude,
+        ],
+        "center": [
+            pmtiles.center_longitude,
+            pmtiles.center_latitude,
+            pmtiles.center_zoom,
+        ],
+    });
+    if pmtiles.tile_type == TileType::Mvt {
+        json["vector_layers"] = metadata
+            .map(|x| x["vector_layers"].clone())
+            .unwrap_or(Value::Array(Vec::new()));
+    }
+    if let Some(description) = metadata.and_then(|x| x.get("description")) {
+        json["description"] = description.clone();
+    }
+    json
+}
+
+/// Writes TileJSON next to an output, like `out.tilejson` for `out.pmtiles`, or `tiles.json`
+/// inside a directory output. Returns the path written.
+pub fn write_tilejson(tilejson: &Value, output_path: &str) -> Result<PathBuf> {
+    if output_path == "-" || is_object_store_url(output_path) {
+        bail!("A TileJSON file can only be written next to a local output, not {output_path}");
+    }
+    let path = match OutputFormat::from_path(output_path) {
+        OutputFormat::Directory => Path::new(output_path).join("tiles.json"),
+        _ => Path::new(output_path).with_extension("tilejson"),
+    };
+    fs_err::write(&path, serde_json::to_string_pretty(tilejson)?)?;
+    Ok(path)
+}
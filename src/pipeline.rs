@@ -0,0 +1,197 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use anyhow::Result;
+use mvt::TileId;
+use pmtiles2::PMTiles;
+use rayon::prelude::*;
+
+use crate::clock::Instant;
+use crate::output::TileSink;
+use crate::{
+    load_features, tiles_covering, ConversionError, ConversionStats, LayerOptions, LoadedLayer,
+    Options, Progress, Tiler,
+};
+
+/// Making an archive, split into stages that can each be swapped out:
+///
+/// 1. `TilePipeline::load` reads and indexes each layer's features. Layers can come from anywhere
+///    else with `LoadedLayer::from_features`.
+/// 2. `TilePipeline::plan` works out the zooms, bounds, header, and metadata, and which tiles to
+///    make.
+/// 3. `TilePipeline::encode_tile` makes one tile, on any thread.
+/// 4. `TilePipeline::write` makes every tile in parallel and sends them to a `TileSink`. Tiles can
+///    also be pulled one at a time from `TilePipeline::iter_tiles`, or sent anywhere else by
+///    calling `encode_tile` on each of `tiles`.
+///
+/// The functions like `layers_to_sink` run all of these in order.
+pub struct TilePipeline<'a> {
+    tiler: Tiler<'a>,
+    archive: PMTiles<Cursor<&'static [u8]>>,
+}
+
+impl<'a> TilePipeline<'a> {
+    /// Loads one layer. This is `load_features`.
+    pub fn load(
+        features: impl Iterator<Item = Result<geojson::Feature>>,
+        layer: &LayerOptions,
+        options: &Options,
+    ) -> Result<LoadedLayer, ConversionError> {
+        load_features(features, layer, options)
+    }
+
+    /// Plans tiling the loaded layers. The `LayerOptions` here decide the name, zooms, and size
+    /// limit of each layer, like for `loaded_layers_to_pmtiles`.
+    pub fn plan(
+        layers: Vec<(LayerOptions, &'a LoadedLayer)>,
+        options: Options,
+    ) -> Result<Self, ConversionError> {
+        let (tiler, archive) = Tiler::new(layers, options)?;
+        Ok(Self { tiler, archive })
+    }
+
+    /// The header and metadata of the archive, with no tiles
+    pub fn archive(&self) -> &PMTiles<Cursor<&'static [u8]>> {
+        &self.archive
+    }
+
+    pub fn into_archive(self) -> PMTiles<Cursor<&'static [u8]>> {
+        self.archive
+    }
+
+    /// Sorted, across all layers
+    pub fn zoom_levels(&self) -> &[u32] {
+        &self.tiler.zoom_levels
+    }
+
+    /// Every tile to try making, as `[z, x, y]`. Tiles without any features come out empty.
+    pub fn tiles(&self) -> Result<Vec<[u32; 3]>, ConversionError> {
+        Ok(tiles_covering(&self.tiler.zoom_levels, &self.tiler.bbox)?
+            .into_iter()
+            .map(|tile_id| [tile_id.z(), tile_id.x(), tile_id.y()])
+            .collect())
+    }
+
+    /// Makes one tile, returning its PMTiles ID and its bytes, compressed like the archive's header
+    /// says, or nothing if it's empty. Tiles aren't counted in the stats from `write`.
+    pub fn encode_tile(
+        &self,
+        [z, x, y]: [u32; 3],
+    ) -> Result<Option<(u64, Vec<u8>)>, ConversionError> {
+        let tile_id = TileId::new(x, y, z)
+            .map_err(|_| ConversionError::InvalidOptions(format!("There's no tile {z}/{x}/{y}")))?;
+        Ok(self.tiler.encode_tile(tile_id)?)
+    }
+
+    /// Makes tiles as they're asked for, a batch at a time in parallel, returning `[z, x, y]` and
+    /// the bytes of each non-empty tile in the order of `tiles`. Only one batch is held in memory,
+    /// so uploading or writing each tile can happen between batches. Tiles are counted in the stats
+    /// from `stats`, but `Options::checkpoint` isn't used.
+    pub fn iter_tiles(&self) -> Result<TileIter<'_, 'a>, ConversionError> {
+        let tiles = tiles_covering(&self.tiler.zoom_levels, &self.tiler.bbox)?;
+        let tiles_total = tiles.len();
+        self.tiler.options.report(Progress::Tiling {
+            tiles_done: 0,
+            tiles_total,
+            bytes: 0,
+        });
+        Ok(TileIter {
+            pipeline: self,
+            batch_size: self.tiler.install(rayon::current_num_threads) * TILES_PER_THREAD,
+            pending: tiles.into_iter(),
+            ready: Vec::new().into_iter(),
+            tiles_done: 0,
+            tiles_total,
+            bytes: 0,
+        })
+    }
+
+    /// The stats so far, for tiles made by `iter_tiles`
+    pub fn stats(&self) -> ConversionStats {
+        self.tiler.stats(Duration::ZERO)
+    }
+
+    /// Makes every tile, sending each one to `sink` as soon as it's done, then finishes it
+    pub fn write(
+        &self,
+        sink: &mut (impl TileSink + Send),
+    ) -> Result<ConversionStats, ConversionError> {
+        self.tiler
+            .send_tiles(&self.tiler.zoom_levels, &self.tiler.bbox, sink)?;
+        self.tiler.options.report(Progress::Writing);
+        let start = Instant::now();
+        sink.finish(&self.archive)?;
+        Ok(self.tiler.stats(start.elapsed()))
+    }
+}
+
+/// How many tiles each thread makes per batch in `TileIter`, enough to keep every thread busy
+/// without holding many tiles at once
+const TILES_PER_THREAD: usize = 16;
+
+/// The tiles from `TilePipeline::iter_tiles`
+pub struct TileIter<'p, 'a> {
+    pipeline: &'p TilePipeline<'a>,
+    batch_size: usize,
+    pending: std::vec::IntoIter<TileId>,
+    ready: std::vec::IntoIter<([u32; 3], Vec<u8>)>,
+    tiles_done: usize,
+    tiles_total: usize,
+    bytes: u64,
+}
+
+impl TileIter<'_, '_> {
+    /// Makes the next batch of tiles in parallel, leaving out empty ones
+    fn encode_batch(&self, batch: Vec<TileId>) -> Result<Vec<([u32; 3], Vec<u8>)>> {
+        let tiler = &self.pipeline.tiler;
+        let start = Instant::now();
+        let tiles = tiler.install(|| {
+            batch
+                .into_par_iter()
+                .map(|tile_id| -> Result<_> {
+                    tiler.options.check_cancelled()?;
+                    Ok(tiler.encode_tile(tile_id)?.map(|(_, data)| {
+                        tiler.recorder.add_tile(tile_id.z(), data.len());
+                        ([tile_id.z(), tile_id.x(), tile_id.y()], data)
+                    }))
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+        tiler.recorder.add_tiling(start.elapsed());
+        Ok(tiles.into_iter().flatten().collect())
+    }
+}
+
+impl Iterator for TileIter<'_, '_> {
+    type Item = Result<([u32; 3], Vec<u8>), ConversionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(tile) = self.ready.next() {
+                return Some(Ok(tile));
+            }
+            let batch: Vec<TileId> = self.pending.by_ref().take(self.batch_size).collect();
+            if batch.is_empty() {
+                return None;
+            }
+            let batch_len = batch.len();
+            match self.encode_batch(batch) {
+                Ok(tiles) => {
+                    self.tiles_done += batch_len;
+                    self.bytes += tiles.iter().map(|(_, data)| data.len() as u64).sum::<u64>();
+                    self.pipeline.tiler.options.report(Progress::Tiling {
+                        tiles_done: self.tiles_done,
+                        tiles_total: self.tiles_total,
+                        bytes: self.bytes,
+                    });
+                    self.ready = tiles.into_iter();
+                }
+                Err(err) => {
+                    // Stop after the first error
+                    self.pending = Vec::new().into_iter();
+                    return Some(Err(err.into()));
+                }
+            }
+        }
+    }
+}
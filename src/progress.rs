@@ -0,0 +1,125 @@
+/// How far a run has got, for `Options::progress`
+#[derive(Clone, Copy, Debug)]
+pub enum Progress {
+    /// Reading in the features
+    Loading,
+    /// Making tiles. `tiles_done` counts every tile covering the features, including empty ones,
+    /// out of `tiles_total`. `bytes` is the compressed size of the tiles made so far. With
+    /// `Options::two_pass`, only the tiles with features are counted.
+    Tiling {
+        tiles_done: usize,
+        tiles_total: usize,
+        bytes: u64,
+    },
+    /// Writing the directories and metadata, once all the tiles are made
+    Writing,
+}
+
+/// One vector tile that was just made, before it's compressed
+#[derive(Clone, Copy, Debug)]
+pub struct TileReport {
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+    /// How many features went in, across every layer
+    pub num_features: usize,
+    /// The uncompressed size
+    pub bytes: usize,
+    /// True if some features were left out to keep under a size limit
+    pub hit_size_limit: bool,
+}
+
+/// Hears how a run is going, from any thread. Closures taking a `Progress` are observers too, for
+/// when nothing else is needed. Nothing is drawn or printed unless the observer does it, and with
+/// the `progress` feature, `IndicatifProgress` draws progress bars in a terminal.
+pub trait ProgressObserver: Send + Sync {
+    fn progress(&self, progress: Progress);
+
+    /// Called after each vector tile with features in it is made. Grid and density tiles aren't
+    /// reported.
+    fn tile_made(&self, _report: TileReport) {}
+}
+
+impl<F: Fn(Progress) + Send + Sync> ProgressObserver for F {
+    fn progress(&self, progress: Progress) {
+        self(progress)
+    }
+}
+
+#[cfg(feature = "progress")]
+pub use self::bars::IndicatifProgress;
+
+#[cfg(feature = "progress")]
+mod bars {
+    use indicatif::{HumanBytes, HumanCount, ProgressBar, ProgressStyle};
+
+    use super::{Progress, ProgressObserver, TileReport};
+
+    /// Draws a spinner while loading and a bar while tiling, with a line for each tile above it.
+    /// Bars are drawn to stderr, and only when it's a terminal.
+    pub struct IndicatifProgress {
+        bar: ProgressBar,
+    }
+
+    impl IndicatifProgress {
+        pub fn new() -> Self {
+            Self {
+                bar: ProgressBar::new_spinner(),
+            }
+        }
+    }
+
+    impl Default for IndicatifProgress {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl ProgressObserver for IndicatifProgress {
+        fn progress(&self, progress: Progress) {
+            match progress {
+                Progress::Loading => {
+                    self.bar.set_message("Loading features");
+                    self.bar
+                        .enable_steady_tick(std::time::Duration::from_millis(100));
+                }
+                Progress::Tiling {
+                    tiles_done,
+                    tiles_total,
+                    ..
+                } => {
+                    // Each shard starts tiling again
+                    if self.bar.length() != Some(tiles_total as u64) || self.bar.is_finished() {
+                        self.bar.reset();
+                        self.bar.disable_steady_tick();
+                        self.bar.set_length(tiles_total as u64);
+                        self.bar.set_style(
+                            ProgressStyle::with_template(
+                                "[{elapsed_precise}] [{wide_bar:.cyan/blue}] {human_pos}/{human_len} tiles ({per_sec}, {eta})",
+                            )
+                            .unwrap(),
+                        );
+                    }
+                    self.bar.set_position(tiles_done as u64);
+                }
+                Progress::Writing => self.bar.finish_and_clear(),
+            }
+        }
+
+        fn tile_made(&self, report: TileReport) {
+            self.bar.println(format!(
+                "Added {} features into {}/{}/{}, costing {}{}",
+                HumanCount(report.num_features as u64),
+                report.z,
+                report.x,
+                report.y,
+                HumanBytes(report.bytes as u64),
+                if report.hit_size_limit {
+                    " (skipping some features after hitting size limit)"
+                } else {
+                    ""
+                }
+            ));
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use serde_json::Value;
+
+/// One property of a feature, borrowed from wherever the feature came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PropertyValue<'a> {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+    String(&'a str),
+}
+
+/// The properties of a feature, for `properties_to_pmtiles`, so rows from Parquet, a database, or
+/// anywhere else can be tiled without building a `geojson::JsonObject` for each one first
+pub trait Properties {
+    fn get(&self, key: &str) -> Option<PropertyValue<'_>>;
+
+    /// Every property, in any order
+    fn iter(&self) -> Box<dyn Iterator<Item = (&str, PropertyValue<'_>)> + '_>;
+}
+
+impl PropertyValue<'_> {
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            PropertyValue::Int(x) => Some(x as f64),
+            PropertyValue::UInt(x) => Some(x as f64),
+            PropertyValue::Double(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Non-finite numbers become null, like in GeoJSON
+    pub fn to_json(&self) -> Value {
+        match *self {
+            PropertyValue::Null => Value::Null,
+            PropertyValue::Bool(x) => x.into(),
+            PropertyValue::Int(x) => x.into(),
+            PropertyValue::UInt(x) => x.into(),
+            PropertyValue::Double(x) => {
+                serde_json::Number::from_f64(x).map_or(Value::Null, Value::Number)
+            }
+            PropertyValue::String(x) => x.into(),
+        }
+    }
+}
+
+impl<'a> From<&'a Value> for PropertyValue<'a> {
+    /// Arrays and objects have no equivalent, so they're null
+    fn from(value: &'a Value) -> Self {
+        match value {
+            Value::Bool(x) => PropertyValue::Bool(*x),
+            Value::Number(x) => match (x.as_i64(), x.as_u64()) {
+                (Some(x), _) => PropertyValue::Int(x),
+                (None, Some(x)) => PropertyValue::UInt(x),
+                _ => PropertyValue::Double(x.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(x) => PropertyValue::String(x),
+            Value::Null | Value::Array(_) | Value::Object(_) => PropertyValue::Null,
+        }
+    }
+}
+
+impl Properties for geojson::JsonObject {
+    fn get(&self, key: &str) -> Option<PropertyValue<'_>> {
+        self.get(key).map(PropertyValue::from)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&str, PropertyValue<'_>)> + '_> {
+        Box::new(
+            self.iter()
+                .map(|(key, value)| (key.as_str(), PropertyValue::from(value))),
+        )
+    }
+}
+
+/// Pairs of keys and values, like a row with its column names
+impl<K: AsRef<str>> Properties for Vec<(K, PropertyValue<'_>)> {
+    fn get(&self, key: &str) -> Option<PropertyValue<'_>> {
+        self.as_slice()
+            .iter()
+            .find(|(k, _)| k.as_ref() == key)
+            .map(|(_, value)| *value)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&str, PropertyValue<'_>)> + '_> {
+        Box::new(
+            self.as_slice()
+                .iter()
+                .map(|(key, value)| (key.as_ref(), *value)),
+        )
+    }
+}
+
+/// Loaded features keep their properties as JSON
+pub(crate) fn to_json_object(properties: &impl Properties) -> geojson::JsonObject {
+    properties
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_json()))
+        .collect()
+}
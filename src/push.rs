@@ -0,0 +1,85 @@
+use std::io::Cursor;
+
+use geo_types::Geometry;
+use pmtiles2::PMTiles;
+
+use crate::output::TileSink;
+use crate::{
+    loaded_layers_to_pmtiles, loaded_layers_to_sink, ConversionError, LayerLoader, LayerOptions,
+    LoadedLayer, Options, Progress,
+};
+
+/// Tiles features pushed in one at a time, for when they come out of another computation and never
+/// exist all together, like `tiler.add_feature(line, properties)?` for each one, then
+/// `tiler.finish()?`. Each feature is filtered, masked, and indexed as it arrives, like any other
+/// input.
+pub struct TilerBuilder {
+    loader: LayerLoader,
+    options: Options,
+    memory_used: usize,
+}
+
+impl TilerBuilder {
+    /// Features go in one layer called `Options::layer_name`
+    pub fn new(options: Options) -> Result<Self, ConversionError> {
+        let layer = LayerOptions::new(options.layer_name.clone());
+        Self::with_layer(layer, options)
+    }
+
+    /// Features go in one layer with these settings
+    pub fn with_layer(layer: LayerOptions, options: Options) -> Result<Self, ConversionError> {
+        options.validate()?;
+        options.report(Progress::Loading);
+        Ok(Self {
+            loader: LayerLoader::new(layer, &options),
+            options,
+            memory_used: 0,
+        })
+    }
+
+    /// Adds a feature from its WGS84 geometry and its properties
+    pub fn add_feature(
+        &mut self,
+        geometry: impl Into<Geometry<f64>>,
+        properties: geojson::JsonObject,
+    ) -> Result<(), ConversionError> {
+        self.add_geojson_feature(geojson::Feature {
+            bbox: None,
+            geometry: Some(geojson::Geometry::from(&geometry.into())),
+            id: None,
+            properties: Some(properties),
+            foreign_members: None,
+        })
+    }
+
+    pub fn add_geojson_feature(
+        &mut self,
+        feature: geojson::Feature,
+    ) -> Result<(), ConversionError> {
+        Ok(self
+            .loader
+            .push(Ok(feature), &self.options, &mut self.memory_used)?)
+    }
+
+    /// Makes every tile, holding them all in memory
+    pub fn finish(self) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+        let layer = self.loader.layer.clone();
+        let loaded = self.loader.finish(&self.options)?;
+        loaded_layers_to_pmtiles(vec![(layer, &loaded)], self.options)
+    }
+
+    /// Makes every tile, sending each one to `sink` like `layers_to_sink`
+    pub fn finish_to_sink(
+        self,
+        sink: &mut (impl TileSink + Send),
+    ) -> Result<PMTiles<Cursor<&'static [u8]>>, ConversionError> {
+        let layer = self.loader.layer.clone();
+        let loaded = self.loader.finish(&self.options)?;
+        loaded_layers_to_sink(vec![(layer, &loaded)], self.options, sink)
+    }
+
+    /// Stops after loading, to make archives from the layer like `load_features`
+    pub fn into_loaded(self) -> Result<LoadedLayer, ConversionError> {
+        Ok(self.loader.finish(&self.options)?)
+    }
+}
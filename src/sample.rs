@@ -0,0 +1,37 @@
+/// Makes random choices, like which features to keep when thinning them, the same way every run
+/// with the same seed. Each choice only depends on the seed and a key for what's being chosen, like
+/// a feature's position in its layer, so the order and thread things happen on don't matter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sampler {
+    seed: u64,
+}
+
+impl Sampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// A separate sampler for each layer, or anything else, so they don't all choose alike
+    pub fn for_stream(&self, name: &str) -> Self {
+        Self::new(mix(self.seed ^ crc32fast::hash(name.as_bytes()) as u64))
+    }
+
+    /// A number from 0 up to but not including 1, spread evenly over keys
+    pub fn uniform(&self, key: u64) -> f64 {
+        // The top 53 bits fill the mantissa exactly
+        (mix(self.seed ^ mix(key)) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// True for about `rate` of keys, where `rate` is from 0 to 1
+    pub fn keeps(&self, key: u64, rate: f64) -> bool {
+        self.uniform(key) < rate
+    }
+}
+
+/// SplitMix64's finalizer, which is stable everywhere, unlike std's hashers
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
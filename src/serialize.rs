@@ -0,0 +1,75 @@
+//! Serde support for the parts of `Options` defined in other crates, for the `serde` feature
+
+use std::collections::{BTreeSet, HashSet};
+
+use pmtiles2::{Compression, TileType};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Attributes;
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "TileType", rename_all = "lowercase")]
+pub(crate) enum TileTypeDef {
+    Unknown,
+    Mvt,
+    Png,
+    Jpeg,
+    WebP,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Compression", rename_all = "lowercase")]
+pub(crate) enum CompressionDef {
+    Unknown,
+    None,
+    GZip,
+    Brotli,
+    ZStd,
+}
+
+/// For `Option<TileType>`
+pub(crate) mod option_tile_type {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Wrapper(#[serde(with = "TileTypeDef")] TileType);
+
+    pub fn serialize<S: Serializer>(value: &Option<TileType>, s: S) -> Result<S::Ok, S::Error> {
+        value.map(Wrapper).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<TileType>, D::Error> {
+        Ok(Option::<Wrapper>::deserialize(d)?.map(|Wrapper(tile_type)| tile_type))
+    }
+}
+
+/// Keys are sorted, so the same options always serialize the same way
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AttributesDef {
+    All,
+    Only(BTreeSet<String>),
+    Except(BTreeSet<String>),
+}
+
+impl Serialize for Attributes {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let sorted = |keys: &HashSet<String>| keys.iter().cloned().collect();
+        match self {
+            Attributes::All => AttributesDef::All,
+            Attributes::Only(keys) => AttributesDef::Only(sorted(keys)),
+            Attributes::Except(keys) => AttributesDef::Except(sorted(keys)),
+        }
+        .serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Attributes {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        Ok(match AttributesDef::deserialize(d)? {
+            AttributesDef::All => Attributes::All,
+            AttributesDef::Only(keys) => Attributes::Only(keys.into_iter().collect()),
+            AttributesDef::Except(keys) => Attributes::Except(keys.into_iter().collect()),
+        })
+    }
+}
@@ -0,0 +1,545 @@
+//! Making tiles in two passes for `Options::two_pass`, like Planetiler does. The first pass reads
+//! each feature once, writing it to a temporary file and a small record for each tile it's in.
+//! Records are sorted by tile in runs that fit in memory, each in its own temporary file. The
+//! second pass merges the runs, so each tile's features come out together, and makes the tiles in
+//! order, without ever indexing the features.
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+use anyhow::Result;
+use geo_types::{Coord, Geometry};
+use mvt::TileId;
+use pmtiles2::util::{tile_id as get_tile_id, zxy};
+use rayon::prelude::*;
+use rstar::RTreeObject;
+
+use crate::clock::Instant;
+use crate::human::HumanCount;
+use crate::math::{self, BBox};
+use crate::output::TileSink;
+use crate::spill::{decode_feature, encode_feature};
+use crate::{
+    borrow_layers, check_layer_count, count_feature_center, densest_center, invalid_options,
+    ArchiveWithStats, LayerLoader, LayerOptions, Options, Progress, Tiler, TreeFeature,
+};
+
+/// How much the records can take up while sorting, without `Options::max_memory`
+const DEFAULT_SORT_MEMORY: usize = 256 * 1024 * 1024;
+
+/// How many tiles each thread makes per batch in the second pass, like `TileIter`
+const TILES_PER_THREAD: usize = 16;
+
+/// One feature being in one tile. Records sort by tile, then layer, then the order features were
+/// read in, which is also the order they're stored in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Record {
+    tile_id: u64,
+    layer: u32,
+    offset: u64,
+    length: u32,
+}
+
+const RECORD_SIZE: usize = 24;
+
+impl Record {
+    fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[..8].copy_from_slice(&self.tile_id.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.layer.to_le_bytes());
+        bytes[12..20].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[20..].copy_from_slice(&self.length.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Self {
+        Self {
+            tile_id: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            layer: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            offset: u64::from_le_bytes(bytes[12..20].try_into().unwrap()),
+            length: u32::from_le_bytes(bytes[20..].try_into().unwrap()),
+        }
+    }
+}
+
+/// Every feature in any tile, stored once, like `Spill` does
+struct FeatureFile {
+    file: BufWriter<File>,
+    length: u64,
+}
+
+impl FeatureFile {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(tempfile::tempfile()?),
+            length: 0,
+        })
+    }
+
+    /// Returns where the feature is
+    fn push(&mut self, feature: &TreeFeature) -> Result<(u64, u32)> {
+        let record = encode_feature(feature)?;
+        self.file.write_all(&record)?;
+        let offset = self.length;
+        self.length += record.len() as u64;
+        Ok((offset, record.len() as u32))
+    }
+
+    fn finish(self) -> Result<File> {
+        Ok(self.file.into_inner()?)
+    }
+}
+
+/// Sorts records in runs of up to `capacity`
+struct RunWriter {
+    buffer: Vec<Record>,
+    capacity: usize,
+    runs: Vec<File>,
+    num_records: usize,
+}
+
+impl RunWriter {
+    fn new(capacity: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            capacity: capacity.max(1),
+            runs: Vec::new(),
+            num_records: 0,
+        }
+    }
+
+    fn push(&mut self, record: Record) -> Result<()> {
+        self.buffer.push(record);
+        self.num_records += 1;
+        if self.buffer.len() >= self.capacity {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.buffer.par_sort_unstable();
+        let mut file = BufWriter::new(tempfile::tempfile()?);
+        for record in self.buffer.drain(..) {
+            file.write_all(&record.to_bytes())?;
+        }
+        let mut file = file.into_inner()?;
+        file.rewind()?;
+        self.runs.push(file);
+        Ok(())
+    }
+
+    fn merge(mut self) -> Result<Merge> {
+        self.flush()?;
+        let mut merge = Merge {
+            runs: self.runs.into_iter().map(BufReader::new).collect(),
+            heap: BinaryHeap::new(),
+        };
+        for run in 0..merge.runs.len() {
+            merge.refill(run)?;
+        }
+        Ok(merge)
+    }
+}
+
+/// Reads the sorted runs back as one sorted stream
+struct Merge {
+    runs: Vec<BufReader<File>>,
+    heap: BinaryHeap<Reverse<(Record, usize)>>,
+}
+
+impl Merge {
+    /// Adds the next record from `run` to the heap, if there is one
+    fn refill(&mut self, run: usize) -> Result<()> {
+        let mut bytes = [0; RECORD_SIZE];
+        match self.runs[run].read_exact(&mut bytes) {
+            Ok(()) => {
+                self.heap.push(Reverse((Record::from_bytes(&bytes), run)));
+                Ok(())
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// How many tiles are left, reading every run through and then starting them over
+    fn count_tiles(&mut self) -> Result<usize> {
+        let mut count = 0;
+        while self.next_tile()?.is_some() {
+            count += 1;
+        }
+        for run in 0..self.runs.len() {
+            self.runs[run].rewind()?;
+            self.refill(run)?;
+        }
+        Ok(count)
+    }
+
+    /// Every record in the next tile, in order
+    fn next_tile(&mut self) -> Result<Option<Vec<Record>>> {
+        let mut records = Vec::new();
+        while let Some(Reverse((record, run))) = self.heap.peek().copied() {
+            if records
+                .first()
+                .is_some_and(|first: &Record| first.tile_id != record.tile_id)
+            {
+                break;
+            }
+            self.heap.pop();
+            self.refill(run)?;
+            records.push(record);
+        }
+        Ok((!records.is_empty()).then_some(records))
+    }
+}
+
+/// `layers_to_sink_with_stats` with `Options::two_pass`
+pub(crate) fn layers_to_sink_sorted<I: Iterator<Item = Result<geojson::Feature>>>(
+    inputs: Vec<(LayerOptions, I)>,
+    options: Options,
+    sink: &mut (impl TileSink + Send),
+) -> Result<ArchiveWithStats> {
+    options.validate()?;
+    check_layer_count(inputs.len(), &options)?;
+    options.report(Progress::Loading);
+
+    // The same zooms `Tiler::new` works out, which can't be guessed here
+    let layer_zooms: Vec<Vec<u32>> = inputs
+        .iter()
+        .map(|(layer, _)| {
+            layer
+                .zoom_levels
+                .clone()
+                .filter(|zooms| !zooms.is_empty())
+                .unwrap_or_else(|| options.zoom_levels.clone())
+        })
+        .collect();
+    let max_zoom = layer_zooms.iter().flatten().max().cloned().unwrap_or(0);
+    let bbox = options.bbox.as_ref().map_or(
+        BBox {
+            min_lon: -180.0,
+            min_lat: -math::MAX_LATITUDE,
+            max_lon: 180.0,
+            max_lat: math::MAX_LATITUDE,
+        },
+        BBox::from,
+    );
+    let bbox_tiles: HashMap<u32, (u32, u32, u32, u32)> = layer_zooms
+        .iter()
+        .flatten()
+        .map(|zoom| (*zoom, bbox.to_tiles(*zoom)))
+        .collect();
+
+    let mut features = FeatureFile::new()?;
+    let mut runs = RunWriter::new(options.max_memory.unwrap_or(DEFAULT_SORT_MEMORY) / RECORD_SIZE);
+    let mut centers = HashMap::new();
+    let mut loaded = Vec::new();
+    let mut tiles = Vec::new();
+    for (layer_index, ((layer_options, input), zoom_levels)) in
+        inputs.into_iter().zip(&layer_zooms).enumerate()
+    {
+        let mut loader = LayerLoader::new(layer_options.clone(), &options);
+        let mut num_features = 0;
+        for f in input {
+            let Some(feature) = loader.prepare(f, &options)? else {
+                continue;
+            };
+            num_features += 1;
+            if options.center.is_none() {
+                count_feature_center(&mut centers, &feature.envelope(), &bbox, max_zoom);
+            }
+            let mut location = None;
+            for zoom in zoom_levels {
+                tiles.clear();
+                tiles_touched(&feature.geometry, *zoom, &mut tiles);
+                tiles.sort_unstable();
+                tiles.dedup();
+                let (x1, y1, x2, y2) = bbox_tiles[zoom];
+                for (x, y) in &tiles {
+                    if !(x1..=x2).contains(x) || !(y1..=y2).contains(y) {
+                        continue;
+                    }
+                    let (offset, length) = match location {
+                        Some(location) => location,
+                        None => *location.insert(features.push(&feature)?),
+                    };
+                    runs.push(Record {
+                        tile_id: get_tile_id(*zoom as u8, *x as u64, *y as u64),
+                        layer: layer_index as u32,
+                        offset,
+                        length,
+                    })?;
+                }
+            }
+        }
+        let mut layer = loader.finish(&options)?;
+        layer.num_features = num_features;
+        loaded.push((layer_options, layer));
+    }
+
+    let (tiler, mut archive) = Tiler::new(borrow_layers(&loaded), options)?;
+    if tiler.options.center.is_none() {
+        if let Some(center) = densest_center(centers, &tiler.bbox, max_zoom) {
+            archive.center_longitude = center.x();
+            archive.center_latitude = center.y();
+        }
+    }
+
+    let num_records = runs.num_records;
+    let mut merge = runs.merge()?;
+    // Reads the records an extra time, so progress can count tiles
+    let tiles_total = merge.count_tiles()?;
+    log::info!(
+        "Sorted {} records for {} tiles into {} runs",
+        HumanCount(num_records as u64),
+        HumanCount(tiles_total as u64),
+        merge.runs.len()
+    );
+    let mut features = features.finish()?;
+    let batch_size = tiler.install(rayon::current_num_threads) * TILES_PER_THREAD;
+    let mut tiles_done = 0;
+    let mut bytes = 0;
+    tiler.options.report(Progress::Tiling {
+        tiles_done: 0,
+        tiles_total,
+        bytes: 0,
+    });
+    loop {
+        let mut batch = Vec::new();
+        while batch.len() < batch_size {
+            let Some(records) = merge.next_tile()? else {
+                break;
+            };
+            let mut encoded = Vec::with_capacity(records.len());
+            for record in &records {
+                let mut feature = vec![0; record.length as usize];
+                features.seek(SeekFrom::Start(record.offset))?;
+                features.read_exact(&mut feature)?;
+                encoded.push((record.layer as usize, feature));
+            }
+            batch.push((records[0].tile_id, encoded));
+        }
+        if batch.is_empty() {
+            break;
+        }
+        tiles_done += batch.len();
+        for (tile_id, data) in encode_batch(&tiler, batch)? {
+            bytes += data.len() as u64;
+            sink.add_tile(tile_id, data)?;
+        }
+        tiler.options.report(Progress::Tiling {
+            tiles_done,
+            tiles_total,
+            bytes,
+        });
+    }
+
+    tiler.options.report(Progress::Writing);
+    let start = Instant::now();
+    sink.finish(&archive)?;
+    let stats = tiler.stats(start.elapsed());
+    Ok((archive, stats))
+}
+
+/// Makes a batch of tiles in parallel from their encoded features, leaving out empty ones
+#[allow(clippy::type_complexity)]
+fn encode_batch(
+    tiler: &Tiler,
+    batch: Vec<(u64, Vec<(usize, Vec<u8>)>)>,
+) -> Result<Vec<(u64, Vec<u8>)>> {
+    let start = Instant::now();
+    let tiles = tiler.install(|| {
+        batch
+            .into_par_iter()
+            .map(|(tile_id, encoded)| -> Result<_> {
+                tiler.options.check_cancelled()?;
+                let (z, x, y) = zxy(tile_id).map_err(|_| invalid_options("Bad tile ID"))?;
+                let tile_id = TileId::new(x as u32, y as u32, z as u32)
+                    .map_err(|_| invalid_options(format!("There's no tile {z}/{x}/{y}")))?;
+                let mut by_layer: Vec<_> = tiler.layers.iter().map(|_| Vec::new()).collect();
+                for (layer, feature) in encoded {
+                    by_layer[layer].push(Cow::Owned(decode_feature(&feature)?));
+                }
+                let features = tiler
+                    .layers
+                    .iter()
+                    .zip(by_layer)
+                    .filter(|(layer, _)| layer.zoom_levels.contains(&tile_id.z()))
+                    .collect();
+                let tile = tiler.encode_tile_features(tile_id, features)?;
+                if let Some((_, ref data)) = tile {
+                    tiler.recorder.add_tile(tile_id.z(), data.len());
+                }
+                Ok(tile)
+            })
+            .collect::<Result<Vec<_>>>()
+    })?;
+    tiler.recorder.add_tiling(start.elapsed());
+    Ok(tiles.into_iter().flatten().collect())
+}
+
+/// The tiles at `zoom` with at least one of the geometry's points inside, counting points on an
+/// edge as in both tiles, like `add_points` does. Other geometry types are never drawn, so they're
+/// in no tiles.
+fn tiles_touched(geometry: &Geometry<f64>, zoom: u32, tiles: &mut Vec<(u32, u32)>) {
+    let num_tiles = 1 << zoom;
+    let mut add = |pt: &Coord| {
+        let [x, y] = math::web_mercator_to_tile([pt.x, pt.y], num_tiles);
+        for tile_x in math::tiles_touching(x, x, num_tiles) {
+            for tile_y in math::tiles_touching(y, y, num_tiles) {
+                tiles.push((tile_x, tile_y));
+            }
+        }
+    };
+    match geometry {
+        Geometry::Point(pt) => add(&pt.0),
+        Geometry::LineString(line_string) => line_string.0.iter().for_each(add),
+        Geometry::MultiLineString(multi_line_string) => multi_line_string
+            .iter()
+            .flat_map(|line_string| &line_string.0)
+            .for_each(add),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use pmtiles2::{Compression, PMTiles, TileType};
+
+    use super::*;
+    use crate::tests::{all_tiles, test_lines};
+
+    fn record(tile_id: u64, layer: u32, offset: u64) -> Record {
+        Record {
+            tile_id,
+            layer,
+            offset,
+            length: 10,
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let big = Record {
+            tile_id: u64::MAX - 1,
+            layer: 3,
+            offset: 1 << 40,
+            length: u32::MAX,
+        };
+        assert_eq!(Record::from_bytes(&big.to_bytes()), big);
+
+        // By tile, then layer, then where the feature is
+        let mut records = vec![
+            record(2, 0, 0),
+            record(1, 1, 0),
+            record(1, 0, 5),
+            record(1, 0, 2),
+        ];
+        records.sort();
+        assert_eq!(
+            records,
+            [
+                record(1, 0, 2),
+                record(1, 0, 5),
+                record(1, 1, 0),
+                record(2, 0, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_runs() {
+        // Every record gets its own run
+        let mut runs = RunWriter::new(1);
+        for record in [
+            record(5, 0, 0),
+            record(2, 1, 10),
+            record(5, 0, 20),
+            record(2, 0, 30),
+            record(9, 0, 40),
+            record(2, 0, 50),
+        ] {
+            runs.push(record).unwrap();
+        }
+        assert_eq!(runs.num_records, 6);
+        let mut merge = runs.merge().unwrap();
+        assert_eq!(merge.runs.len(), 6);
+        assert_eq!(merge.count_tiles().unwrap(), 3);
+
+        let mut tiles = Vec::new();
+        while let Some(records) = merge.next_tile().unwrap() {
+            tiles.push(records);
+        }
+        assert_eq!(
+            tiles,
+            [
+                vec![record(2, 0, 30), record(2, 0, 50), record(2, 1, 10)],
+                vec![record(5, 0, 0), record(5, 0, 20)],
+                vec![record(9, 0, 40)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_same_tiles_as_in_memory() {
+        let options = || Options {
+            // Without a key to tell every feature apart, the R-tree decides the order of ties
+            sort_by_key: Some("id".to_string()),
+            // Enough for a few hundred records per run
+            max_memory: Some(300 * RECORD_SIZE),
+            ..Default::default()
+        };
+        let inputs = || {
+            vec![(
+                LayerOptions::new("lines"),
+                test_lines(200).into_iter().map(Ok),
+            )]
+        };
+
+        let mut in_memory = PMTiles::new(TileType::Mvt, Compression::None);
+        crate::layers_to_sink(inputs(), options(), &mut in_memory).unwrap();
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let mut options = options();
+        options.two_pass = true;
+        options.progress = Some(Box::new({
+            let reports = reports.clone();
+            move |progress| {
+                if let Progress::Tiling {
+                    tiles_done,
+                    tiles_total,
+                    ..
+                } = progress
+                {
+                    reports.lock().unwrap().push((tiles_done, tiles_total));
+                }
+            }
+        }));
+        let mut sorted = PMTiles::new(TileType::Mvt, Compression::None);
+        layers_to_sink_sorted(inputs(), options, &mut sorted).unwrap();
+
+        let (in_memory, sorted) = (all_tiles(&mut in_memory), all_tiles(&mut sorted));
+        assert!(in_memory.len() > 100);
+        assert_eq!(
+            sorted.keys().collect::<Vec<_>>(),
+            in_memory.keys().collect::<Vec<_>>()
+        );
+        for (tile_id, data) in &in_memory {
+            assert!(&sorted[tile_id] == data, "tile {tile_id} is different");
+        }
+
+        // Progress counts tiles with features, not records
+        let reports = reports.lock().unwrap();
+        let (tiles_done, tiles_total) = *reports.last().unwrap();
+        assert_eq!(tiles_done, tiles_total);
+        assert!(tiles_total >= sorted.len() && tiles_total < sorted.len() * 2);
+    }
+}
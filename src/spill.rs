@@ -0,0 +1,244 @@
+use std::borrow::Cow;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use geo::algorithm::coords_iter::CoordsIter;
+use geozero::wkb::Wkb;
+use geozero::{CoordDimensions, ToGeo, ToWkb};
+use rstar::{RTree, RTreeObject, AABB};
+
+use crate::TreeFeature;
+
+/// Features that didn't fit under `Options::max_memory`, kept in a temporary file. Only their
+/// envelopes and where they are in the file stay in memory, and each one is read back whenever a
+/// tile needs it. Each record is the length of the geometry as a little-endian u32, the geometry as
+/// WKB, then the properties as JSON, if there are any.
+pub(crate) struct Spill {
+    tree: RTree<SpilledFeature>,
+    file: Mutex<std::fs::File>,
+}
+
+pub(crate) struct SpillWriter {
+    file: BufWriter<std::fs::File>,
+    length: u64,
+    features: Vec<SpilledFeature>,
+}
+
+struct SpilledFeature {
+    envelope: AABB<[f64; 2]>,
+    offset: u64,
+    length: u32,
+}
+
+impl RTreeObject for SpilledFeature {
+    type Envelope = AABB<[f64; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        self.envelope
+    }
+}
+
+/// A feature going into a tile, borrowed from the index, or owned when it was read back from a
+/// `Spill` or made by `Options::process_tile`
+pub type FeatureRef<'a> = Cow<'a, TreeFeature>;
+
+impl SpillWriter {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(tempfile::tempfile()?),
+            length: 0,
+            features: Vec::new(),
+        })
+    }
+
+    pub fn push(&mut self, feature: TreeFeature) -> Result<()> {
+        let record = encode_feature(&feature)?;
+        let length = record.len() as u32;
+        self.file.write_all(&record)?;
+        self.features.push(SpilledFeature {
+            envelope: feature.envelope(),
+            offset: self.length,
+            length,
+        });
+        self.length += length as u64;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn finish(self) -> Result<Spill> {
+        Ok(Spill {
+            tree: RTree::bulk_load(self.features),
+            file: Mutex::new(self.file.into_inner()?),
+        })
+    }
+}
+
+impl Spill {
+    pub fn locate_in_envelope_intersecting(
+        &self,
+        envelope: &AABB<[f64; 2]>,
+    ) -> Result<Vec<TreeFeature>> {
+        self.tree
+            .locate_in_envelope_intersecting(envelope)
+            .map(|feature| self.read(feature))
+            .collect()
+    }
+
+    pub fn envelopes(&self) -> impl Iterator<Item = AABB<[f64; 2]>> + '_ {
+        self.tree.iter().map(|feature| feature.envelope)
+    }
+
+    /// Reads back every feature, in the order they're stored
+    pub fn for_each(&self, mut f: impl FnMut(&TreeFeature)) -> Result<()> {
+        let mut features: Vec<_> = self.tree.iter().collect();
+        features.sort_by_key(|feature| feature.offset);
+        for feature in features {
+            f(&self.read(feature)?);
+        }
+        Ok(())
+    }
+
+    fn read(&self, feature: &SpilledFeature) -> Result<TreeFeature> {
+        let mut record = vec![0; feature.length as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(feature.offset))?;
+            file.read_exact(&mut record)?;
+        }
+        decode_feature(&record)
+    }
+}
+
+/// A feature as one of the records described on `Spill`, also used by `Options::two_pass`
+pub(crate) fn encode_feature(feature: &TreeFeature) -> Result<Vec<u8>> {
+    let geometry = feature.geometry.to_wkb(CoordDimensions::xy())?;
+    let properties = match feature.properties {
+        Some(ref properties) => serde_json::to_vec(properties)?,
+        None => Vec::new(),
+    };
+    if u32::try_from(4 + geometry.len() + properties.len()).is_err() {
+        anyhow::bail!("A feature is too big to spill to disk");
+    }
+    let mut record = Vec::with_capacity(4 + geometry.len() + properties.len());
+    record.extend_from_slice(&(geometry.len() as u32).to_le_bytes());
+    record.extend_from_slice(&geometry);
+    record.extend_from_slice(&properties);
+    Ok(record)
+}
+
+pub(crate) fn decode_feature(record: &[u8]) -> Result<TreeFeature> {
+    let geometry_length = u32::from_le_bytes(record[..4].try_into().unwrap()) as usize;
+    let (geometry, properties) = record[4..].split_at(geometry_length);
+    let geometry = Wkb(geometry.to_vec())
+        .to_geo()
+        .context("Reading back a spilled feature")?;
+    let properties = if properties.is_empty() {
+        None
+    } else {
+        Some(serde_json::from_slice(properties)?)
+    };
+    Ok(TreeFeature {
+        geometry,
+        properties,
+    })
+}
+
+/// Roughly how much memory a loaded feature takes up, counting its envelope in the R-tree
+pub(crate) fn approx_size(feature: &TreeFeature) -> usize {
+    let properties = match feature.properties {
+        // Maps and boxed values take up a few times as much as the JSON text
+        Some(ref properties) => {
+            3 * properties
+                .iter()
+                .map(|(key, value)| key.len() + value.to_string().len())
+                .sum::<usize>()
+        }
+        None => 0,
+    };
+    128 + 16 * feature.geometry.coords_count() + properties
+}
+
+#[cfg(test)]
+mod tests {
+    use geo_types::{LineString, MultiLineString, Point};
+    use serde_json::json;
+
+    use super::*;
+    use crate::tests::{all_tiles, test_lines};
+    use crate::{load_inputs, LayerOptions, Options};
+
+    #[test]
+    fn test_encode_decode() {
+        let geometries: [geo_types::Geometry<f64>; 3] = [
+            Point::new(1.5, -2.5).into(),
+            LineString::from(vec![(0.0, 0.0), (1.0, 2.0), (-3.0, 4.0)]).into(),
+            MultiLineString::new(vec![
+                LineString::from(vec![(0.0, 0.0), (1.0, 1.0)]),
+                LineString::from(vec![(5.0, 5.0), (6.0, 7.0)]),
+            ])
+            .into(),
+        ];
+        for geometry in geometries {
+            for properties in [
+                None,
+                json!({ "name": "A road", "count": 3, "oneway": true })
+                    .as_object()
+                    .cloned(),
+            ] {
+                let feature = TreeFeature {
+                    geometry: geometry.clone(),
+                    properties,
+                };
+                let decoded = decode_feature(&encode_feature(&feature).unwrap()).unwrap();
+                assert_eq!(decoded.geometry, feature.geometry);
+                assert_eq!(decoded.properties, feature.properties);
+            }
+        }
+    }
+
+    #[test]
+    fn test_spilled_tiles_match() {
+        let tiles = |max_memory| {
+            let options = Options {
+                zoom_levels: (8..=12).collect(),
+                // Spilled features are found after the ones in memory, so ties could come out in a
+                // different order
+                sort_by_key: Some("id".to_string()),
+                max_memory,
+                ..Default::default()
+            };
+            let inputs = vec![(
+                LayerOptions::new("lines"),
+                test_lines(200).into_iter().map(Ok),
+            )];
+            let loaded = load_inputs(inputs, &options).unwrap();
+            let layer = &loaded[0].1;
+            let spilled = layer.spill.as_ref().map_or(0, |spill| spill.tree.size());
+            if max_memory.is_some() {
+                assert!(layer.tree.size() > 0);
+                assert!(spilled > 0);
+            } else {
+                assert_eq!(spilled, 0);
+            }
+            let mut archive =
+                crate::loaded_layers_to_pmtiles(vec![(LayerOptions::new("lines"), layer)], options)
+                    .unwrap();
+            all_tiles(&mut archive)
+        };
+
+        let spilled = tiles(Some(10 * 1024));
+        let in_memory = tiles(None);
+        assert!(in_memory.len() > 100);
+        assert_eq!(
+            spilled.keys().collect::<Vec<_>>(),
+            in_memory.keys().collect::<Vec<_>>()
+        );
+        for (tile_id, data) in &in_memory {
+            assert!(&spilled[tile_id] == data, "tile {tile_id} is different");
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// What happened during a conversion, from `layers_to_pmtiles_with_stats` and
+/// `layers_to_sink_with_stats`
+#[derive(Clone, Debug, Default)]
+pub struct ConversionStats {
+    /// Every feature from the inputs, including ones left out
+    pub features_read: usize,
+    /// Invalid features left out by `Options::skip_invalid`
+    pub features_skipped: usize,
+    /// Features left out by `Options::filter` or `Options::sample_rate`, or for being outside
+    /// `Options::mask`
+    pub features_dropped: usize,
+    /// Each zoom with at least one non-empty tile, in order. Tiles already done in a checkpoint
+    /// aren't counted.
+    pub zooms: Vec<ZoomStats>,
+    /// Reading the features and working out the zooms and metadata
+    pub loading: Duration,
+    /// Making, compressing, and sending out every tile
+    pub tiling: Duration,
+    /// Writing the directories and metadata once the tiles are done
+    pub writing: Duration,
+}
+
+/// The non-empty tiles made at one zoom. Sizes are compressed, like they're stored.
+#[derive(Clone, Debug)]
+pub struct ZoomStats {
+    pub zoom: u32,
+    pub num_tiles: usize,
+    pub min_bytes: usize,
+    pub median_bytes: usize,
+    pub max_bytes: usize,
+    pub bytes: u64,
+}
+
+impl ConversionStats {
+    pub fn num_tiles(&self) -> usize {
+        self.zooms.iter().map(|zoom| zoom.num_tiles).sum()
+    }
+
+    /// The total size of the tiles, leaving out the directories and metadata
+    pub fn bytes(&self) -> u64 {
+        self.zooms.iter().map(|zoom| zoom.bytes).sum()
+    }
+}
+
+/// Tile sizes and time spent tiling, collected from every thread
+#[derive(Default)]
+pub(crate) struct TileRecorder {
+    sizes: Mutex<BTreeMap<u32, Vec<usize>>>,
+    tiling: Mutex<Duration>,
+}
+
+impl TileRecorder {
+    pub fn add_tile(&self, zoom: u32, bytes: usize) {
+        self.sizes
+            .lock()
+            .unwrap()
+            .entry(zoom)
+            .or_default()
+            .push(bytes);
+    }
+
+    pub fn add_tiling(&self, duration: Duration) {
+        *self.tiling.lock().unwrap() += duration;
+    }
+
+    /// Fills in `stats.zooms` and `stats.tiling`
+    pub fn finish(&self, stats: &mut ConversionStats) {
+        stats.tiling = *self.tiling.lock().unwrap();
+        stats.zooms = self
+            .sizes
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .map(|(zoom, sizes)| {
+                sizes.sort_unstable();
+                ZoomStats {
+                    zoom: *zoom,
+                    num_tiles: sizes.len(),
+                    min_bytes: sizes[0],
+                    median_bytes: sizes[sizes.len() / 2],
+                    max_bytes: sizes[sizes.len() - 1],
+                    bytes: sizes.iter().map(|bytes| *bytes as u64).sum(),
+                }
+            })
+            .collect();
+    }
+}
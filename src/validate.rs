@@ -0,0 +1,156 @@
+//! Checks encoded vector tiles against the MVT 2.1 spec, so broken tiles are caught before they
+//! reach a frontend's parser
+
+use std::collections::HashSet;
+
+use anyhow::{bail, Context, Result};
+
+use crate::input::Message;
+
+/// Fails on the first thing in `bytes` (an uncompressed tile) that breaks a rule of the spec
+pub fn validate_tile(bytes: &[u8]) -> Result<()> {
+    let mut names = HashSet::new();
+    for field in Message::new(bytes) {
+        if let (3, layer) = field? {
+            let name = validate_layer(layer.as_bytes()?)?;
+            if !names.insert(name.clone()) {
+                bail!("Two layers are named {name}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_layer(bytes: &[u8]) -> Result<String> {
+    let mut version = None;
+    let mut name = None;
+    let mut features = Vec::new();
+    let mut keys = HashSet::new();
+    let mut num_values = 0;
+    for field in Message::new(bytes) {
+        match field? {
+            (15, value) => version = Some(value.as_u64()?),
+            (1, value) => name = Some(value.as_str()?.to_string()),
+            (2, value) => features.push(value.as_bytes()?),
+            (3, value) => {
+                let key = value.as_str()?;
+                if !keys.insert(key) {
+                    bail!("Layer key {key} is repeated");
+                }
+            }
+            (4, value) => {
+                validate_value(value.as_bytes()?)?;
+                num_values += 1;
+            }
+            (5, value) if value.as_u64()? == 0 => bail!("Layer extent is 0"),
+            _ => {}
+        }
+    }
+    let Some(name) = name else {
+        bail!("Layer has no name");
+    };
+    if version != Some(2) {
+        bail!("Layer {name} is version {version:?}, not 2");
+    }
+    for (idx, bytes) in features.into_iter().enumerate() {
+        validate_feature(bytes, keys.len(), num_values)
+            .with_context(|| format!("Feature {idx} of layer {name}"))?;
+    }
+    Ok(name)
+}
+
+/// Each value has exactly one field, with the wire type matching the field
+fn validate_value(bytes: &[u8]) -> Result<()> {
+    let mut num_fields = 0;
+    for field in Message::new(bytes) {
+        match field? {
+            (1, x) => {
+                x.as_str()?;
+            }
+            (2, x) | (3, x) => {
+                x.as_f64()?;
+            }
+            (4..=7, x) => {
+                x.as_u64()?;
+            }
+            (x, _) => bail!("Layer value has unknown field {x}"),
+        }
+        num_fields += 1;
+    }
+    if num_fields != 1 {
+        bail!("Layer value has {num_fields} fields, not 1");
+    }
+    Ok(())
+}
+
+fn validate_feature(bytes: &[u8], num_keys: usize, num_values: usize) -> Result<()> {
+    let mut tags = Vec::new();
+    let mut geometry_type = 0;
+    let mut commands = Vec::new();
+    for field in Message::new(bytes) {
+        match field? {
+            (2, value) => value.read_packed_varints(&mut tags)?,
+            (3, value) => geometry_type = value.as_u64()?,
+            (4, value) => value.read_packed_varints(&mut commands)?,
+            _ => {}
+        }
+    }
+
+    if tags.len() % 2 != 0 {
+        bail!("Feature has an odd number of tags");
+    }
+    for pair in tags.chunks(2) {
+        if pair[0] as usize >= num_keys || pair[1] as usize >= num_values {
+            bail!("Feature has a tag index out of range");
+        }
+    }
+
+    // Pairs of (command ID, count), checking each command has all of its parameters
+    let mut sequence = Vec::new();
+    let mut i = 0;
+    while i < commands.len() {
+        let id = commands[i] & 0x7;
+        let count = commands[i] >> 3;
+        i += 1;
+        let params = match id {
+            1 | 2 => 2 * count as usize,
+            7 => 0,
+            _ => bail!("Unknown geometry command {id}"),
+        };
+        if commands.len() - i < params {
+            bail!("Geometry is truncated");
+        }
+        i += params;
+        sequence.push((id, count));
+    }
+    if sequence.is_empty() {
+        bail!("Geometry is empty");
+    }
+
+    match geometry_type {
+        // One MoveTo of all the points
+        1 => {
+            if !matches!(sequence[..], [(1, count)] if count > 0) {
+                bail!("Point geometry should be one MoveTo, not {sequence:?}");
+            }
+        }
+        // A MoveTo of one point, then LineTo of at least one, for each line
+        2 => {
+            for part in sequence.chunks(2) {
+                if !matches!(part, [(1, 1), (2, count)] if *count > 0) {
+                    bail!("LineString geometry has a bad line {part:?}");
+                }
+            }
+        }
+        // A MoveTo of one point, LineTo of at least two, then a ClosePath for each ring
+        3 => {
+            for part in sequence.chunks(3) {
+                if !matches!(part, [(1, 1), (2, count), (7, 1)] if *count > 1) {
+                    bail!("Polygon geometry has a bad ring {part:?}");
+                }
+            }
+        }
+        x => bail!("Feature has geometry type {x}, not a point, linestring, or polygon"),
+    }
+    Ok(())
+}
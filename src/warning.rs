@@ -0,0 +1,63 @@
+use std::fmt;
+
+use crate::SkippedFeature;
+
+/// Something that didn't stop the run but may not be what was wanted, sent to
+/// `Options::on_warning` as it happens, from any thread
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Warning {
+    /// `Options::skip_invalid` left out a feature
+    Skipped(SkippedFeature),
+    /// A feature's geometry can't go in vector tiles, so it's loaded but never drawn. Only Points,
+    /// LineStrings, and MultiLineStrings can.
+    UnsupportedGeometry {
+        layer: String,
+        /// Counting from 0, in the order the layer's features were read
+        index: usize,
+        geometry_type: &'static str,
+    },
+    /// Part of a feature is past the edge of web mercator, further north or south than about 85.05°
+    /// or past 180° east or west, so it was moved onto the edge
+    ClampedCoordinates {
+        layer: String,
+        /// Counting from 0, in the order the layer's features were read
+        index: usize,
+    },
+    /// Features `Options::filter`, `Options::sample_rate`, `Options::mask`, or
+    /// `Options::transform` left out of a layer, once it's loaded
+    DroppedFeatures { layer: String, count: usize },
+    /// None of the features in a layer have the property it's sorted by
+    MissingSortKey { layer: String, key: String },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::Skipped(skipped) => write!(
+                f,
+                "Skipped feature {} in layer {}: {}",
+                skipped.index, skipped.layer, skipped.reason
+            ),
+            Warning::UnsupportedGeometry {
+                layer,
+                index,
+                geometry_type,
+            } => write!(
+                f,
+                "Feature {index} in layer {layer} is a {geometry_type}, which won't be drawn"
+            ),
+            Warning::ClampedCoordinates { layer, index } => write!(
+                f,
+                "Feature {index} in layer {layer} goes past the edge of web mercator"
+            ),
+            Warning::DroppedFeatures { layer, count } => {
+                write!(f, "Left out {count} features from layer {layer}")
+            }
+            Warning::MissingSortKey { layer, key } => write!(
+                f,
+                "None of the features in layer {layer} have a {key} property to sort by"
+            ),
+        }
+    }
+}